@@ -0,0 +1,249 @@
+//! Agent mode: offers MCP tools to the model, runs the tool calls it makes and feeds
+//! the results back until the model answers without calling a tool.
+
+use rig::completion::{Message, ToolDefinition};
+use rig::OneOrMany;
+use rmcp::model::CallToolResult;
+use serde_json::{json, Map, Value};
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use super::content::{assistant_tool_message, tool_result};
+use super::helpers::{completion_metadata, finish_reason};
+use super::{ChatRequest, ChatService, ChatStreamEvent, ChatUsage, ToolCallInfo};
+use crate::core::mcp::helpers::call_server_tool;
+use crate::core::mcp::models::ToolWithServer;
+use crate::core::state::SharedMcpServers;
+
+/// Converts MCP tools into the definitions offered to the model. Names are not
+/// namespaced, so of several tools with the same name only the first is offered.
+pub fn tool_definitions(tools: &[ToolWithServer]) -> Vec<ToolDefinition> {
+    tools
+        .iter()
+        .enumerate()
+        .filter(|(index, tool)| tools[..*index].iter().all(|t| t.name != tool.name))
+        .map(|(_, tool)| tool)
+        .map(|tool| ToolDefinition {
+            name: tool.name.clone(),
+            description: tool.description.clone().unwrap_or_default(),
+            parameters: tool.input_schema.clone(),
+        })
+        .collect()
+}
+
+/// Normalizes tool call arguments into the object MCP expects.
+/// Some providers send the arguments as a JSON-encoded string.
+pub fn parse_tool_arguments(arguments: &Value) -> Result<Option<Map<String, Value>>, String> {
+    match arguments {
+        Value::Null => Ok(None),
+        Value::Object(map) => Ok(Some(map.clone())),
+        Value::String(text) if text.trim().is_empty() => Ok(None),
+        Value::String(text) => match serde_json::from_str::<Value>(text) {
+            Ok(Value::Object(map)) => Ok(Some(map)),
+            _ => Err(format!(
+                "Tool arguments must be a JSON object, got: {}",
+                text
+            )),
+        },
+        other => Err(format!(
+            "Tool arguments must be a JSON object, got: {}",
+            other
+        )),
+    }
+}
+
+/// Flattens the text content of a tool result, falling back to its JSON form
+pub fn tool_result_text(result: &CallToolResult) -> String {
+    let value = serde_json::to_value(result).unwrap_or_default();
+    let texts: Vec<&str> = value
+        .get("content")
+        .and_then(|c| c.as_array())
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| item.get("text").and_then(|t| t.as_str()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if texts.is_empty() {
+        value.to_string()
+    } else {
+        texts.join("\n")
+    }
+}
+
+/// Runs `tool` on the MCP server `server`, returning the text handed back to the model
+/// and whether the call failed. The local server's tool loop runs its calls through
+/// this too.
+pub(crate) async fn run_server_tool(
+    servers: &SharedMcpServers,
+    server: &str,
+    tool: &str,
+    arguments: &Value,
+) -> (String, bool) {
+    let arguments = match parse_tool_arguments(arguments) {
+        Ok(arguments) => arguments,
+        Err(e) => return (e, true),
+    };
+
+    match call_server_tool(servers, server, tool, arguments).await {
+        Ok(result) => (tool_result_text(&result), result.is_error.unwrap_or(false)),
+        Err(e) => (e, true),
+    }
+}
+
+/// Runs one tool call, returning the text handed back to the model and whether it failed
+async fn execute_tool_call(
+    servers: &SharedMcpServers,
+    tools: &[ToolWithServer],
+    call: &ToolCallInfo,
+) -> (String, bool) {
+    // The first tool of that name is the one `tool_definitions` offered
+    let Some(tool) = tools.iter().find(|tool| tool.name == call.name) else {
+        return (format!("Tool {} is not available", call.name), true);
+    };
+    run_server_tool(servers, &tool.server, &call.name, &call.arguments).await
+}
+
+impl ChatService {
+    /// Runs the agent loop, streaming every model turn through `emit_callback`.
+    ///
+    /// Each turn offers `tools` to the model. Tool calls are dispatched to the MCP servers
+    /// (bounded by `MCP_TOOL_CALL_TIMEOUT`), reported with `tool_result` events and sent
+    /// back to the model. Ends with `complete` once the model answers without tool calls,
+    /// with `cancelled` when `cancel_token` fires, and with `error` after `max_steps` turns.
+    /// The metadata of the `complete` event sums the token usage of all turns.
+    pub async fn stream_agent(
+        &self,
+        mut request: ChatRequest,
+        tools: Vec<ToolWithServer>,
+        servers: SharedMcpServers,
+        max_steps: usize,
+        cancel_token: CancellationToken,
+        emit_callback: impl Fn(ChatStreamEvent) + Send + Sync + 'static,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let stream_id = request
+            .stream_id
+            .clone()
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let callback = Box::new(emit_callback);
+        let Some(_permit) = self.acquire_slot(&cancel_token).await else {
+            callback(ChatStreamEvent::new(
+                &stream_id,
+                "cancelled",
+                String::new(),
+                true,
+            ));
+            return Ok(stream_id);
+        };
+        let cancelled = |stream_id: &str| {
+            log::info!("Agent stream {} cancelled", stream_id);
+            ChatStreamEvent::new(stream_id, "cancelled", String::new(), true)
+        };
+
+        let definitions = tool_definitions(&tools);
+        let started = Instant::now();
+        let context = self.fit_context(&mut request, &cancel_token).await;
+        let (mut prompt, mut history) = self.convert_request_messages(&request)?;
+        let mut first_token_at: Option<Instant> = None;
+        let mut total_usage: Option<ChatUsage> = None;
+
+        callback(
+            ChatStreamEvent::new(&stream_id, "start", String::new(), false).with_context(context),
+        );
+
+        for step_index in 0..max_steps {
+            log::debug!("Agent stream {} step {}", stream_id, step_index + 1);
+
+            let step = self
+                .run_turn(
+                    &mut request,
+                    prompt.clone(),
+                    history.clone(),
+                    definitions.clone(),
+                    &stream_id,
+                    &cancel_token,
+                    &*callback,
+                )
+                .await;
+            let step = match step {
+                Ok(step) => step,
+                Err(e) => {
+                    callback(ChatStreamEvent::new(&stream_id, "error", e.clone(), true));
+                    return Err(e.into());
+                }
+            };
+
+            if step.cancelled {
+                callback(cancelled(&stream_id));
+                return Ok(stream_id);
+            }
+            first_token_at = first_token_at.or(step.first_token_at);
+            if let Some(usage) = step.usage {
+                *total_usage.get_or_insert_with(ChatUsage::default) += usage;
+            }
+            if step.tool_calls.is_empty() {
+                let metadata = completion_metadata(
+                    total_usage,
+                    finish_reason(&step.tool_calls, step.usage, request.params.max_tokens),
+                    started,
+                    first_token_at,
+                    Instant::now(),
+                );
+                callback(
+                    ChatStreamEvent::new(&stream_id, "complete", String::new(), true)
+                        .with_metadata(metadata.answered_by(&request)),
+                );
+                return Ok(stream_id);
+            }
+
+            history.push(prompt);
+            history.push(assistant_tool_message(&step.content, &step.tool_calls));
+
+            let mut results = Vec::new();
+            for call in &step.tool_calls {
+                let (text, is_error) = tokio::select! {
+                    _ = cancel_token.cancelled() => {
+                        callback(cancelled(&stream_id));
+                        return Ok(stream_id);
+                    }
+                    outcome = execute_tool_call(&servers, &tools, call) => outcome,
+                };
+                if is_error {
+                    log::warn!("Tool {} failed: {}", call.name, text);
+                }
+
+                callback(ChatStreamEvent::new(
+                    &stream_id,
+                    "tool_result",
+                    json!({
+                        "id": call.id,
+                        "name": call.name,
+                        "content": text,
+                        "is_error": is_error,
+                    })
+                    .to_string(),
+                    false,
+                ));
+                results.push(tool_result(&call.id, call.call_id.as_deref(), text));
+            }
+
+            prompt = Message::User {
+                content: OneOrMany::many(results).expect("tool calls are never empty"),
+            };
+        }
+
+        let message = format!(
+            "Agent stopped after {} steps without a final answer",
+            max_steps
+        );
+        callback(ChatStreamEvent::new(
+            &stream_id,
+            "error",
+            message.clone(),
+            true,
+        ));
+        Err(message.into())
+    }
+}
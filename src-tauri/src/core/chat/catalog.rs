@@ -0,0 +1,285 @@
+//! Models offered by each provider, read from its models endpoint and cached in the
+//! data folder so the list is still available offline after the first fetch.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use super::constants::{
+    ANTHROPIC_API_VERSION, MOCK_ECHO_MODEL, MOCK_PROVIDER, MODEL_LIST_CACHE_DIR,
+    MODEL_LIST_CACHE_TTL_SECS, MODEL_LIST_ENDPOINTS, OPENAI_COMPATIBLE_PROVIDER,
+};
+use super::providers::{build_http_client, ProviderConfig};
+
+/// A model as listed by its provider
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProviderModel {
+    pub id: String,
+    /// Display name, when the provider has one besides the id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    /// What the provider reports the model supports: `chat`, `vision`, `tools`,
+    /// `reasoning` or `embedding`
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+/// What `list_provider_models` returns, also the format of the cache file
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProviderModelList {
+    pub provider: String,
+    pub models: Vec<ProviderModel>,
+    /// When the list was fetched, in milliseconds since the epoch
+    pub fetched_at: u64,
+    /// Set when the provider could not be reached and an expired cache was returned
+    #[serde(default)]
+    pub stale: bool,
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// The models endpoint for a provider kind, `<base_url>/models` when a base URL is set
+pub fn models_url(kind: &str, base_url: Option<&str>) -> Result<String, String> {
+    if let Some(base_url) = base_url {
+        return Ok(format!("{}/models", base_url.trim_end_matches('/')));
+    }
+    if kind == OPENAI_COMPATIBLE_PROVIDER {
+        return Err(format!("Provider kind '{}' requires a base_url", kind));
+    }
+    MODEL_LIST_ENDPOINTS
+        .iter()
+        .find(|(provider, _)| *provider == kind)
+        .map(|(_, url)| url.to_string())
+        .ok_or_else(|| format!("Provider '{}' does not list its models", kind))
+}
+
+/// The API key from the config, or from `<KIND>_API_KEY` like the chat clients
+fn api_key(kind: &str, config: Option<&ProviderConfig>) -> Option<String> {
+    config
+        .and_then(|config| config.api_key.clone())
+        .filter(|key| !key.is_empty())
+        .or_else(|| {
+            let var = format!("{}_API_KEY", kind.to_ascii_uppercase().replace('-', "_"));
+            std::env::var(var).ok()
+        })
+}
+
+fn has_item(value: &Value, pointer: &str, item: &str) -> bool {
+    value
+        .pointer(pointer)
+        .and_then(|v| v.as_array())
+        .is_some_and(|items| items.iter().any(|v| v.as_str() == Some(item)))
+}
+
+fn is_true(value: &Value, pointer: &str) -> bool {
+    value.pointer(pointer).and_then(|v| v.as_bool()) == Some(true)
+}
+
+/// Reads one model entry, whatever the provider's field names
+fn normalize_model(item: &Value) -> Option<ProviderModel> {
+    let id = item
+        .get("id")
+        .or_else(|| item.get("name"))
+        .and_then(|v| v.as_str())?;
+    // Gemini names its models `models/<id>`
+    let id = id.strip_prefix("models/").unwrap_or(id).to_string();
+    let name = ["display_name", "displayName", "name"]
+        .iter()
+        .filter_map(|key| item.get(*key).and_then(|v| v.as_str()))
+        .map(|name| name.strip_prefix("models/").unwrap_or(name))
+        .find(|name| *name != id)
+        .map(String::from);
+    let context_length = [
+        "/context_length",
+        "/context_window",
+        "/max_context_length",
+        "/inputTokenLimit",
+        "/top_provider/context_length",
+    ]
+    .iter()
+    .find_map(|pointer| item.pointer(pointer).and_then(|v| v.as_u64()));
+
+    let checks = [
+        (
+            "chat",
+            is_true(item, "/capabilities/completion_chat")
+                || has_item(item, "/supportedGenerationMethods", "generateContent")
+                || has_item(item, "/endpoints", "chat")
+                || item.get("type").and_then(|v| v.as_str()) == Some("chat"),
+        ),
+        (
+            "vision",
+            is_true(item, "/capabilities/vision")
+                || has_item(item, "/architecture/input_modalities", "image"),
+        ),
+        (
+            "tools",
+            is_true(item, "/capabilities/function_calling")
+                || has_item(item, "/supported_parameters", "tools"),
+        ),
+        (
+            "reasoning",
+            has_item(item, "/supported_parameters", "reasoning"),
+        ),
+        (
+            "embedding",
+            has_item(item, "/supportedGenerationMethods", "embedContent")
+                || has_item(item, "/endpoints", "embed")
+                || item.get("type").and_then(|v| v.as_str()) == Some("embedding"),
+        ),
+    ];
+    let capabilities = checks
+        .iter()
+        .filter(|(_, supported)| *supported)
+        .map(|(capability, _)| capability.to_string())
+        .collect();
+
+    Some(ProviderModel {
+        id,
+        name,
+        context_length,
+        capabilities,
+    })
+}
+
+/// Reads the models from a models endpoint response: a `data` or `models` array, or a
+/// bare array. Sorted by id.
+pub fn normalize_models(body: &Value) -> Vec<ProviderModel> {
+    let items = body
+        .get("data")
+        .or_else(|| body.get("models"))
+        .unwrap_or(body)
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let mut models: Vec<ProviderModel> = items.iter().filter_map(normalize_model).collect();
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+    models.dedup_by(|a, b| a.id == b.id);
+    models
+}
+
+/// Queries the models endpoint of the provider registered or known as `name`
+pub async fn fetch_provider_models(
+    name: &str,
+    config: Option<&ProviderConfig>,
+) -> Result<Vec<ProviderModel>, String> {
+    if name == MOCK_PROVIDER {
+        return Ok(vec![ProviderModel {
+            id: MOCK_ECHO_MODEL.to_string(),
+            name: None,
+            context_length: None,
+            capabilities: vec!["chat".to_string()],
+        }]);
+    }
+    let kind = config.map_or(name, |config| config.kind(name));
+    let url = models_url(kind, config.and_then(|config| config.base_url.as_deref()))?;
+    let client = build_http_client(config.unwrap_or(&ProviderConfig::default()))?;
+
+    let mut request = client.get(&url);
+    if let Some(key) = api_key(kind, config) {
+        request = match kind {
+            "anthropic" => request
+                .header("x-api-key", key)
+                .header("anthropic-version", ANTHROPIC_API_VERSION),
+            "gemini" => request.query(&[("key", key)]),
+            _ => request.bearer_auth(key),
+        };
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Failed to list models of {}: {}", name, e))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!(
+            "Failed to list models of {}: {} {}",
+            name, status, body
+        ));
+    }
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("Failed to list models of {}: {}", name, e))?;
+    let body: Value = serde_json::from_str(&body)
+        .map_err(|e| format!("Invalid models response from {}: {}", name, e))?;
+    Ok(normalize_models(&body))
+}
+
+/// Cache file of a provider's model list
+pub fn model_cache_path(data_dir: &Path, provider: &str) -> Result<PathBuf, String> {
+    if provider.is_empty() || provider.contains(['/', '\\']) || provider.starts_with('.') {
+        return Err(format!("Invalid provider name '{}'", provider));
+    }
+    Ok(data_dir
+        .join(MODEL_LIST_CACHE_DIR)
+        .join(format!("{}.json", provider)))
+}
+
+pub fn read_model_cache(path: &Path) -> Option<ProviderModelList> {
+    let text = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+pub fn write_model_cache(path: &Path, list: &ProviderModelList) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let text = serde_json::to_string_pretty(list).map_err(|e| e.to_string())?;
+    std::fs::write(path, text).map_err(|e| e.to_string())
+}
+
+/// Whether a cached list is younger than `MODEL_LIST_CACHE_TTL_SECS`
+pub fn is_cache_fresh(list: &ProviderModelList, now: u64) -> bool {
+    now.saturating_sub(list.fetched_at) < MODEL_LIST_CACHE_TTL_SECS * 1000
+}
+
+/// Returns the cached list while it is fresh, otherwise fetches and caches a new one.
+/// When the provider cannot be reached, an expired cache is returned marked `stale`.
+pub async fn list_models(
+    data_dir: &Path,
+    provider: &str,
+    config: Option<&ProviderConfig>,
+    refresh: bool,
+) -> Result<ProviderModelList, String> {
+    let path = model_cache_path(data_dir, provider)?;
+    let cached = read_model_cache(&path);
+    if let Some(list) = &cached {
+        if !refresh && is_cache_fresh(list, now_ms()) {
+            return Ok(list.clone());
+        }
+    }
+
+    match fetch_provider_models(provider, config).await {
+        Ok(models) => {
+            let list = ProviderModelList {
+                provider: provider.to_string(),
+                models,
+                fetched_at: now_ms(),
+                stale: false,
+            };
+            if let Err(e) = write_model_cache(&path, &list) {
+                log::warn!("Failed to cache the models of {}: {}", provider, e);
+            }
+            Ok(list)
+        }
+        Err(e) => match cached {
+            Some(list) => {
+                log::warn!("{}, using the cached list", e);
+                Ok(ProviderModelList {
+                    stale: true,
+                    ..list
+                })
+            }
+            None => Err(e),
+        },
+    }
+}
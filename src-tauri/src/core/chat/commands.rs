@@ -1,118 +1,796 @@
-use super::{ChatMessage, ChatRequest, ChatService};
-use std::sync::Once;
-use tauri::{Emitter, Runtime};
-
-static CHAT_SERVICE_INIT: Once = Once::new();
-static mut CHAT_SERVICE: Option<ChatService> = None;
-
-fn get_chat_service() -> &'static ChatService {
-    unsafe {
-        CHAT_SERVICE_INIT.call_once(|| {
-            CHAT_SERVICE = Some(ChatService::new());
-        });
-        CHAT_SERVICE.as_ref().unwrap()
-    }
-}
+use super::catalog::{list_models, ProviderModelList};
+use super::compare::{CompareBranch, CompareChatResponse, CompareResult};
+use super::constants::{
+    AGENT_MAX_STEPS, CHAT_STREAM_BUFFER_SIZE, CHAT_STREAM_EVENT, CHAT_STREAM_RETENTION_SECS,
+};
+use super::content::{load_request_media, ContentPart};
+use super::context::ContextOptions;
+use super::embeddings::EmbeddingResponse;
+use super::helpers::{
+    params_from_model_settings, run_agent, run_chat, spawn_agent_stream, spawn_chat_stream,
+};
+use super::providers::{validate_provider_config, ProviderConfig, ProviderConfigSummary};
+use super::recorder::{load_thread_history, ThreadRecorder};
+use super::retry::{ChatTarget, RetryPolicy};
+use super::streams::{ActiveChatStream, ActiveStreamInfo, ChatStreamBuffer, SharedChatStreams};
+use super::structured::ResponseFormat;
+use super::titles::spawn_title_job;
+use super::{
+    AgentOptions, ChatMessage, ChatRequest, ChatResponse, ChatStreamEvent, CompletionMetadata,
+    GenerationParams,
+};
+use crate::core::app::commands::get_jan_data_folder_path;
+use crate::core::mcp::helpers::list_tools_with_server;
+use crate::core::mcp::models::ToolWithServer;
+use crate::core::prompts::helpers::render_template_by_id;
+use crate::core::state::AppState;
+use crate::core::threads::models::ThreadAssistantInfo;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{Emitter, Runtime, State};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 #[derive(serde::Deserialize)]
 pub struct StreamChatRequest {
+    /// Prompt text, replaced by the rendered template when `template_id` is set
+    #[serde(default)]
     pub prompt: String,
     pub provider: String,
     pub model: String,
     pub stream_id: Option<String>,
     pub chat_history: Option<Vec<ChatMessage>>,
+    /// Images and documents for the prompt. Local `path` sources are read from the data
+    /// folder here, so the frontend only sends the path.
+    #[serde(default)]
+    pub attachments: Vec<ContentPart>,
+    pub supports_vision: Option<bool>,
+    /// Runs the request through the MCP tool-calling agent loop when set
+    pub agent: Option<AgentOptions>,
+    pub instructions: Option<String>,
+    /// Thread assistant whose instructions and model settings apply to this request.
+    /// Explicit `instructions` and generation parameters take precedence.
+    pub assistant: Option<ThreadAssistantInfo>,
+    #[serde(flatten)]
+    pub params: GenerationParams,
+    /// Overrides the provider config registered with `set_provider_config`
+    pub provider_config: Option<ProviderConfig>,
+    /// How the history is fitted into the model's context window
+    pub context: Option<ContextOptions>,
+    /// Retries of transient provider errors such as 429 and 5xx responses
+    pub retry: Option<RetryPolicy>,
+    /// Provider/model pairs tried in order when the request fails before any content
+    /// was streamed. Their configs come from `set_provider_config`.
+    #[serde(default)]
+    pub fallbacks: Vec<ChatTarget>,
+    /// Requires a JSON reply, optionally matching a JSON Schema. Invalid replies are
+    /// sent back with the validation errors up to `validation_retries` times.
+    pub response_format: Option<ResponseFormat>,
+    pub validation_retries: Option<u32>,
+    /// Thread the turn is stored in. The history is read from the thread when
+    /// `chat_history` is unset, and the prompt and streamed reply are written to it.
+    pub thread_id: Option<String>,
+    /// Id of the assistant message created in the thread, a new id when unset
+    pub message_id: Option<String>,
+    /// Prompt template rendered with `variables` to make the prompt
+    pub template_id: Option<String>,
+    pub variables: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// One prompt sent to several provider/model pairs, see `compare_chat`
+#[derive(serde::Deserialize)]
+pub struct CompareChatRequest {
+    /// Id of the comparison, used with `cancel_chat_stream` and `attach_chat_stream`
+    pub compare_id: Option<String>,
+    /// Provider/model pairs that answer the prompt. Their configs come from
+    /// `set_provider_config`.
+    pub targets: Vec<ChatTarget>,
+    pub prompt: String,
+    pub chat_history: Option<Vec<ChatMessage>>,
+    #[serde(default)]
+    pub attachments: Vec<ContentPart>,
+    pub supports_vision: Option<bool>,
+    pub instructions: Option<String>,
+    pub assistant: Option<ThreadAssistantInfo>,
+    #[serde(flatten)]
+    pub params: GenerationParams,
+    pub context: Option<ContextOptions>,
+    pub retry: Option<RetryPolicy>,
+    pub response_format: Option<ResponseFormat>,
+    pub validation_retries: Option<u32>,
+}
+
+/// Applies the assistant's instructions and model settings where the request sets none
+fn apply_assistant(
+    instructions: Option<String>,
+    params: GenerationParams,
+    assistant: Option<ThreadAssistantInfo>,
+) -> (Option<String>, GenerationParams) {
+    match assistant {
+        Some(assistant) => (
+            instructions.or(assistant.instructions),
+            params.or(params_from_model_settings(&assistant.model.settings)),
+        ),
+        None => (instructions, params),
+    }
+}
+
+impl StreamChatRequest {
+    /// Renders the prompt from `template_id`, when set
+    fn render_template<R: Runtime>(
+        &mut self,
+        app_handle: &tauri::AppHandle<R>,
+    ) -> Result<(), String> {
+        if let Some(template_id) = self.template_id.take() {
+            let variables = self.variables.take().unwrap_or_default();
+            self.prompt = render_template_by_id(app_handle.clone(), &template_id, &variables)?;
+        }
+        Ok(())
+    }
+
+    /// The thread, assistant message id and assistant id when the turn is stored in a thread
+    fn thread_turn(&self) -> Option<(String, Option<String>, Option<String>)> {
+        let thread_id = self.thread_id.clone()?;
+        let assistant_id = self
+            .assistant
+            .as_ref()
+            .map(|assistant| assistant.id.clone());
+        Some((thread_id, self.message_id.clone(), assistant_id))
+    }
+
+    /// Splits the request into the `ChatRequest` and the agent options
+    fn into_chat_request(self, stream_id: Option<String>) -> (ChatRequest, Option<AgentOptions>) {
+        let (instructions, params) =
+            apply_assistant(self.instructions, self.params, self.assistant);
+
+        let chat_request = ChatRequest {
+            prompt: self.prompt,
+            provider: self.provider,
+            model: self.model,
+            stream_id,
+            chat_history: self.chat_history,
+            attachments: self.attachments,
+            supports_vision: self.supports_vision,
+            instructions,
+            params,
+            provider_config: self.provider_config,
+            context: self.context,
+            retry: self.retry,
+            fallbacks: self.fallbacks,
+            response_format: self.response_format,
+            validation_retries: self.validation_retries,
+            tools: Vec::new(),
+            tool_choice: None,
+        };
+        (chat_request, self.agent)
+    }
+}
+
+impl CompareChatRequest {
+    /// Splits the request into the pairs to ask and the request they share
+    fn into_chat_request(self) -> (Vec<ChatTarget>, ChatRequest) {
+        let (instructions, params) =
+            apply_assistant(self.instructions, self.params, self.assistant);
+        let chat_request = ChatRequest {
+            prompt: self.prompt,
+            provider: String::new(),
+            model: String::new(),
+            stream_id: None,
+            chat_history: self.chat_history,
+            attachments: self.attachments,
+            supports_vision: self.supports_vision,
+            instructions,
+            params,
+            provider_config: None,
+            context: self.context,
+            retry: self.retry,
+            fallbacks: Vec::new(),
+            response_format: self.response_format,
+            validation_retries: self.validation_retries,
+            tools: Vec::new(),
+            tool_choice: None,
+        };
+        (self.targets, chat_request)
+    }
+}
+
+/// Falls back to the registered provider configs when the request or its fallbacks
+/// carry none
+async fn resolve_provider_config(state: &AppState, request: &mut ChatRequest) {
+    let provider_configs = state.provider_configs.lock().await;
+    if request.provider_config.is_none() {
+        request.provider_config = provider_configs.get(&request.provider).cloned();
+    }
+    for target in &mut request.fallbacks {
+        if target.provider_config.is_none() {
+            target.provider_config = provider_configs.get(&target.provider).cloned();
+        }
+    }
+}
+
+/// Numbers `event` in the stream's replay buffer and emits it to the frontend.
+/// Returns the numbered event.
+fn emit_stream_event<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    buffer: &std::sync::Mutex<ChatStreamBuffer>,
+    event: ChatStreamEvent,
+) -> ChatStreamEvent {
+    let event = match buffer.lock() {
+        Ok(mut buffer) => buffer.push(event),
+        Err(_) => event,
+    };
+    if let Err(e) = app_handle.emit(CHAT_STREAM_EVENT, &event) {
+        log::warn!("Failed to emit chat stream event: {}", e);
+    }
+    event
+}
+
+/// Marks a stream finished and keeps it attachable for a while, then drops it unless the
+/// id was reused
+fn finish_stream(
+    chat_streams: &SharedChatStreams,
+    stream_id: &str,
+    buffer: Arc<std::sync::Mutex<ChatStreamBuffer>>,
+) {
+    if let Ok(mut buffer) = buffer.lock() {
+        buffer.finish();
+    }
+    let chat_streams = chat_streams.clone();
+    let stream_id = stream_id.to_string();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(CHAT_STREAM_RETENTION_SECS)).await;
+        let mut chat_streams = chat_streams.lock().await;
+        if chat_streams
+            .get(&stream_id)
+            .is_some_and(|stream| Arc::ptr_eq(&stream.buffer, &buffer))
+        {
+            chat_streams.remove(&stream_id);
+        }
+    });
+}
+
+/// Generates the thread's title and summary in the background once its first reply is
+/// stored, with the title model or else the model that wrote the reply
+async fn start_title_job<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    state: &AppState,
+    thread_id: String,
+    provider: String,
+    model: String,
+) {
+    let mut target = state
+        .title_model
+        .lock()
+        .await
+        .clone()
+        .unwrap_or(ChatTarget {
+            provider,
+            model,
+            provider_config: None,
+        });
+    if target.provider_config.is_none() {
+        target.provider_config = state
+            .provider_configs
+            .lock()
+            .await
+            .get(&target.provider)
+            .cloned();
+    }
+    spawn_title_job(
+        app_handle.clone(),
+        state.chat_service.clone(),
+        thread_id,
+        target,
+    );
+}
+
+/// Loads the local attachments of a request and, with a `thread_id`, the thread history.
+/// Then writes the prompt and an `in_progress` reply to the thread.
+async fn prepare_thread_turn<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    thread: Option<(String, Option<String>, Option<String>)>,
+    request: &mut ChatRequest,
+) -> Result<Option<ThreadRecorder<R>>, String> {
+    let data_dir = get_jan_data_folder_path(app_handle.clone());
+    let Some((thread_id, message_id, assistant_id)) = thread else {
+        load_request_media(request, &data_dir)?;
+        return Ok(None);
+    };
+
+    if request.chat_history.is_none() {
+        request.chat_history = Some(load_thread_history(app_handle.clone(), &thread_id).await?);
+    }
+    // The thread keeps the attachment paths, not the encoded files
+    let attachments = request.attachments.clone();
+    load_request_media(request, &data_dir)?;
+
+    let recorder = ThreadRecorder::start(
+        app_handle.clone(),
+        &thread_id,
+        message_id,
+        assistant_id,
+        &request.prompt,
+        &attachments,
+    )
+    .await?;
+    Ok(Some(recorder))
+}
+
+/// The MCP tools offered to an agent run, with its step limit; `None` without `agent`
+async fn agent_tools(
+    state: &AppState,
+    agent: Option<&AgentOptions>,
+) -> Result<Option<(Vec<ToolWithServer>, usize)>, String> {
+    let Some(options) = agent else {
+        return Ok(None);
+    };
+    let mut tools = list_tools_with_server(&state.mcp_servers, None).await?;
+    if let Some(servers) = &options.servers {
+        tools.retain(|tool| servers.contains(&tool.server));
+    }
+    Ok(Some((tools, options.max_steps.unwrap_or(AGENT_MAX_STEPS))))
+}
+
+/// The events a non-streaming reply is recorded with: its text and completion
+fn reply_events(response: &ChatResponse) -> Vec<ChatStreamEvent> {
+    let text = ChatStreamEvent::new(&response.stream_id, "text", response.content.clone(), false);
+    let mut complete = ChatStreamEvent::new(&response.stream_id, "complete", String::new(), true);
+    complete.metadata = response
+        .metadata
+        .clone()
+        .map(|metadata| CompletionMetadata {
+            structured: response.structured.clone(),
+            ..metadata
+        });
+    vec![text, complete]
 }
 
 /// Starts a streaming chat session using the Rig framework.
 /// Emits events to the frontend via Tauri events.
+/// The stream is registered in `AppState` so it can be stopped with `cancel_chat_stream`.
+/// With `agent` set, tools from the connected MCP servers are offered to the model and
+/// executed, emitting `tool_call` and `tool_result` events for each step.
+/// With `thread_id` set, the prompt and the reply are stored in the thread as the stream
+/// progresses, so the reply survives a reload of the window.
 #[tauri::command]
 pub async fn stream_chat<R: Runtime>(
     app_handle: tauri::AppHandle<R>,
-    request: StreamChatRequest,
+    state: State<'_, AppState>,
+    mut request: StreamChatRequest,
 ) -> Result<String, String> {
-    let chat_request = ChatRequest {
-        prompt: request.prompt,
-        provider: request.provider,
-        model: request.model,
-        stream_id: request.stream_id,
-        chat_history: request.chat_history,
-    };
+    request.render_template(&app_handle)?;
+    let stream_id = request
+        .stream_id
+        .clone()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let thread = request.thread_turn();
+    let (mut chat_request, agent) = request.into_chat_request(Some(stream_id.clone()));
+    resolve_provider_config(&state, &mut chat_request).await;
 
-    let event_name = "chat-stream";
-    let app_handle_clone = app_handle.clone();
+    let agent_tools = agent_tools(&state, agent.as_ref()).await?;
 
-    let stream_id = tokio::task::spawn_blocking(move || {
-        let chat_service = get_chat_service();
+    let cancel_token = CancellationToken::new();
+    let buffer = {
+        let mut chat_streams = state.chat_streams.lock().await;
+        if chat_streams
+            .get(&stream_id)
+            .is_some_and(|stream| !stream.is_finished())
+        {
+            return Err(format!("stream_id {} exists", stream_id));
+        }
+        let stream = ActiveChatStream::new(
+            cancel_token.clone(),
+            CHAT_STREAM_BUFFER_SIZE,
+            &chat_request.provider,
+            &chat_request.model,
+            thread.as_ref().map(|(thread_id, _, _)| thread_id.clone()),
+        );
+        let buffer = stream.buffer.clone();
+        chat_streams.insert(stream_id.clone(), stream);
+        buffer
+    };
 
-        // Use a blocking runtime for the non-Send future
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| format!("Failed to create runtime: {}", e))?;
+    let title_job = thread.as_ref().map(|(thread_id, _, _)| {
+        (
+            thread_id.clone(),
+            chat_request.provider.clone(),
+            chat_request.model.clone(),
+        )
+    });
+    let recorder = match prepare_thread_turn(&app_handle, thread, &mut chat_request).await {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            state.chat_streams.lock().await.remove(&stream_id);
+            return Err(e);
+        }
+    };
+    // Events are written to the thread by a separate task, so file writes never hold up
+    // the stream
+    let (record_tx, mut record_rx) = mpsc::unbounded_channel::<ChatStreamEvent>();
+    let recording = recorder.map(|mut recorder| {
+        tokio::spawn(async move {
+            while let Some(event) = record_rx.recv().await {
+                if let Err(e) = recorder.record(&event).await {
+                    log::warn!(
+                        "Failed to write message {} to thread: {}",
+                        recorder.message_id(),
+                        e
+                    );
+                }
+            }
+            recorder
+        })
+    });
 
-        rt.block_on(async {
-            chat_service
-                .stream_chat(chat_request, move |event| {
-                    let app_handle = app_handle_clone.clone();
+    let emit_handle = app_handle.clone();
+    let emit_buffer = buffer.clone();
+    let emit = move |event: ChatStreamEvent| {
+        let event = emit_stream_event(&emit_handle, &emit_buffer, event);
+        let _ = record_tx.send(event);
+    };
+    let task = match agent_tools {
+        Some((tools, max_steps)) => spawn_agent_stream(
+            state.chat_service.clone(),
+            chat_request,
+            tools,
+            state.mcp_servers.clone(),
+            max_steps,
+            cancel_token,
+            emit,
+        ),
+        None => spawn_chat_stream(state.chat_service.clone(), chat_request, cancel_token, emit),
+    };
+    let result = task.await.map_err(|e| format!("Task failed: {}", e));
 
-                    // Emit the streaming event to the frontend
-                    if let Err(e) = app_handle.emit(event_name, event) {
-                        eprintln!("Failed to emit chat stream event: {}", e);
-                    }
-                })
-                .await
-        })
-    })
-    .await
-    .map_err(|e| format!("Task failed: {}", e))?
-    .map_err(|e| format!("Failed to stream chat: {}", e))?;
+    finish_stream(&state.chat_streams, &stream_id, buffer);
+
+    let result = result.and_then(|result| result);
+    if let Some(recording) = recording {
+        // The channel closes once the chat task has dropped its callback
+        if let Ok(mut recorder) = recording.await {
+            if let (false, Err(e)) = (recorder.is_finished(), &result) {
+                if let Err(write_error) = recorder.fail(e).await {
+                    log::warn!("Failed to write message error to thread: {}", write_error);
+                }
+            }
+        }
+    }
+    if let (Ok(_), Some((thread_id, provider, model))) = (&result, title_job) {
+        start_title_job(&app_handle, &state, thread_id, provider, model).await;
+    }
 
-    Ok(stream_id)
+    result
 }
 
 /// Performs a non-streaming chat request using the Rig framework.
 /// Returns the complete response once it's ready.
+/// With `agent` set, the agent loop runs its tool calls to the end first; the response
+/// holds the text of every turn.
 #[tauri::command]
 pub async fn chat<R: Runtime>(
-    _app_handle: tauri::AppHandle<R>,
-    request: StreamChatRequest,
+    app_handle: tauri::AppHandle<R>,
+    state: State<'_, AppState>,
+    mut request: StreamChatRequest,
 ) -> Result<serde_json::Value, String> {
-    let chat_request = ChatRequest {
-        prompt: request.prompt,
-        provider: request.provider,
-        model: request.model,
-        stream_id: request.stream_id,
-        chat_history: request.chat_history,
+    request.render_template(&app_handle)?;
+    let stream_id = request.stream_id.clone();
+    let thread = request.thread_turn();
+    let (mut chat_request, agent) = request.into_chat_request(stream_id);
+    resolve_provider_config(&state, &mut chat_request).await;
+    let agent_tools = agent_tools(&state, agent.as_ref()).await?;
+    let title_job = thread.as_ref().map(|(thread_id, _, _)| {
+        (
+            thread_id.clone(),
+            chat_request.provider.clone(),
+            chat_request.model.clone(),
+        )
+    });
+    let mut recorder = prepare_thread_turn(&app_handle, thread, &mut chat_request).await?;
+
+    let result = match agent_tools {
+        Some((tools, max_steps)) => {
+            run_agent(
+                &state.chat_service,
+                chat_request,
+                tools,
+                state.mcp_servers.clone(),
+                max_steps,
+            )
+            .await
+        }
+        None => run_chat(&state.chat_service, chat_request)
+            .await
+            .map(|response| {
+                let events = reply_events(&response);
+                (response, events)
+            }),
     };
+    if let Some(recorder) = recorder.as_mut() {
+        let written = match &result {
+            Ok((_, events)) => {
+                let mut written = Ok(());
+                for event in events {
+                    written = recorder.record(event).await;
+                    if written.is_err() {
+                        break;
+                    }
+                }
+                written
+            }
+            Err(e) => recorder.fail(e).await,
+        };
+        if let Err(e) = written {
+            log::warn!("Failed to write message to thread: {}", e);
+        }
+    }
+    let (response, _) = result?;
+    if let Some((thread_id, provider, model)) = title_job {
+        start_title_job(&app_handle, &state, thread_id, provider, model).await;
+    }
 
-    let response = tokio::task::spawn_blocking(move || {
-        let chat_service = get_chat_service();
+    Ok(serde_json::to_value(response).map_err(|e| e.to_string())?)
+}
 
-        // Use a blocking runtime for the non-Send future
-        let rt = tokio::runtime::Runtime::new()
-            .map_err(|e| format!("Failed to create runtime: {}", e))?;
+/// Sends one prompt to several provider/model pairs at once and waits for all replies.
+/// Every branch streams `chat-stream` events tagged with `compare`; the comparison is
+/// registered under `compare_id`, so one `cancel_chat_stream` stops all branches and
+/// `attach_chat_stream` replays the events of all of them.
+#[tauri::command]
+pub async fn compare_chat<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    state: State<'_, AppState>,
+    request: CompareChatRequest,
+) -> Result<CompareChatResponse, String> {
+    if request.targets.is_empty() {
+        return Err("compare_chat needs at least one target".to_string());
+    }
+    let compare_id = request
+        .compare_id
+        .clone()
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let (targets, mut base_request) = request.into_chat_request();
+    load_request_media(
+        &mut base_request,
+        &get_jan_data_folder_path(app_handle.clone()),
+    )?;
 
-        rt.block_on(async { chat_service.chat_non_streaming(chat_request).await })
-    })
-    .await
-    .map_err(|e| format!("Task failed: {}", e))?
-    .map_err(|e| format!("Failed to chat: {}", e))?;
+    let cancel_token = CancellationToken::new();
+    let buffer = {
+        let mut chat_streams = state.chat_streams.lock().await;
+        if chat_streams
+            .get(&compare_id)
+            .is_some_and(|stream| !stream.is_finished())
+        {
+            return Err(format!("stream_id {} exists", compare_id));
+        }
+        let models: Vec<String> = targets
+            .iter()
+            .map(|target| format!("{}/{}", target.provider, target.model))
+            .collect();
+        let stream = ActiveChatStream::new(
+            cancel_token.clone(),
+            CHAT_STREAM_BUFFER_SIZE,
+            "compare",
+            &models.join(", "),
+            None,
+        );
+        if let Ok(mut buffer) = stream.buffer.lock() {
+            buffer.set_branches(targets.len());
+        }
+        let buffer = stream.buffer.clone();
+        chat_streams.insert(compare_id.clone(), stream);
+        buffer
+    };
 
-    Ok(serde_json::to_value(response).map_err(|e| e.to_string())?)
+    let mut branches = Vec::new();
+    for (index, target) in targets.into_iter().enumerate() {
+        let stream_id = format!("{}:{}", compare_id, index);
+        let branch = CompareBranch {
+            compare_id: compare_id.clone(),
+            index,
+            provider: target.provider.clone(),
+            model: target.model.clone(),
+        };
+        let mut chat_request = ChatRequest {
+            provider: target.provider,
+            model: target.model,
+            provider_config: target.provider_config,
+            stream_id: Some(stream_id.clone()),
+            ..base_request.clone()
+        };
+        resolve_provider_config(&state, &mut chat_request).await;
+
+        let result = Arc::new(std::sync::Mutex::new(CompareResult::new(
+            &branch, &stream_id,
+        )));
+        let emit_result = result.clone();
+        let emit_handle = app_handle.clone();
+        let emit_buffer = buffer.clone();
+        let emit = move |event: ChatStreamEvent| {
+            if let Ok(mut result) = emit_result.lock() {
+                result.record(&event);
+            }
+            emit_stream_event(
+                &emit_handle,
+                &emit_buffer,
+                event.with_compare(branch.clone()),
+            );
+        };
+        let task = spawn_chat_stream(
+            state.chat_service.clone(),
+            chat_request,
+            cancel_token.child_token(),
+            emit,
+        );
+        branches.push((task, result));
+    }
+
+    let mut results = Vec::new();
+    for (task, result) in branches {
+        let outcome = task.await.map_err(|e| format!("Task failed: {}", e));
+        let mut result = result
+            .lock()
+            .map(|result| result.clone())
+            .map_err(|e| e.to_string())?;
+        if let Err(e) = outcome.and_then(|outcome| outcome) {
+            result.fail(e);
+        }
+        results.push(result);
+    }
+
+    finish_stream(&state.chat_streams, &compare_id, buffer);
+
+    Ok(CompareChatResponse {
+        compare_id,
+        results,
+    })
 }
 
 /// Cancels an ongoing chat stream by stream ID.
-/// Note: This is a placeholder implementation since the rig library doesn't provide direct cancellation.
+/// The stream emits a final `cancelled` event once the provider connection is dropped.
 #[tauri::command]
-pub async fn cancel_chat_stream<R: Runtime>(
-    _app_handle: tauri::AppHandle<R>,
+pub async fn cancel_chat_stream(
+    state: State<'_, AppState>,
     stream_id: String,
 ) -> Result<(), String> {
-    // This is a placeholder implementation
-    // The rig library doesn't provide direct cancellation capabilities in this version
-    // In a real implementation, you would need to track active streams and cancel them
+    let chat_streams = state.chat_streams.lock().await;
+    match chat_streams.get(&stream_id) {
+        Some(stream) if !stream.is_finished() => {
+            stream.cancel_token.cancel();
+            log::info!("Cancelled chat stream: {}", stream_id);
+            Ok(())
+        }
+        _ => Err(format!("No chat stream: {}", stream_id)),
+    }
+}
 
-    println!(
-        "Cancel chat stream request received for stream_id: {}",
-        stream_id
-    );
+/// Returns the buffered events of a stream numbered `from_seq` or later, so a reloaded
+/// window can catch up. Later events keep arriving on the `chat-stream` event; their
+/// `seq` lets the caller drop the ones it already replayed.
+/// Finished streams stay attachable for `CHAT_STREAM_RETENTION_SECS`.
+#[tauri::command]
+pub async fn attach_chat_stream(
+    state: State<'_, AppState>,
+    stream_id: String,
+    from_seq: Option<u64>,
+) -> Result<Vec<ChatStreamEvent>, String> {
+    let chat_streams = state.chat_streams.lock().await;
+    let stream = chat_streams
+        .get(&stream_id)
+        .ok_or_else(|| format!("No chat stream: {}", stream_id))?;
+    let buffer = stream.buffer.lock().map_err(|e| e.to_string())?;
+    Ok(buffer.events_from(from_seq.unwrap_or(0)))
+}
+
+/// Lists the running streams and the recently finished ones, oldest first
+#[tauri::command]
+pub async fn list_active_streams(
+    state: State<'_, AppState>,
+) -> Result<Vec<ActiveStreamInfo>, String> {
+    let chat_streams = state.chat_streams.lock().await;
+    let mut streams: Vec<ActiveStreamInfo> = chat_streams
+        .iter()
+        .map(|(stream_id, stream)| stream.info(stream_id))
+        .collect();
+    streams.sort_by_key(|info| info.started_at);
+    Ok(streams)
+}
+
+/// Registers the credentials and endpoint used for `name` in chat requests.
+/// `name` is the provider part of model ids, so a config named `lan` serves `lan/<model>`.
+/// Configs are kept in memory only, so API keys never reach the disk from here: the
+/// frontend, which stores them, registers them again on every app start before routing
+/// chats or local API requests to the provider.
+#[tauri::command]
+pub async fn set_provider_config(
+    state: State<'_, AppState>,
+    name: String,
+    config: ProviderConfig,
+) -> Result<(), String> {
+    validate_provider_config(&name, &config)?;
+    let mut provider_configs = state.provider_configs.lock().await;
+    provider_configs.insert(name.clone(), config);
+    log::info!("Provider config {} registered", name);
+    Ok(())
+}
+
+/// Removes a provider config; the provider falls back to environment variables.
+#[tauri::command]
+pub async fn remove_provider_config(
+    state: State<'_, AppState>,
+    name: String,
+) -> Result<(), String> {
+    let mut provider_configs = state.provider_configs.lock().await;
+    provider_configs
+        .remove(&name)
+        .map(|_| ())
+        .ok_or_else(|| format!("No provider config: {}", name))
+}
+
+/// Lists the registered provider configs without their secrets.
+#[tauri::command]
+pub async fn list_provider_configs(
+    state: State<'_, AppState>,
+) -> Result<Vec<ProviderConfigSummary>, String> {
+    let provider_configs = state.provider_configs.lock().await;
+    let mut summaries: Vec<ProviderConfigSummary> = provider_configs
+        .iter()
+        .map(|(name, config)| config.summary(name))
+        .collect();
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(summaries)
+}
+
+/// Embeds `inputs` with a remote provider's embedding model, one vector per input.
+/// Inputs are sent in batches; see `embeddings::validate_inputs` for the size limits.
+#[tauri::command]
+pub async fn embed(
+    state: State<'_, AppState>,
+    provider: String,
+    model: String,
+    inputs: Vec<String>,
+) -> Result<EmbeddingResponse, String> {
+    let config = state.provider_configs.lock().await.get(&provider).cloned();
+    state
+        .chat_service
+        .embed(&provider, &model, inputs, config.as_ref())
+        .await
+}
+
+/// Sets the model that writes thread titles and summaries after the first reply of a
+/// thread. A small local model is a good fit; `None` uses the thread's chat model.
+#[tauri::command]
+pub async fn set_title_model(
+    state: State<'_, AppState>,
+    target: Option<ChatTarget>,
+) -> Result<(), String> {
+    *state.title_model.lock().await = target;
     Ok(())
 }
+
+/// The model set with `set_title_model`, `None` when titles use the thread's chat model
+#[tauri::command]
+pub async fn get_title_model(state: State<'_, AppState>) -> Result<Option<ChatTarget>, String> {
+    Ok(state.title_model.lock().await.clone())
+}
+
+/// Lists the models a provider offers, from its models endpoint or `<base_url>/models`.
+/// The list is cached in the data folder for `MODEL_LIST_CACHE_TTL_SECS`; `refresh`
+/// fetches it again. Without network an expired cache is returned marked `stale`.
+#[tauri::command]
+pub async fn list_provider_models<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    state: State<'_, AppState>,
+    provider: String,
+    refresh: Option<bool>,
+) -> Result<ProviderModelList, String> {
+    let config = state.provider_configs.lock().await.get(&provider).cloned();
+    let data_dir = get_jan_data_folder_path(app_handle);
+    list_models(
+        &data_dir,
+        &provider,
+        config.as_ref(),
+        refresh.unwrap_or(false),
+    )
+    .await
+}
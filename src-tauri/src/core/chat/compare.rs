@@ -0,0 +1,81 @@
+//! Side-by-side comparison: one prompt answered by several provider/model pairs at once.
+//! Each pair runs as its own chat stream; the events of all of them go out on the
+//! `chat-stream` event tagged with the branch they belong to.
+
+use serde::{Deserialize, Serialize};
+
+use super::{ChatStreamEvent, CompletionMetadata};
+
+/// Identifies the branch of a comparison an event belongs to
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CompareBranch {
+    pub compare_id: String,
+    /// Position of the pair in the request's `targets`
+    pub index: usize,
+    pub provider: String,
+    pub model: String,
+}
+
+/// The reply of one provider/model pair
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CompareResult {
+    pub provider: String,
+    pub model: String,
+    pub stream_id: String,
+    pub content: String,
+    /// `completed`, `cancelled` or `error`
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Usage, time to first token and duration of the reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<CompletionMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CompareChatResponse {
+    pub compare_id: String,
+    /// One result per target, in the order of the request
+    pub results: Vec<CompareResult>,
+}
+
+impl CompareResult {
+    pub fn new(branch: &CompareBranch, stream_id: &str) -> Self {
+        Self {
+            provider: branch.provider.clone(),
+            model: branch.model.clone(),
+            stream_id: stream_id.to_string(),
+            content: String::new(),
+            status: "in_progress".to_string(),
+            error: None,
+            metadata: None,
+        }
+    }
+
+    /// Collects the reply from the branch's stream events
+    pub fn record(&mut self, event: &ChatStreamEvent) {
+        match event.event_type.as_str() {
+            "text" => self.content.push_str(&event.content),
+            // The reply failed the response format and is requested again
+            "validation" => self.content.clear(),
+            "complete" => {
+                self.status = "completed".to_string();
+                self.metadata = event.metadata.clone();
+            }
+            "cancelled" => self.status = "cancelled".to_string(),
+            "error" => {
+                self.status = "error".to_string();
+                self.error = Some(event.content.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// Marks a branch that ended without a final event as failed
+    pub fn fail(&mut self, error: String) {
+        if !matches!(self.status.as_str(), "completed" | "cancelled" | "error") {
+            self.status = "error".to_string();
+            self.error = Some(error);
+        }
+    }
+}
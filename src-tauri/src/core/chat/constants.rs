@@ -0,0 +1,149 @@
+// Chat Constants
+pub const CHAT_STREAM_EVENT: &str = "chat-stream";
+/// Number of chats `ChatService` runs at once; further requests wait for a free slot
+pub const MAX_CONCURRENT_CHATS: usize = 8;
+/// Largest local file attached to a chat message, read and base64-encoded before sending
+pub const MAX_ATTACHMENT_BYTES: u64 = 20 * 1024 * 1024;
+/// Events kept per stream for `attach_chat_stream` to replay
+pub const CHAT_STREAM_BUFFER_SIZE: usize = 2048;
+/// How long a finished stream stays attachable, so a reload right at the end still
+/// receives the final event
+pub const CHAT_STREAM_RETENTION_SECS: u64 = 60;
+/// How often a reply streamed into a thread is written to its messages.jsonl
+pub const THREAD_FLUSH_INTERVAL_MS: u64 = 500;
+/// Retries of a transient provider error before falling back or failing
+pub const CHAT_MAX_RETRIES: u32 = 2;
+/// Longest single wait between retries, also when `Retry-After` asks for more
+pub const CHAT_MAX_RETRY_DELAY_MS: u64 = 60_000;
+/// Times a reply that does not match the requested JSON format is sent back for correction
+pub const STRUCTURED_OUTPUT_MAX_RETRIES: u32 = 2;
+/// Providers accepting OpenAI's `response_format` with a JSON Schema
+pub const JSON_SCHEMA_PROVIDERS: &[&str] = &[
+    "openai",
+    "azure",
+    "openrouter",
+    "mistral",
+    "xai",
+    OPENAI_COMPATIBLE_PROVIDER,
+];
+/// Providers accepting `response_format` with `json_object` only
+pub const JSON_MODE_PROVIDERS: &[&str] = &["deepseek", "groq", "moonshot", "together"];
+/// Default number of model turns the agent loop may take
+pub const AGENT_MAX_STEPS: usize = 10;
+
+/// Built-in offline provider replaying scripted responses, see `chat::mock`
+pub const MOCK_PROVIDER: &str = "mock";
+/// Model of the mock provider that streams the prompt back
+pub const MOCK_ECHO_MODEL: &str = "echo";
+/// Length of the vectors the mock provider returns from `embed`
+pub const MOCK_EMBEDDING_DIMENSIONS: usize = 16;
+
+/// Inputs sent to a provider in one embeddings request
+pub const EMBEDDING_BATCH_SIZE: usize = 96;
+/// Most inputs one `embed` call accepts
+pub const EMBEDDING_MAX_INPUTS: usize = 2048;
+/// Longest input, in estimated tokens; most embedding models stop at 8k
+pub const EMBEDDING_MAX_INPUT_TOKENS: u64 = 8192;
+
+/// Provider kind for servers exposing the OpenAI API at a custom base URL
+pub const OPENAI_COMPATIBLE_PROVIDER: &str = "openai-compatible";
+
+/// Providers reachable through rig's `DynClientBuilder`
+pub const REMOTE_PROVIDERS: &[&str] = &[
+    "anthropic",
+    "azure",
+    "cohere",
+    "deepseek",
+    "galadriel",
+    "gemini",
+    "groq",
+    "huggingface",
+    "hyperbolic",
+    "mira",
+    "mistral",
+    "moonshot",
+    "openai",
+    "openrouter",
+    "perplexity",
+    "together",
+    "xai",
+];
+
+/// Models endpoint of each provider; configs with a `base_url` use `<base_url>/models`
+pub const MODEL_LIST_ENDPOINTS: &[(&str, &str)] = &[
+    ("openai", "https://api.openai.com/v1/models"),
+    ("anthropic", "https://api.anthropic.com/v1/models"),
+    ("cohere", "https://api.cohere.com/v1/models"),
+    ("deepseek", "https://api.deepseek.com/models"),
+    (
+        "gemini",
+        "https://generativelanguage.googleapis.com/v1beta/models",
+    ),
+    ("groq", "https://api.groq.com/openai/v1/models"),
+    ("mistral", "https://api.mistral.ai/v1/models"),
+    ("moonshot", "https://api.moonshot.ai/v1/models"),
+    ("openrouter", "https://openrouter.ai/api/v1/models"),
+    ("together", "https://api.together.xyz/v1/models"),
+    ("xai", "https://api.x.ai/v1/models"),
+];
+/// Version header required by Anthropic's API
+pub const ANTHROPIC_API_VERSION: &str = "2023-06-01";
+/// How long a provider's cached model list is used before it is fetched again
+pub const MODEL_LIST_CACHE_TTL_SECS: u64 = 24 * 60 * 60;
+/// Folder under the data folder holding the cached model lists
+pub const MODEL_LIST_CACHE_DIR: &str = "providers/models";
+
+/// Context window assumed for models missing from `MODEL_CONTEXT_WINDOWS`
+pub const DEFAULT_CONTEXT_WINDOW: u64 = 32_768;
+/// Tokens kept free for the reply when the request sets no `max_tokens`
+pub const DEFAULT_RESERVED_OUTPUT_TOKENS: u64 = 4096;
+/// Estimated tokens for the role and separators of each message
+pub const MESSAGE_OVERHEAD_TOKENS: u64 = 4;
+/// Estimated tokens of an image; providers charge between a few hundred and ~1500
+pub const IMAGE_TOKEN_ESTIMATE: u64 = 1024;
+/// Length limit of the summary that replaces older messages
+pub const SUMMARY_MAX_TOKENS: u64 = 512;
+pub const SUMMARY_INSTRUCTIONS: &str =
+    "Summarize the conversation below in a few short paragraphs. \
+Keep names, numbers, decisions and open questions; leave out greetings and small talk. \
+Reply with the summary only.";
+
+/// Event emitted when a generated title and summary were written to a thread
+pub const THREAD_UPDATED_EVENT: &str = "thread-updated";
+/// Length limit of the reply carrying a generated thread title and summary
+pub const TITLE_MAX_TOKENS: u64 = 200;
+/// Generated titles are cut to this many characters
+pub const TITLE_MAX_CHARS: usize = 60;
+pub const TITLE_INSTRUCTIONS: &str = "Write a title and a summary for the conversation below. \
+The title names the topic in at most six words, without quotes or a trailing period. \
+The summary is one short paragraph. Use the language of the conversation.";
+
+/// Context windows by model family, matched as a prefix of the model name (see
+/// `context::context_window`). Listed most specific first within each family.
+pub const MODEL_CONTEXT_WINDOWS: &[(&str, u64)] = &[
+    ("gpt-5", 400_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4.5", 128_000),
+    ("chatgpt-4o", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude", 200_000),
+    ("gemini-1.5-pro", 2_097_152),
+    ("gemini", 1_048_576),
+    ("command-r", 128_000),
+    ("mistral-large", 128_000),
+    ("mistral", 32_768),
+    ("deepseek", 128_000),
+    ("llama-3.1", 128_000),
+    ("llama-3.3", 128_000),
+    ("llama3.1", 128_000),
+    ("llama3.3", 128_000),
+    ("qwen", 32_768),
+    ("grok", 131_072),
+];
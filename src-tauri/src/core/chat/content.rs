@@ -0,0 +1,318 @@
+//! Chat message content: plain text, or a list of text, image and document parts.
+//! Local files are read and base64-encoded on the Rust side before a request is sent.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rig::completion::Message;
+use rig::message::{
+    AssistantContent, DocumentMediaType, ImageMediaType, MimeType, ToolResultContent, UserContent,
+};
+use rig::OneOrMany;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::constants::MAX_ATTACHMENT_BYTES;
+use super::{ChatRequest, ToolCallInfo};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ChatContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    Image(MediaSource),
+    Document(MediaSource),
+}
+
+/// Where an image or document comes from. Exactly one of `url`, `data` or `path` is set.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct MediaSource {
+    /// Remote URL or `data:` URI
+    pub url: Option<String>,
+    /// Base64-encoded file content
+    pub data: Option<String>,
+    /// File path, relative to the data folder or absolute inside it
+    pub path: Option<String>,
+    pub media_type: Option<String>,
+    pub name: Option<String>,
+}
+
+impl ChatContent {
+    /// The text parts joined by newlines
+    pub fn text(&self) -> String {
+        match self {
+            ChatContent::Text(text) => text.clone(),
+            ChatContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    pub fn parts(&self) -> Vec<ContentPart> {
+        match self {
+            ChatContent::Text(text) => vec![ContentPart::Text { text: text.clone() }],
+            ChatContent::Parts(parts) => parts.clone(),
+        }
+    }
+
+    pub fn parts_mut(&mut self) -> &mut [ContentPart] {
+        match self {
+            ChatContent::Text(_) => &mut [],
+            ChatContent::Parts(parts) => parts,
+        }
+    }
+}
+
+impl Default for ChatContent {
+    fn default() -> Self {
+        ChatContent::Text(String::new())
+    }
+}
+
+impl From<String> for ChatContent {
+    fn from(text: String) -> Self {
+        ChatContent::Text(text)
+    }
+}
+
+impl From<&str> for ChatContent {
+    fn from(text: &str) -> Self {
+        ChatContent::Text(text.to_string())
+    }
+}
+
+/// Guesses a media type from a file extension
+pub fn media_type_from_path(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let media_type = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "pdf" => "application/pdf",
+        "txt" | "log" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        _ => return None,
+    };
+    Some(media_type)
+}
+
+/// Resolves `path` against the data folder, rejecting anything outside it
+pub fn resolve_data_path(data_dir: &Path, path: &str) -> Result<PathBuf, String> {
+    let candidate = Path::new(path);
+    let full_path = if candidate.is_absolute() {
+        candidate.to_path_buf()
+    } else {
+        data_dir.join(candidate)
+    };
+
+    let canonical = full_path
+        .canonicalize()
+        .map_err(|e| format!("Cannot read attachment {}: {}", path, e))?;
+    let data_dir = data_dir.canonicalize().map_err(|e| e.to_string())?;
+    if !canonical.starts_with(&data_dir) {
+        return Err(format!("Attachment {} is outside the data folder", path));
+    }
+    Ok(canonical)
+}
+
+/// Replaces a local `path` source with its base64 data, filling in the media type and name
+pub fn load_local_media(source: &mut MediaSource, data_dir: &Path) -> Result<(), String> {
+    let Some(path) = source.path.take() else {
+        return Ok(());
+    };
+    let file_path = resolve_data_path(data_dir, &path)?;
+
+    let size = std::fs::metadata(&file_path)
+        .map_err(|e| format!("Cannot read attachment {}: {}", path, e))?
+        .len();
+    if size > MAX_ATTACHMENT_BYTES {
+        return Err(format!(
+            "Attachment {} is {} bytes, the limit is {} bytes",
+            path, size, MAX_ATTACHMENT_BYTES
+        ));
+    }
+    let bytes =
+        std::fs::read(&file_path).map_err(|e| format!("Cannot read attachment {}: {}", path, e))?;
+
+    source.data = Some(BASE64.encode(bytes));
+    if source.media_type.is_none() {
+        source.media_type = media_type_from_path(&file_path).map(String::from);
+    }
+    if source.name.is_none() {
+        source.name = file_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+    }
+    Ok(())
+}
+
+/// Loads every local file referenced by `parts`
+pub fn load_local_parts(parts: &mut [ContentPart], data_dir: &Path) -> Result<(), String> {
+    for part in parts {
+        match part {
+            ContentPart::Image(source) | ContentPart::Document(source) => {
+                load_local_media(source, data_dir)?
+            }
+            ContentPart::Text { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+/// Loads the local files of the prompt attachments and the chat history
+pub fn load_request_media(request: &mut ChatRequest, data_dir: &Path) -> Result<(), String> {
+    load_local_parts(&mut request.attachments, data_dir)?;
+    for message in request.chat_history.iter_mut().flatten() {
+        load_local_parts(message.content.parts_mut(), data_dir)?;
+    }
+    Ok(())
+}
+
+/// Splits a `data:<media type>;base64,<data>` URI
+pub fn parse_data_uri(uri: &str) -> Option<(String, String)> {
+    let rest = uri.strip_prefix("data:")?;
+    let (header, data) = rest.split_once(',')?;
+    let media_type = header.strip_suffix(";base64")?;
+    Some((media_type.to_string(), data.to_string()))
+}
+
+fn is_text_media_type(media_type: &str) -> bool {
+    media_type.starts_with("text/") || media_type == "application/json"
+}
+
+/// Converts a content part into rig content.
+/// Without `vision`, images are replaced by a short note so text-only models still answer.
+pub fn to_user_content(part: &ContentPart, vision: bool) -> Result<UserContent, String> {
+    match part {
+        ContentPart::Text { text } => Ok(UserContent::text(text.clone())),
+        ContentPart::Image(source) => {
+            if !vision {
+                let name = source.name.as_deref().unwrap_or("image");
+                return Ok(UserContent::text(format!(
+                    "[Image {} omitted: the model does not support images]",
+                    name
+                )));
+            }
+            let (data, media_type) = match (&source.data, &source.url) {
+                (Some(data), _) => (data.clone(), source.media_type.clone()),
+                (None, Some(url)) => match parse_data_uri(url) {
+                    Some((media_type, data)) => (data, Some(media_type)),
+                    None => {
+                        let media_type = source
+                            .media_type
+                            .as_deref()
+                            .and_then(ImageMediaType::from_mime_type);
+                        return Ok(UserContent::image_url(url.clone(), media_type, None));
+                    }
+                },
+                (None, None) => return Err(unresolved_source_error(source)),
+            };
+            let media_type = media_type
+                .as_deref()
+                .and_then(ImageMediaType::from_mime_type);
+            Ok(UserContent::image_base64(data, media_type, None))
+        }
+        ContentPart::Document(source) => {
+            let name = source.name.as_deref().unwrap_or("document");
+            let media_type = source.media_type.as_deref().unwrap_or("text/plain");
+            match (&source.data, &source.url) {
+                (Some(data), _) if is_text_media_type(media_type) => {
+                    let bytes = BASE64
+                        .decode(data)
+                        .map_err(|e| format!("Invalid base64 data for {}: {}", name, e))?;
+                    Ok(UserContent::text(format!(
+                        "{}:\n\n{}",
+                        name,
+                        String::from_utf8_lossy(&bytes)
+                    )))
+                }
+                (Some(data), _) => Ok(UserContent::document(
+                    data.clone(),
+                    DocumentMediaType::from_mime_type(media_type),
+                )),
+                (None, Some(url)) => Ok(UserContent::text(format!("{}: {}", name, url))),
+                (None, None) => Err(unresolved_source_error(source)),
+            }
+        }
+    }
+}
+
+fn unresolved_source_error(source: &MediaSource) -> String {
+    match &source.path {
+        Some(path) => format!("Attachment {} was not loaded", path),
+        None => "Attachment has no url, data or path".to_string(),
+    }
+}
+
+/// Builds a user message from content and extra attachments
+pub fn user_message(
+    content: &ChatContent,
+    attachments: &[ContentPart],
+    vision: bool,
+) -> Result<Message, String> {
+    let items = content
+        .parts()
+        .iter()
+        .chain(attachments)
+        .filter(|part| !matches!(part, ContentPart::Text { text } if text.is_empty()))
+        .map(|part| to_user_content(part, vision))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match OneOrMany::many(items) {
+        Ok(content) => Ok(Message::User { content }),
+        Err(_) => Ok(Message::user(String::new())),
+    }
+}
+
+/// Builds the assistant turn that requested `tool_calls`
+pub fn assistant_tool_message(content: &str, tool_calls: &[ToolCallInfo]) -> Message {
+    let mut items = Vec::new();
+    if !content.is_empty() {
+        items.push(AssistantContent::text(content));
+    }
+    for call in tool_calls {
+        items.push(match &call.call_id {
+            Some(call_id) => AssistantContent::tool_call_with_call_id(
+                call.id.clone(),
+                call_id.clone(),
+                call.name.clone(),
+                call.arguments.clone(),
+            ),
+            None => AssistantContent::tool_call(
+                call.id.clone(),
+                call.name.clone(),
+                call.arguments.clone(),
+            ),
+        });
+    }
+
+    Message::Assistant {
+        id: None,
+        content: OneOrMany::many(items).expect("tool calls are never empty"),
+    }
+}
+
+/// Builds the result of the tool call `id`, as sent back to the model
+pub fn tool_result(id: &str, call_id: Option<&str>, text: String) -> UserContent {
+    let content = OneOrMany::one(ToolResultContent::text(text));
+    match call_id {
+        Some(call_id) => {
+            UserContent::tool_result_with_call_id(id.to_string(), call_id.to_string(), content)
+        }
+        None => UserContent::tool_result(id.to_string(), content),
+    }
+}
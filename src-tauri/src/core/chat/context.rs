@@ -0,0 +1,200 @@
+//! Keeps the chat history within the model's context window. Token counts are estimated
+//! from the text length, which is close enough to leave room for the reply without
+//! shipping a tokenizer per provider.
+
+use serde::{Deserialize, Serialize};
+
+use super::constants::{
+    DEFAULT_CONTEXT_WINDOW, IMAGE_TOKEN_ESTIMATE, MESSAGE_OVERHEAD_TOKENS, MODEL_CONTEXT_WINDOWS,
+    SUMMARY_MAX_TOKENS,
+};
+use super::content::{ChatContent, ContentPart};
+use super::ChatMessage;
+
+/// How the history is shortened when it does not fit
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContextStrategy {
+    /// Drops the oldest messages until the rest fits
+    #[default]
+    DropOldest,
+    /// Keeps the system messages and the last `count` messages, then drops the oldest
+    /// of those if they still do not fit
+    KeepLast { count: usize },
+    /// Replaces the messages before the last `keep_last` with a summary written by the
+    /// same model
+    Summarize { keep_last: usize },
+}
+
+impl ContextStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContextStrategy::DropOldest => "drop_oldest",
+            ContextStrategy::KeepLast { .. } => "keep_last",
+            ContextStrategy::Summarize { .. } => "summarize",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ContextOptions {
+    #[serde(default)]
+    pub strategy: ContextStrategy,
+    /// Overrides the context window looked up from the model name
+    pub context_window: Option<u64>,
+}
+
+/// What was done to the history, reported on the `start` event
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ContextReport {
+    pub strategy: String,
+    pub context_window: u64,
+    /// Estimated tokens of the request as sent: preamble, history and prompt
+    pub estimated_tokens: u64,
+    pub dropped: usize,
+    pub summarized: usize,
+}
+
+/// The messages to send, and the older ones to replace with a summary
+#[derive(Debug, Clone, Default)]
+pub struct ContextPlan {
+    pub keep: Vec<ChatMessage>,
+    pub summarize: Vec<ChatMessage>,
+    pub dropped: usize,
+}
+
+pub fn estimate_text_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+pub fn estimate_part_tokens(part: &ContentPart) -> u64 {
+    match part {
+        ContentPart::Text { text } => estimate_text_tokens(text),
+        ContentPart::Image(_) => IMAGE_TOKEN_ESTIMATE,
+        // Base64 takes 4 characters per 3 bytes, and about 4 bytes make a token
+        ContentPart::Document(source) => source
+            .data
+            .as_ref()
+            .map(|data| (data.len() as u64 * 3 / 4).div_ceil(4))
+            .unwrap_or(IMAGE_TOKEN_ESTIMATE),
+    }
+}
+
+pub fn estimate_content_tokens(content: &ChatContent) -> u64 {
+    match content {
+        ChatContent::Text(text) => estimate_text_tokens(text),
+        ChatContent::Parts(parts) => parts.iter().map(estimate_part_tokens).sum(),
+    }
+}
+
+pub fn estimate_message_tokens(message: &ChatMessage) -> u64 {
+    let tool_call_tokens: u64 = message
+        .tool_calls
+        .iter()
+        .map(|call| {
+            estimate_text_tokens(&call.name) + estimate_text_tokens(&call.arguments.to_string())
+        })
+        .sum();
+    MESSAGE_OVERHEAD_TOKENS + estimate_content_tokens(&message.content) + tool_call_tokens
+}
+
+/// Whether `name` belongs to `family`. A family ending in a version number only matches
+/// the whole number, so `gpt-4` takes neither `gpt-4o` nor `gpt-4.5`.
+fn is_model_family(name: &str, family: &str) -> bool {
+    let Some(rest) = name.strip_prefix(family) else {
+        return false;
+    };
+    !family.ends_with(|c: char| c.is_ascii_digit())
+        || !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '.')
+}
+
+/// Context window of a model, matched on its name without the provider or organization
+/// prefix (`openai/`, `models/`). The longest family of `MODEL_CONTEXT_WINDOWS` the name
+/// starts with wins.
+pub fn context_window(model: &str) -> u64 {
+    let model = model.to_ascii_lowercase();
+    let name = model.rsplit('/').next().unwrap_or_default();
+    MODEL_CONTEXT_WINDOWS
+        .iter()
+        .filter(|(family, _)| is_model_family(name, family))
+        .max_by_key(|(family, _)| family.len())
+        .map(|(_, window)| *window)
+        .unwrap_or(DEFAULT_CONTEXT_WINDOW)
+}
+
+/// Decides which history messages fit in `budget` tokens.
+/// System messages are always kept, they become the preamble. The kept conversation
+/// never starts with an assistant message, which some providers reject, nor with tool
+/// results whose call was dropped.
+pub fn plan_context(
+    history: Vec<ChatMessage>,
+    budget: u64,
+    strategy: &ContextStrategy,
+) -> ContextPlan {
+    let (system, mut conversation): (Vec<ChatMessage>, Vec<ChatMessage>) = history
+        .into_iter()
+        .partition(|message| message.role == "system");
+    let system_tokens: u64 = system.iter().map(estimate_message_tokens).sum();
+    let mut dropped = 0;
+    let mut summarize = Vec::new();
+
+    let total = |conversation: &[ChatMessage], summarize: &[ChatMessage]| {
+        let summary_tokens = if summarize.is_empty() {
+            0
+        } else {
+            SUMMARY_MAX_TOKENS
+        };
+        system_tokens
+            + summary_tokens
+            + conversation
+                .iter()
+                .map(estimate_message_tokens)
+                .sum::<u64>()
+    };
+
+    match strategy {
+        ContextStrategy::DropOldest => {}
+        ContextStrategy::KeepLast { count } => {
+            let excess = conversation.len().saturating_sub(*count);
+            conversation.drain(..excess);
+            dropped += excess;
+        }
+        ContextStrategy::Summarize { keep_last } => {
+            if total(&conversation, &summarize) > budget {
+                let older = conversation.len().saturating_sub(*keep_last);
+                summarize = conversation.drain(..older).collect();
+            }
+        }
+    }
+
+    while !conversation.is_empty() && total(&conversation, &summarize) > budget {
+        conversation.remove(0);
+        dropped += 1;
+    }
+    let shortened = dropped > 0 || !summarize.is_empty();
+    while shortened
+        && conversation
+            .first()
+            .is_some_and(|message| message.role == "assistant" || message.role == "tool")
+    {
+        conversation.remove(0);
+        dropped += 1;
+    }
+
+    let mut keep = system;
+    keep.extend(conversation);
+    ContextPlan {
+        keep,
+        summarize,
+        dropped,
+    }
+}
+
+/// Renders messages as a plain transcript for the summarization prompt
+pub fn transcript(messages: &[ChatMessage]) -> String {
+    messages
+        .iter()
+        .map(|message| format!("{}: {}", message.role, message.content.text()))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
@@ -0,0 +1,131 @@
+//! Embeddings from the remote providers, for retrieval features and the proxy's
+//! `/v1/embeddings`. Inputs are checked against size limits and sent in batches the
+//! provider accepts; the vectors come back in the order of the inputs.
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use super::constants::{
+    EMBEDDING_BATCH_SIZE, EMBEDDING_MAX_INPUTS, EMBEDDING_MAX_INPUT_TOKENS,
+    MOCK_EMBEDDING_DIMENSIONS, MOCK_PROVIDER,
+};
+use super::context::estimate_text_tokens;
+use super::providers::{build_embedding_model, ProviderConfig};
+use super::ChatService;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct EmbeddingUsage {
+    /// Estimated from the inputs, providers do not report it through rig
+    pub prompt_tokens: u64,
+    pub total_tokens: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EmbeddingResponse {
+    pub provider: String,
+    pub model: String,
+    /// One vector per input, in the order of the inputs
+    pub embeddings: Vec<Vec<f64>>,
+    pub dimensions: usize,
+    pub usage: EmbeddingUsage,
+}
+
+/// Checks the inputs against `EMBEDDING_MAX_INPUTS` and `EMBEDDING_MAX_INPUT_TOKENS`.
+/// Returns the estimated token count.
+pub fn validate_inputs(inputs: &[String]) -> Result<u64, String> {
+    if inputs.is_empty() {
+        return Err("No inputs to embed".to_string());
+    }
+    if inputs.len() > EMBEDDING_MAX_INPUTS {
+        return Err(format!(
+            "Too many inputs: {} (at most {})",
+            inputs.len(),
+            EMBEDDING_MAX_INPUTS
+        ));
+    }
+    let mut total = 0;
+    for (index, input) in inputs.iter().enumerate() {
+        if input.trim().is_empty() {
+            return Err(format!("Input {} is empty", index));
+        }
+        let tokens = estimate_text_tokens(input);
+        if tokens > EMBEDDING_MAX_INPUT_TOKENS {
+            return Err(format!(
+                "Input {} is too long: about {} tokens (at most {})",
+                index, tokens, EMBEDDING_MAX_INPUT_TOKENS
+            ));
+        }
+        total += tokens;
+    }
+    Ok(total)
+}
+
+/// Deterministic vector for the mock provider: a normalized byte histogram of the text
+pub fn mock_embedding(text: &str) -> Vec<f64> {
+    let mut vector = vec![0.0; MOCK_EMBEDDING_DIMENSIONS];
+    for (index, byte) in text.bytes().enumerate() {
+        vector[(byte as usize + index) % MOCK_EMBEDDING_DIMENSIONS] += 1.0;
+    }
+    let norm = vector.iter().map(|v| v * v).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+impl ChatService {
+    /// Embeds `inputs` with `model` of `provider`, in batches of at most
+    /// `EMBEDDING_BATCH_SIZE` or what the model accepts. Takes one chat slot.
+    pub async fn embed(
+        &self,
+        provider: &str,
+        model: &str,
+        inputs: Vec<String>,
+        provider_config: Option<&ProviderConfig>,
+    ) -> Result<EmbeddingResponse, String> {
+        let prompt_tokens = validate_inputs(&inputs)?;
+        let embeddings = if provider == MOCK_PROVIDER {
+            inputs.iter().map(|input| mock_embedding(input)).collect()
+        } else {
+            let _permit = self
+                .acquire_slot(&CancellationToken::new())
+                .await
+                .ok_or("Chat service is shut down")?;
+            let embedding_model =
+                build_embedding_model(&self.client, provider, model, provider_config)?;
+            let batch_size = match embedding_model.max_documents() {
+                0 => EMBEDDING_BATCH_SIZE,
+                max => max.min(EMBEDDING_BATCH_SIZE),
+            };
+
+            let mut embeddings = Vec::with_capacity(inputs.len());
+            for batch in inputs.chunks(batch_size) {
+                let vectors = embedding_model
+                    .embed_texts(batch.to_vec())
+                    .await
+                    .map_err(|e| format!("Embedding request to {} failed: {}", provider, e))?;
+                if vectors.len() != batch.len() {
+                    return Err(format!(
+                        "{} returned {} embeddings for {} inputs",
+                        provider,
+                        vectors.len(),
+                        batch.len()
+                    ));
+                }
+                embeddings.extend(vectors.into_iter().map(|embedding| embedding.vec));
+            }
+            embeddings
+        };
+
+        Ok(EmbeddingResponse {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            dimensions: embeddings.first().map_or(0, Vec::len),
+            embeddings,
+            usage: EmbeddingUsage {
+                prompt_tokens,
+                total_tokens: prompt_tokens,
+            },
+        })
+    }
+}
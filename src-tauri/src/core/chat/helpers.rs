@@ -0,0 +1,316 @@
+use serde_json::{json, Map, Value};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::constants::{MOCK_PROVIDER, REMOTE_PROVIDERS};
+use super::{
+    ChatMessage, ChatRequest, ChatResponse, ChatService, ChatStreamEvent, ChatUsage,
+    CompletionMetadata, GenerationParams, ToolCallInfo, ToolChoice,
+};
+use crate::core::mcp::models::ToolWithServer;
+use crate::core::state::SharedMcpServers;
+
+/// Splits a `provider/model` id into its parts if the provider is a supported remote
+/// provider or the built-in mock provider
+pub fn parse_remote_model(model_id: &str) -> Option<(String, String)> {
+    let (provider, model) = model_id.split_once('/')?;
+    if model.is_empty() || !(REMOTE_PROVIDERS.contains(&provider) || provider == MOCK_PROVIDER) {
+        return None;
+    }
+    Some((provider.to_string(), model.to_string()))
+}
+
+/// Joins the assistant instructions and any system messages into the preamble
+pub fn build_preamble(
+    instructions: &Option<String>,
+    history: &Option<Vec<ChatMessage>>,
+) -> Option<String> {
+    let system_messages: Vec<String> = history
+        .iter()
+        .flatten()
+        .filter(|msg| msg.role == "system")
+        .map(|msg| msg.content.text())
+        .collect();
+    let parts: Vec<&str> = instructions
+        .as_deref()
+        .into_iter()
+        .chain(system_messages.iter().map(String::as_str))
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("\n\n"))
+    }
+}
+
+/// Builds the provider-specific request fields for the parameters rig has no setter for
+/// (`top_p`, `stop`, `seed`), then merges `extra_params` over them.
+pub fn build_additional_params(provider: &str, params: &GenerationParams) -> Option<Value> {
+    let mut fields = Map::new();
+    let mut insert = |key: &str, value: Option<Value>| {
+        if let Some(value) = value {
+            fields.insert(key.to_string(), value);
+        }
+    };
+    let top_p = params.top_p.map(|v| json!(v));
+    let stop = params
+        .stop
+        .clone()
+        .filter(|s| !s.is_empty())
+        .map(|v| json!(v));
+    let seed = params.seed.map(|v| json!(v));
+
+    match provider {
+        "anthropic" => {
+            insert("top_p", top_p);
+            insert("stop_sequences", stop);
+        }
+        "cohere" => {
+            insert("p", top_p);
+            insert("stop_sequences", stop);
+            insert("seed", seed);
+        }
+        "gemini" => {
+            let mut config = Map::new();
+            for (key, value) in [("topP", top_p), ("stopSequences", stop), ("seed", seed)] {
+                if let Some(value) = value {
+                    config.insert(key.to_string(), value);
+                }
+            }
+            if !config.is_empty() {
+                insert("generationConfig", Some(Value::Object(config)));
+            }
+        }
+        _ => {
+            insert("top_p", top_p);
+            insert("stop", stop);
+            insert("seed", seed);
+        }
+    }
+
+    if let Some(Value::Object(extra)) = &params.extra_params {
+        fields.extend(extra.clone());
+    }
+
+    if fields.is_empty() {
+        None
+    } else {
+        Some(Value::Object(fields))
+    }
+}
+
+/// Builds the provider-specific request fields for `choice`, rig has no setter for it.
+/// `None` when the provider's default already means `choice`.
+pub fn tool_choice_params(kind: &str, choice: &ToolChoice) -> Option<Value> {
+    match kind {
+        "anthropic" => {
+            let choice = match choice {
+                ToolChoice::Auto => json!({ "type": "auto" }),
+                ToolChoice::None => json!({ "type": "none" }),
+                ToolChoice::Required => json!({ "type": "any" }),
+                ToolChoice::Tool(name) => json!({ "type": "tool", "name": name }),
+            };
+            Some(json!({ "tool_choice": choice }))
+        }
+        "gemini" => {
+            let config = match choice {
+                ToolChoice::Auto => json!({ "mode": "AUTO" }),
+                ToolChoice::None => json!({ "mode": "NONE" }),
+                ToolChoice::Required => json!({ "mode": "ANY" }),
+                ToolChoice::Tool(name) => json!({ "mode": "ANY", "allowedFunctionNames": [name] }),
+            };
+            Some(json!({ "toolConfig": { "functionCallingConfig": config } }))
+        }
+        "cohere" => match choice {
+            ToolChoice::Auto => None,
+            ToolChoice::None => Some(json!({ "tool_choice": "NONE" })),
+            // Cohere cannot force a specific tool
+            ToolChoice::Required | ToolChoice::Tool(_) => {
+                Some(json!({ "tool_choice": "REQUIRED" }))
+            }
+        },
+        _ => {
+            let choice = match choice {
+                ToolChoice::Auto => json!("auto"),
+                ToolChoice::None => json!("none"),
+                ToolChoice::Required => json!("required"),
+                ToolChoice::Tool(name) => {
+                    json!({ "type": "function", "function": { "name": name } })
+                }
+            };
+            Some(json!({ "tool_choice": choice }))
+        }
+    }
+}
+
+/// Reads generation parameters from a thread assistant's model settings
+pub fn params_from_model_settings(settings: &Value) -> GenerationParams {
+    let stop = match settings.get("stop") {
+        Some(Value::String(stop)) => Some(vec![stop.clone()]),
+        Some(Value::Array(items)) => Some(
+            items
+                .iter()
+                .filter_map(|item| item.as_str().map(String::from))
+                .collect(),
+        ),
+        _ => None,
+    };
+
+    GenerationParams {
+        temperature: settings.get("temperature").and_then(|v| v.as_f64()),
+        top_p: settings.get("top_p").and_then(|v| v.as_f64()),
+        max_tokens: settings.get("max_tokens").and_then(|v| v.as_u64()),
+        stop,
+        seed: settings.get("seed").and_then(|v| v.as_u64()),
+        extra_params: None,
+    }
+}
+
+/// Infers why a model turn ended, since rig does not report it for streams
+pub fn finish_reason(
+    tool_calls: &[ToolCallInfo],
+    usage: Option<ChatUsage>,
+    max_tokens: Option<u64>,
+) -> &'static str {
+    if !tool_calls.is_empty() {
+        return "tool_calls";
+    }
+    match (usage, max_tokens) {
+        (Some(usage), Some(max_tokens)) if usage.completion_tokens >= max_tokens => "length",
+        _ => "stop",
+    }
+}
+
+/// Builds the completion metadata from the usage and the timestamps of a chat.
+/// Throughput is measured from the first token so it excludes the queueing time.
+pub fn completion_metadata(
+    usage: Option<ChatUsage>,
+    finish_reason: &str,
+    started: Instant,
+    first_token_at: Option<Instant>,
+    finished: Instant,
+) -> CompletionMetadata {
+    let generation_secs = finished
+        .saturating_duration_since(first_token_at.unwrap_or(started))
+        .as_secs_f64();
+    let tokens_per_second = usage
+        .filter(|usage| usage.completion_tokens > 0 && generation_secs > 0.0)
+        .map(|usage| usage.completion_tokens as f64 / generation_secs);
+
+    CompletionMetadata {
+        usage,
+        finish_reason: finish_reason.to_string(),
+        time_to_first_token_ms: first_token_at
+            .map(|at| at.saturating_duration_since(started).as_millis() as u64),
+        duration_ms: finished.saturating_duration_since(started).as_millis() as u64,
+        tokens_per_second,
+        provider: None,
+        model: None,
+        structured: None,
+    }
+}
+
+/// Runs a streaming chat as a task on the app runtime.
+pub fn spawn_chat_stream(
+    chat_service: ChatService,
+    request: ChatRequest,
+    cancel_token: CancellationToken,
+    emit_callback: impl Fn(ChatStreamEvent) + Send + Sync + 'static,
+) -> JoinHandle<Result<String, String>> {
+    tokio::spawn(async move {
+        chat_service
+            .stream_chat(request, cancel_token, emit_callback)
+            .await
+            .map_err(|e| format!("Failed to stream chat: {}", e))
+    })
+}
+
+/// Runs the agent loop as a task on the app runtime, like `spawn_chat_stream`.
+pub fn spawn_agent_stream(
+    chat_service: ChatService,
+    request: ChatRequest,
+    tools: Vec<ToolWithServer>,
+    servers: SharedMcpServers,
+    max_steps: usize,
+    cancel_token: CancellationToken,
+    emit_callback: impl Fn(ChatStreamEvent) + Send + Sync + 'static,
+) -> JoinHandle<Result<String, String>> {
+    tokio::spawn(async move {
+        chat_service
+            .stream_agent(
+                request,
+                tools,
+                servers,
+                max_steps,
+                cancel_token,
+                emit_callback,
+            )
+            .await
+            .map_err(|e| format!("Failed to run agent: {}", e))
+    })
+}
+
+/// Runs a non-streaming chat.
+pub async fn run_chat(
+    chat_service: &ChatService,
+    request: ChatRequest,
+) -> Result<ChatResponse, String> {
+    chat_service
+        .chat_non_streaming(request)
+        .await
+        .map_err(|e| format!("Failed to chat: {}", e))
+}
+
+/// Runs the agent loop to the end, for callers that do not stream. The content of the
+/// response joins the text of every turn; the emitted events are returned with it.
+pub async fn run_agent(
+    chat_service: &ChatService,
+    request: ChatRequest,
+    tools: Vec<ToolWithServer>,
+    servers: SharedMcpServers,
+    max_steps: usize,
+) -> Result<(ChatResponse, Vec<ChatStreamEvent>), String> {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = events.clone();
+    let stream_id = chat_service
+        .stream_agent(
+            request,
+            tools,
+            servers,
+            max_steps,
+            CancellationToken::new(),
+            move |event| {
+                if let Ok(mut events) = sink.lock() {
+                    events.push(event);
+                }
+            },
+        )
+        .await
+        .map_err(|e| format!("Failed to run agent: {}", e))?;
+    let events = std::mem::take(&mut *events.lock().map_err(|e| e.to_string())?);
+
+    let content = events
+        .iter()
+        .filter(|event| event.event_type == "text")
+        .map(|event| event.content.as_str())
+        .collect();
+    let metadata = events
+        .iter()
+        .rev()
+        .find(|event| event.event_type == "complete")
+        .and_then(|event| event.metadata.clone());
+    let response = ChatResponse {
+        stream_id,
+        content,
+        status: "completed".to_string(),
+        metadata,
+        structured: None,
+        tool_calls: Vec::new(),
+    };
+    Ok((response, events))
+}
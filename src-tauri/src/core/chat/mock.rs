@@ -0,0 +1,216 @@
+//! Built-in `mock` provider for running the chat pipeline without network or API keys.
+//! The model `echo` streams the prompt back word by word. Any other model names a
+//! fixture file (JSON or YAML) whose scripted responses are replayed, including
+//! reasoning, tool calls, delays and errors.
+
+use futures_util::stream;
+use futures_util::StreamExt;
+use rig::completion::Message;
+use rig::message::{AssistantContent, ToolResultContent, UserContent};
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+
+use super::constants::MOCK_ECHO_MODEL;
+use super::context::estimate_text_tokens;
+use super::{ChatUsage, ChunkStream, StreamChunk, ToolCallInfo};
+
+/// Scripted responses of a fixture file
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MockFixture {
+    #[serde(default)]
+    pub responses: Vec<MockResponse>,
+}
+
+/// One scripted model turn
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MockResponse {
+    /// Plays this response when the prompt contains the text. Responses without it are
+    /// played in order, one per model turn of the conversation.
+    pub prompt_contains: Option<String>,
+    #[serde(default)]
+    pub chunks: Vec<MockChunk>,
+    /// Fails the request before anything is streamed
+    pub error: Option<String>,
+    /// Wait before each chunk
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// Reported usage, estimated from the text when unset
+    pub usage: Option<ChatUsage>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MockChunk {
+    Text {
+        text: String,
+    },
+    Reasoning {
+        text: String,
+    },
+    ToolCall {
+        id: Option<String>,
+        name: String,
+        #[serde(default)]
+        arguments: Value,
+    },
+    /// Ends the stream with this error
+    Error {
+        message: String,
+    },
+    Delay {
+        ms: u64,
+    },
+}
+
+/// Text of a rig message: its text parts and tool results
+pub fn message_text(message: &Message) -> String {
+    let parts: Vec<String> = match message {
+        Message::User { content } => content
+            .iter()
+            .flat_map(|item| match item {
+                UserContent::Text(text) => vec![text.text.clone()],
+                UserContent::ToolResult(result) => result
+                    .content
+                    .iter()
+                    .filter_map(|item| match item {
+                        ToolResultContent::Text(text) => Some(text.text.clone()),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            })
+            .collect(),
+        Message::Assistant { content, .. } => content
+            .iter()
+            .filter_map(|item| match item {
+                AssistantContent::Text(text) => Some(text.text.clone()),
+                _ => None,
+            })
+            .collect(),
+    };
+    parts.join("\n")
+}
+
+/// Reads a fixture file
+pub fn load_fixture(path: &str) -> Result<MockFixture, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read mock fixture {}: {}", path, e))?;
+    // YAML is a superset of JSON, so this reads both
+    serde_yaml::from_str(&text).map_err(|e| format!("Invalid mock fixture {}: {}", path, e))
+}
+
+/// Picks the response for a turn: the first whose `prompt_contains` matches, otherwise
+/// the `turn`-th unconditional one, repeating the last once the script runs out
+pub fn select_response<'a>(
+    fixture: &'a MockFixture,
+    prompt: &str,
+    turn: usize,
+) -> Option<&'a MockResponse> {
+    let matching = fixture.responses.iter().find(|response| {
+        response
+            .prompt_contains
+            .as_deref()
+            .is_some_and(|text| prompt.contains(text))
+    });
+    if matching.is_some() {
+        return matching;
+    }
+    let unconditional: Vec<&MockResponse> = fixture
+        .responses
+        .iter()
+        .filter(|response| response.prompt_contains.is_none())
+        .collect();
+    unconditional.get(turn).or(unconditional.last()).copied()
+}
+
+/// The response of the `echo` model: the prompt, one word per chunk
+fn echo_response(prompt: &str) -> MockResponse {
+    let chunks = prompt
+        .split_inclusive(char::is_whitespace)
+        .map(|word| MockChunk::Text {
+            text: word.to_string(),
+        })
+        .collect();
+    MockResponse {
+        chunks,
+        ..Default::default()
+    }
+}
+
+/// Opens a stream for `model`, replaying `echo` or the fixture the model names
+pub fn mock_stream(
+    model: &str,
+    prompt: &Message,
+    history: &[Message],
+) -> Result<ChunkStream, String> {
+    let prompt_text = message_text(prompt);
+    let response = if model == MOCK_ECHO_MODEL {
+        echo_response(&prompt_text)
+    } else {
+        let fixture = load_fixture(model)?;
+        let turn = history
+            .iter()
+            .filter(|message| matches!(message, Message::Assistant { .. }))
+            .count();
+        select_response(&fixture, &prompt_text, turn)
+            .cloned()
+            .ok_or_else(|| format!("Mock fixture {} has no responses", model))?
+    };
+    if let Some(error) = response.error {
+        return Err(error);
+    }
+
+    let prompt_tokens = history
+        .iter()
+        .chain(std::iter::once(prompt))
+        .map(|message| estimate_text_tokens(&message_text(message)))
+        .sum::<u64>();
+    let mut completion_tokens = 0;
+    let mut items = Vec::new();
+    let mut delay = 0;
+    for (index, chunk) in response.chunks.into_iter().enumerate() {
+        let item = match chunk {
+            MockChunk::Text { text } => {
+                completion_tokens += estimate_text_tokens(&text);
+                Ok(StreamChunk::Text(text))
+            }
+            MockChunk::Reasoning { text } => {
+                completion_tokens += estimate_text_tokens(&text);
+                Ok(StreamChunk::Reasoning(text))
+            }
+            MockChunk::ToolCall {
+                id,
+                name,
+                arguments,
+            } => Ok(StreamChunk::ToolCall(ToolCallInfo {
+                id: id.unwrap_or_else(|| format!("mock_call_{}", index)),
+                call_id: None,
+                name,
+                arguments,
+            })),
+            MockChunk::Error { message } => Err(message),
+            MockChunk::Delay { ms } => {
+                delay += ms;
+                continue;
+            }
+        };
+        items.push((delay + response.delay_ms, item));
+        delay = 0;
+    }
+    let usage = response.usage.unwrap_or(ChatUsage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: prompt_tokens + completion_tokens,
+    });
+    items.push((delay, Ok(StreamChunk::Final(Some(usage)))));
+
+    Ok(stream::iter(items)
+        .then(|(delay, item)| async move {
+            if delay > 0 {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+            }
+            item
+        })
+        .boxed())
+}
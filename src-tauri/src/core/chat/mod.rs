@@ -1,12 +1,65 @@
+use futures_util::stream::BoxStream;
 use futures_util::StreamExt;
+use rig::completion::{GetTokenUsage, Message, ToolDefinition};
+use rig::message::UserContent;
+use rig::streaming::{StreamedAssistantContent, StreamingCompletion};
+use rig::OneOrMany;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+pub mod agent;
+pub mod catalog;
 pub mod commands;
+pub mod compare;
+pub mod constants;
+pub mod content;
+pub mod context;
+pub mod embeddings;
+pub mod helpers;
+pub mod mock;
+pub mod providers;
+pub mod recorder;
+pub mod retry;
+pub mod streams;
+pub mod structured;
+pub mod titles;
+
+use compare::CompareBranch;
+use constants::{
+    DEFAULT_RESERVED_OUTPUT_TOKENS, MAX_CONCURRENT_CHATS, MESSAGE_OVERHEAD_TOKENS, MOCK_PROVIDER,
+    SUMMARY_INSTRUCTIONS, SUMMARY_MAX_TOKENS,
+};
+use content::{assistant_tool_message, tool_result, user_message, ChatContent, ContentPart};
+use context::{
+    context_window, estimate_message_tokens, estimate_part_tokens, estimate_text_tokens,
+    plan_context, transcript, ContextOptions, ContextReport,
+};
+use helpers::{
+    build_additional_params, build_preamble, completion_metadata, finish_reason, tool_choice_params,
+};
+use mock::mock_stream;
+use providers::{build_agent, ProviderConfig};
+use retry::{ChatTarget, RetryPolicy};
+use structured::{
+    format_instructions, merge_params, response_format_params, ResponseFormat, StructuredOutput,
+};
+
+#[cfg(test)]
+mod tests;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatMessage {
     pub role: String,
-    pub content: String,
+    pub content: ChatContent,
     pub timestamp: Option<u64>,
+    /// Tool calls requested by an `assistant` message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCallInfo>,
+    /// The call a `tool` message holds the result of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -16,6 +69,53 @@ pub struct ChatRequest {
     pub model: String,
     pub stream_id: Option<String>,
     pub chat_history: Option<Vec<ChatMessage>>,
+    /// Images and documents sent along with `prompt`
+    #[serde(default)]
+    pub attachments: Vec<ContentPart>,
+    /// Whether the model accepts images; when `false` they are replaced by a text note
+    #[serde(default)]
+    pub supports_vision: Option<bool>,
+    /// Assistant instructions, sent ahead of any system messages as the preamble
+    #[serde(default)]
+    pub instructions: Option<String>,
+    #[serde(flatten)]
+    pub params: GenerationParams,
+    /// Credentials and endpoint for `provider`; environment variables are used when unset
+    #[serde(skip)]
+    pub provider_config: Option<ProviderConfig>,
+    /// How the history is fitted into the context window, `drop_oldest` when unset
+    #[serde(default)]
+    pub context: Option<ContextOptions>,
+    /// Retries of transient errors, two retries when unset
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    /// Provider/model pairs tried in order when this one fails before streaming anything
+    #[serde(default)]
+    pub fallbacks: Vec<ChatTarget>,
+    /// Requires a JSON reply, optionally matching a JSON Schema
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+    /// Times an invalid JSON reply is sent back for correction, two when unset
+    #[serde(default)]
+    pub validation_retries: Option<u32>,
+    /// Tools offered to the model. Its calls are returned to the caller, not run.
+    #[serde(default)]
+    pub tools: Vec<ToolDefinition>,
+    /// Whether the model may or must call one of `tools`, left to the provider when unset
+    #[serde(default)]
+    pub tool_choice: Option<ToolChoice>,
+}
+
+/// Sampling parameters passed through to the provider
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct GenerationParams {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u64>,
+    pub stop: Option<Vec<String>>,
+    pub seed: Option<u64>,
+    /// Provider-specific fields merged into the request body as-is
+    pub extra_params: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -24,6 +124,118 @@ pub struct ChatStreamEvent {
     pub content: String,
     pub event_type: String,
     pub is_final: bool,
+    /// Position of the event in its stream, starting at 1; 0 for events that are not
+    /// buffered (see `streams::ChatStreamBuffer`)
+    #[serde(default)]
+    pub seq: u64,
+    /// Usage and timing, set on the `complete` event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<CompletionMetadata>,
+    /// How the history was fitted into the context window, set on the `start` event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<ContextReport>,
+    /// The comparison branch the event belongs to, set by `compare_chat`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compare: Option<CompareBranch>,
+}
+
+/// Token usage reported by the provider
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct ChatUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+/// Usage, finish reason and timing of a finished completion
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct CompletionMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatUsage>,
+    /// `stop`, `length` or `tool_calls`
+    pub finish_reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_to_first_token_ms: Option<u64>,
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_second: Option<f64>,
+    /// Provider and model that answered, which differ from the request after a fallback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Validation result of a reply with a JSON `response_format`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured: Option<StructuredOutput>,
+}
+
+/// Enables the agent loop, which offers MCP tools to the model and runs its tool calls
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AgentOptions {
+    /// Maximum number of model turns before giving up, defaults to `AGENT_MAX_STEPS`
+    pub max_steps: Option<usize>,
+    /// Restricts the offered tools to these MCP servers, defaults to all connected servers
+    pub servers: Option<Vec<String>>,
+}
+
+/// A tool call requested by the model
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ToolCallInfo {
+    pub id: String,
+    pub call_id: Option<String>,
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+/// Whether the model may or must call a tool
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", content = "name", rename_all = "snake_case")]
+pub enum ToolChoice {
+    Auto,
+    None,
+    /// Any of the offered tools
+    Required,
+    /// The tool with this name
+    Tool(String),
+}
+
+/// Provider-independent piece of a streamed model turn
+#[derive(Debug, Clone)]
+pub enum StreamChunk {
+    Text(String),
+    Reasoning(String),
+    ToolCall(ToolCallInfo),
+    Final(Option<ChatUsage>),
+}
+
+type ChunkStream = BoxStream<'static, Result<StreamChunk, String>>;
+
+/// What a single model turn produced
+#[derive(Debug, Default)]
+struct StepOutput {
+    content: String,
+    tool_calls: Vec<ToolCallInfo>,
+    usage: Option<ChatUsage>,
+    first_token_at: Option<Instant>,
+    cancelled: bool,
+}
+
+impl StepOutput {
+    fn cancelled() -> Self {
+        Self {
+            cancelled: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl CompletionMetadata {
+    /// Records the provider and model the request ended up using
+    pub fn answered_by(mut self, request: &ChatRequest) -> Self {
+        self.provider = Some(request.provider.clone());
+        self.model = Some(request.model.clone());
+        self
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -31,121 +243,370 @@ pub struct ChatResponse {
     pub stream_id: String,
     pub content: String,
     pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<CompletionMetadata>,
+    /// The parsed JSON reply and its validation status, with a JSON `response_format`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured: Option<StructuredOutput>,
+    /// Calls of the request's `tools` the model made instead of, or besides, answering
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCallInfo>,
 }
 
+/// Runs chats against the configured providers.
+/// Cheap to clone; clones share the provider clients and the concurrency limit.
+#[derive(Clone)]
 pub struct ChatService {
-    client: rig::client::builder::DynClientBuilder,
+    client: Arc<rig::client::builder::DynClientBuilder>,
+    limiter: Arc<Semaphore>,
 }
 
 impl ChatService {
     pub fn new() -> Self {
+        Self::with_concurrency(MAX_CONCURRENT_CHATS)
+    }
+
+    /// Creates a service that runs at most `max_concurrent` chats at once
+    pub fn with_concurrency(max_concurrent: usize) -> Self {
         Self {
-            client: rig::client::builder::DynClientBuilder::new(),
+            client: Arc::new(rig::client::builder::DynClientBuilder::new()),
+            limiter: Arc::new(Semaphore::new(max_concurrent)),
+        }
+    }
+
+    /// Waits for a free chat slot. Returns `None` if `cancel_token` fires first.
+    async fn acquire_slot(&self, cancel_token: &CancellationToken) -> Option<OwnedSemaphorePermit> {
+        tokio::select! {
+            _ = cancel_token.cancelled() => None,
+            permit = self.limiter.clone().acquire_owned() => permit.ok(),
         }
     }
 
+    /// Converts the history into rig messages. System messages are left out here,
+    /// they are sent as the preamble (see `build_preamble`). Consecutive `tool` messages
+    /// become one user turn, as the results of parallel calls go back together.
     fn convert_to_rig_chat_history(
         &self,
         history: &Option<Vec<ChatMessage>>,
-    ) -> Vec<rig::completion::Message> {
-        let mut messages = Vec::new();
+        vision: bool,
+    ) -> Result<Vec<Message>, String> {
+        let mut messages: Vec<Message> = Vec::new();
 
         if let Some(history_messages) = history {
             for msg in history_messages {
                 let rig_message = match msg.role.as_str() {
-                    "user" => rig::completion::Message::user(msg.content.clone()),
-                    "assistant" => rig::completion::Message::assistant(msg.content.clone()),
-                    "system" => rig::completion::Message::user(msg.content.clone()), // system messages as user in rig
-                    _ => rig::completion::Message::user(msg.content.clone()),        // fallback
+                    "assistant" if !msg.tool_calls.is_empty() => {
+                        assistant_tool_message(&msg.content.text(), &msg.tool_calls)
+                    }
+                    "assistant" => Message::assistant(msg.content.text()),
+                    "system" => continue,
+                    "tool" => {
+                        let id = msg
+                            .tool_call_id
+                            .as_deref()
+                            .ok_or("Tool message without a tool_call_id")?;
+                        let result = tool_result(id, None, msg.content.text());
+                        if let Some(Message::User { content }) = messages.last_mut() {
+                            if content
+                                .iter()
+                                .all(|item| matches!(item, UserContent::ToolResult(_)))
+                            {
+                                content.push(result);
+                                continue;
+                            }
+                        }
+                        Message::User {
+                            content: OneOrMany::one(result),
+                        }
+                    }
+                    _ => user_message(&msg.content, &[], vision)?,
                 };
                 messages.push(rig_message);
             }
         }
 
-        messages
+        Ok(messages)
     }
 
+    /// Converts the history and the prompt with its attachments into rig messages.
+    /// A request without a prompt whose history ends with tool results continues after
+    /// them: the results are sent as the prompt.
+    fn convert_request_messages(
+        &self,
+        request: &ChatRequest,
+    ) -> Result<(Message, Vec<Message>), String> {
+        let vision = request.supports_vision.unwrap_or(true);
+        let mut history = self.convert_to_rig_chat_history(&request.chat_history, vision)?;
+        let continues_after_tools = request.prompt.is_empty()
+            && request.attachments.is_empty()
+            && request
+                .chat_history
+                .as_ref()
+                .and_then(|history| history.last())
+                .is_some_and(|message| message.role == "tool");
+        if continues_after_tools {
+            if let Some(prompt) = history.pop() {
+                return Ok((prompt, history));
+            }
+        }
+        let prompt = user_message(
+            &ChatContent::Text(request.prompt.clone()),
+            &request.attachments,
+            vision,
+        )?;
+        Ok((prompt, history))
+    }
+
+    /// Fits the history into the model's context window using the request's strategy,
+    /// summarizing older turns with the same model when the strategy asks for it.
+    /// Returns `None` for requests without history.
+    async fn fit_context(
+        &self,
+        request: &mut ChatRequest,
+        cancel_token: &CancellationToken,
+    ) -> Option<ContextReport> {
+        let history = request.chat_history.take()?;
+        let options = request.context.clone().unwrap_or_default();
+        let window = options
+            .context_window
+            .unwrap_or_else(|| context_window(&request.model));
+        let reserved = request
+            .params
+            .max_tokens
+            .unwrap_or(DEFAULT_RESERVED_OUTPUT_TOKENS);
+        let fixed = request
+            .instructions
+            .as_deref()
+            .map_or(0, estimate_text_tokens)
+            + estimate_text_tokens(&request.prompt)
+            + request
+                .attachments
+                .iter()
+                .map(estimate_part_tokens)
+                .sum::<u64>()
+            + MESSAGE_OVERHEAD_TOKENS;
+        let budget = window.saturating_sub(reserved + fixed);
+
+        let mut plan = plan_context(history, budget, &options.strategy);
+        let mut summarized = 0;
+        if !plan.summarize.is_empty() {
+            match self
+                .summarize_history(request, &plan.summarize, cancel_token)
+                .await
+            {
+                Ok(summary) => {
+                    summarized = plan.summarize.len();
+                    plan.keep.insert(
+                        0,
+                        ChatMessage::new_system(format!(
+                            "Summary of the earlier conversation:\n{}",
+                            summary
+                        )),
+                    );
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Failed to summarize chat history, dropping {} messages instead: {}",
+                        plan.summarize.len(),
+                        e
+                    );
+                    plan.dropped += plan.summarize.len();
+                }
+            }
+        }
+
+        let estimated_tokens = fixed + plan.keep.iter().map(estimate_message_tokens).sum::<u64>();
+        request.chat_history = Some(plan.keep);
+        Some(ContextReport {
+            strategy: options.strategy.name().to_string(),
+            context_window: window,
+            estimated_tokens,
+            dropped: plan.dropped,
+            summarized,
+        })
+    }
+
+    /// Asks the request's model for a summary of `messages`
+    async fn summarize_history(
+        &self,
+        request: &ChatRequest,
+        messages: &[ChatMessage],
+        cancel_token: &CancellationToken,
+    ) -> Result<String, String> {
+        let summary_request = ChatRequest {
+            prompt: transcript(messages),
+            provider: request.provider.clone(),
+            model: request.model.clone(),
+            stream_id: None,
+            chat_history: None,
+            attachments: Vec::new(),
+            supports_vision: Some(false),
+            instructions: Some(SUMMARY_INSTRUCTIONS.to_string()),
+            params: GenerationParams {
+                max_tokens: Some(SUMMARY_MAX_TOKENS),
+                ..Default::default()
+            },
+            provider_config: request.provider_config.clone(),
+            context: None,
+            retry: None,
+            fallbacks: Vec::new(),
+            response_format: None,
+            validation_retries: None,
+            tools: Vec::new(),
+            tool_choice: None,
+        };
+        let (prompt, history) = self.convert_request_messages(&summary_request)?;
+        let stream = self
+            .open_stream(&summary_request, prompt, history, Vec::new())
+            .await?;
+        let step = drain_stream("summary", stream, cancel_token, &|_| {}).await?;
+        if step.cancelled {
+            return Err("Summary cancelled".to_string());
+        }
+        Ok(step.content)
+    }
+
+    /// Opens a completion stream for one model turn, offering `tools` to the model
+    async fn open_stream(
+        &self,
+        request: &ChatRequest,
+        prompt: Message,
+        history: Vec<Message>,
+        tools: Vec<ToolDefinition>,
+    ) -> Result<ChunkStream, String> {
+        if request.provider == MOCK_PROVIDER {
+            return mock_stream(&request.model, &prompt, &history);
+        }
+        let mut builder = match &request.provider_config {
+            Some(config) => build_agent(&self.client, &request.provider, &request.model, config)?,
+            None => self
+                .client
+                .agent(&request.provider, &request.model)
+                .map_err(|e| e.to_string())?,
+        };
+        let format = request.response_format.as_ref();
+        let preamble = build_preamble(&request.instructions, &request.chat_history);
+        let preamble = match (preamble, format.and_then(format_instructions)) {
+            (Some(preamble), Some(format)) => Some(format!("{}\n\n{}", preamble, format)),
+            (preamble, format) => preamble.or(format),
+        };
+        if let Some(preamble) = preamble {
+            builder = builder.preamble(&preamble);
+        }
+        if let Some(temperature) = request.params.temperature {
+            builder = builder.temperature(temperature);
+        }
+        if let Some(max_tokens) = request.params.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        let kind = request
+            .provider_config
+            .as_ref()
+            .map_or(request.provider.as_str(), |config| {
+                config.kind(&request.provider)
+            });
+        let mut params = build_additional_params(&request.provider, &request.params);
+        if let Some(format) = format {
+            if let Some(format_params) = response_format_params(kind, format) {
+                params = Some(merge_params(params, format_params));
+            }
+        }
+        if let Some(choice) = request.tool_choice.as_ref().filter(|_| !tools.is_empty()) {
+            if let Some(choice_params) = tool_choice_params(kind, choice) {
+                params = Some(merge_params(params, choice_params));
+            }
+        }
+        if let Some(params) = params {
+            builder = builder.additional_params(params);
+        }
+        let agent = builder.build();
+        let response = agent
+            .stream_completion(prompt, history)
+            .await
+            .map_err(|e| e.to_string())?
+            .tools(tools)
+            .stream()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(response.map(into_stream_chunk).boxed())
+    }
+
+    /// Streams a chat completion, invoking `emit_callback` for every event.
+    /// Stops early when `cancel_token` is cancelled: the rig stream (and with it the
+    /// provider connection) is dropped and a final `cancelled` event is emitted.
     pub async fn stream_chat(
         &self,
-        request: ChatRequest,
-        emit_callback: impl Fn(ChatStreamEvent) + Send + 'static,
+        mut request: ChatRequest,
+        cancel_token: CancellationToken,
+        emit_callback: impl Fn(ChatStreamEvent) + Send + Sync + 'static,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
         let stream_id = request
             .stream_id
+            .clone()
             .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
         let callback = Box::new(emit_callback);
+        let Some(_permit) = self.acquire_slot(&cancel_token).await else {
+            callback(ChatStreamEvent::new(
+                &stream_id,
+                "cancelled",
+                String::new(),
+                true,
+            ));
+            return Ok(stream_id);
+        };
+        let started = Instant::now();
 
-        let chat_history = self.convert_to_rig_chat_history(&request.chat_history);
+        let context = self.fit_context(&mut request, &cancel_token).await;
+        let (prompt, chat_history) = self.convert_request_messages(&request)?;
+
+        // Send initial event
+        callback(
+            ChatStreamEvent::new(&stream_id, "start", String::new(), false).with_context(context),
+        );
 
-        let mut stream = self
-            .client
-            .stream_chat(
-                &request.provider,
-                &request.model,
-                request.prompt,
+        let (step, structured) = match self
+            .run_structured_turn(
+                &mut request,
+                prompt,
                 chat_history,
+                &stream_id,
+                &cancel_token,
+                &*callback,
             )
-            .await?;
-
-        // Send initial event
-        callback(ChatStreamEvent {
-            stream_id: stream_id.clone(),
-            content: String::new(),
-            event_type: "start".to_string(),
-            is_final: false,
-        });
-
-        let mut full_content = String::new();
-
-        while let Some(chunk) = stream.next().await {
-            match chunk {
-                Ok(rig::streaming::StreamedAssistantContent::Text(text)) => {
-                    full_content.push_str(&text.text);
-                    callback(ChatStreamEvent {
-                        stream_id: stream_id.clone(),
-                        content: text.text,
-                        event_type: "text".to_string(),
-                        is_final: false,
-                    });
-                }
-                Ok(rig::streaming::StreamedAssistantContent::Reasoning(reasoning)) => {
-                    let reasoning_text = reasoning.reasoning.join("");
-                    callback(ChatStreamEvent {
-                        stream_id: stream_id.clone(),
-                        content: reasoning_text,
-                        event_type: "reasoning".to_string(),
-                        is_final: false,
-                    });
-                }
-                Ok(rig::streaming::StreamedAssistantContent::ToolCall(tool_call)) => {
-                    let tool_info = serde_json::to_string(&tool_call)
-                        .unwrap_or_else(|_| "Unknown tool call".to_string());
-                    callback(ChatStreamEvent {
-                        stream_id: stream_id.clone(),
-                        content: tool_info,
-                        event_type: "tool_call".to_string(),
-                        is_final: false,
-                    });
-                }
-                Ok(rig::streaming::StreamedAssistantContent::Final(_)) => {
-                    callback(ChatStreamEvent {
-                        stream_id: stream_id.clone(),
-                        content: String::new(),
-                        event_type: "complete".to_string(),
-                        is_final: true,
-                    });
-                    break;
-                }
-                Err(e) => {
-                    callback(ChatStreamEvent {
-                        stream_id: stream_id.clone(),
-                        content: e.to_string(),
-                        event_type: "error".to_string(),
-                        is_final: true,
-                    });
-                    return Err(e.into());
-                }
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => {
+                callback(ChatStreamEvent::new(&stream_id, "error", e.clone(), true));
+                return Err(e.into());
             }
+        };
+
+        if step.cancelled {
+            log::info!("Chat stream {} cancelled", stream_id);
+            callback(ChatStreamEvent::new(
+                &stream_id,
+                "cancelled",
+                String::new(),
+                true,
+            ));
+        } else {
+            let metadata = completion_metadata(
+                step.usage,
+                finish_reason(&step.tool_calls, step.usage, request.params.max_tokens),
+                started,
+                step.first_token_at,
+                Instant::now(),
+            );
+            let metadata = CompletionMetadata {
+                structured,
+                ..metadata.answered_by(&request)
+            };
+            callback(
+                ChatStreamEvent::new(&stream_id, "complete", String::new(), true)
+                    .with_metadata(metadata),
+            );
         }
 
         Ok(stream_id)
@@ -153,92 +614,248 @@ impl ChatService {
 
     pub async fn chat_non_streaming(
         &self,
-        request: ChatRequest,
+        mut request: ChatRequest,
     ) -> Result<ChatResponse, Box<dyn std::error::Error + Send + Sync>> {
         let stream_id = request
             .stream_id
+            .clone()
             .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let _permit = self
+            .acquire_slot(&CancellationToken::new())
+            .await
+            .ok_or("Chat service is shut down")?;
+        let started = Instant::now();
 
-        let chat_history = self.convert_to_rig_chat_history(&request.chat_history);
+        self.fit_context(&mut request, &CancellationToken::new())
+            .await;
+        let (prompt, chat_history) = self.convert_request_messages(&request)?;
 
-        let mut stream = self
-            .client
-            .stream_chat(
-                &request.provider,
-                &request.model,
-                request.prompt,
+        let (step, structured) = self
+            .run_structured_turn(
+                &mut request,
+                prompt,
                 chat_history,
+                &stream_id,
+                &CancellationToken::new(),
+                &|_| {},
             )
             .await?;
 
-        let mut full_content = String::new();
-
-        while let Some(chunk) = stream.next().await {
-            match chunk {
-                Ok(rig::streaming::StreamedAssistantContent::Text(text)) => {
-                    full_content.push_str(&text.text);
-                }
-                Ok(rig::streaming::StreamedAssistantContent::Final(_)) => {
-                    break;
-                }
-                Err(e) => {
-                    return Err(e.into());
-                }
-                _ => {}
-            }
-        }
+        let metadata = completion_metadata(
+            step.usage,
+            finish_reason(&step.tool_calls, step.usage, request.params.max_tokens),
+            started,
+            step.first_token_at,
+            Instant::now(),
+        );
 
         Ok(ChatResponse {
             stream_id,
-            content: full_content,
+            content: step.content,
             status: "completed".to_string(),
+            metadata: Some(metadata.answered_by(&request)),
+            structured,
+            tool_calls: step.tool_calls,
         })
     }
 }
 
+/// Converts a rig stream item into a `StreamChunk`
+fn into_stream_chunk<R: GetTokenUsage>(
+    chunk: Result<StreamedAssistantContent<R>, rig::completion::CompletionError>,
+) -> Result<StreamChunk, String> {
+    match chunk {
+        Ok(StreamedAssistantContent::Text(text)) => Ok(StreamChunk::Text(text.text)),
+        Ok(StreamedAssistantContent::Reasoning(reasoning)) => {
+            Ok(StreamChunk::Reasoning(reasoning.reasoning.join("")))
+        }
+        Ok(StreamedAssistantContent::ToolCall(tool_call)) => {
+            Ok(StreamChunk::ToolCall(ToolCallInfo {
+                id: tool_call.id,
+                call_id: tool_call.call_id,
+                name: tool_call.function.name,
+                arguments: tool_call.function.arguments,
+            }))
+        }
+        Ok(StreamedAssistantContent::Final(response)) => {
+            Ok(StreamChunk::Final(response.token_usage().map(|usage| {
+                ChatUsage {
+                    prompt_tokens: usage.input_tokens,
+                    completion_tokens: usage.output_tokens,
+                    total_tokens: usage.total_tokens,
+                }
+            })))
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Consumes one model turn, emitting `text`, `reasoning` and `tool_call` events.
+/// Returns when the stream ends, fails, or `cancel_token` is cancelled.
+async fn drain_stream(
+    stream_id: &str,
+    mut stream: ChunkStream,
+    cancel_token: &CancellationToken,
+    callback: &(dyn Fn(ChatStreamEvent) + Send + Sync),
+) -> Result<StepOutput, String> {
+    let mut step = StepOutput::default();
+
+    loop {
+        let chunk = tokio::select! {
+            _ = cancel_token.cancelled() => {
+                // Dropping the stream closes the underlying HTTP connection
+                drop(stream);
+                step.cancelled = true;
+                return Ok(step);
+            }
+            chunk = stream.next() => chunk,
+        };
+
+        if matches!(
+            chunk,
+            Some(Ok(StreamChunk::Text(_)
+                | StreamChunk::Reasoning(_)
+                | StreamChunk::ToolCall(_)))
+        ) && step.first_token_at.is_none()
+        {
+            step.first_token_at = Some(Instant::now());
+        }
+
+        match chunk {
+            Some(Ok(StreamChunk::Text(text))) => {
+                step.content.push_str(&text);
+                callback(ChatStreamEvent::new(stream_id, "text", text, false));
+            }
+            Some(Ok(StreamChunk::Reasoning(reasoning))) => {
+                callback(ChatStreamEvent::new(
+                    stream_id,
+                    "reasoning",
+                    reasoning,
+                    false,
+                ));
+            }
+            Some(Ok(StreamChunk::ToolCall(tool_call))) => {
+                let tool_info = serde_json::to_string(&tool_call)
+                    .unwrap_or_else(|_| "Unknown tool call".to_string());
+                callback(ChatStreamEvent::new(
+                    stream_id,
+                    "tool_call",
+                    tool_info,
+                    false,
+                ));
+                step.tool_calls.push(tool_call);
+            }
+            Some(Ok(StreamChunk::Final(usage))) => {
+                step.usage = usage;
+                return Ok(step);
+            }
+            None => return Ok(step),
+            Some(Err(e)) => return Err(e),
+        }
+    }
+}
+
 impl Default for ChatService {
     fn default() -> Self {
         Self::new()
     }
 }
 
+impl GenerationParams {
+    /// Fills the parameters left unset with the ones from `fallback`
+    pub fn or(self, fallback: GenerationParams) -> Self {
+        Self {
+            temperature: self.temperature.or(fallback.temperature),
+            top_p: self.top_p.or(fallback.top_p),
+            max_tokens: self.max_tokens.or(fallback.max_tokens),
+            stop: self.stop.or(fallback.stop),
+            seed: self.seed.or(fallback.seed),
+            extra_params: self.extra_params.or(fallback.extra_params),
+        }
+    }
+}
+
+impl ChatStreamEvent {
+    pub fn new(stream_id: &str, event_type: &str, content: String, is_final: bool) -> Self {
+        Self {
+            stream_id: stream_id.to_string(),
+            content,
+            event_type: event_type.to_string(),
+            is_final,
+            seq: 0,
+            metadata: None,
+            context: None,
+            compare: None,
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: CompletionMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn with_context(mut self, context: Option<ContextReport>) -> Self {
+        self.context = context;
+        self
+    }
+
+    pub fn with_compare(mut self, branch: CompareBranch) -> Self {
+        self.compare = Some(branch);
+        self
+    }
+}
+
+impl std::ops::AddAssign for ChatUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
 impl ChatMessage {
-    pub fn new_user(content: String) -> Self {
+    pub fn new_user(content: impl Into<ChatContent>) -> Self {
         Self {
             role: "user".to_string(),
-            content,
+            content: content.into(),
             timestamp: Some(
                 std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap()
                     .as_secs(),
             ),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
         }
     }
 
     pub fn new_assistant(content: String) -> Self {
         Self {
             role: "assistant".to_string(),
-            content,
+            content: content.into(),
             timestamp: Some(
                 std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap()
                     .as_secs(),
             ),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
         }
     }
 
     pub fn new_system(content: String) -> Self {
         Self {
             role: "system".to_string(),
-            content,
+            content: content.into(),
             timestamp: Some(
                 std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap()
                     .as_secs(),
             ),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
         }
     }
 }
@@ -0,0 +1,260 @@
+//! Provider credentials and endpoints configured in the app. Requests with a
+//! `ProviderConfig` get a client built from it instead of the environment variables
+//! `DynClientBuilder` reads.
+
+use rig::agent::AgentBuilder;
+use rig::client::builder::DynClientBuilder;
+use rig::client::completion::{CompletionClientDyn, CompletionModelHandle};
+use rig::client::embeddings::EmbeddingsClientDyn;
+use rig::embeddings::embedding::EmbeddingModelDyn;
+use rig::providers::{anthropic, openai};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use tauri_plugin_http::reqwest;
+
+use super::constants::{OPENAI_COMPATIBLE_PROVIDER, REMOTE_PROVIDERS};
+use crate::core::downloads::helpers::validate_proxy_config;
+use crate::core::downloads::models::ProxyConfig;
+
+/// Credentials and connection settings for a provider.
+/// `Debug` redacts the API key, header values and proxy password.
+#[derive(Deserialize, Clone, Default)]
+pub struct ProviderConfig {
+    /// Provider implementation to use, defaults to the name the config is registered under.
+    /// `openai-compatible` talks the OpenAI API to `base_url`.
+    pub kind: Option<String>,
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub proxy: Option<ProxyConfig>,
+    /// Accepts invalid TLS certificates, e.g. of a self-signed server on the local network.
+    /// `proxy.ignore_ssl` is honoured too.
+    pub ignore_ssl: Option<bool>,
+}
+
+impl fmt::Debug for ProviderConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProviderConfig")
+            .field("kind", &self.kind)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("base_url", &self.base_url)
+            .field("headers", &self.headers.keys().collect::<Vec<_>>())
+            .field("proxy", &self.proxy.as_ref().map(|p| &p.url))
+            .field("ignore_ssl", &self.ignore_ssl)
+            .finish()
+    }
+}
+
+/// What `list_provider_configs` returns: the config without its secrets
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ProviderConfigSummary {
+    pub name: String,
+    pub kind: String,
+    pub has_api_key: bool,
+    pub base_url: Option<String>,
+    pub headers: Vec<String>,
+    pub proxy_url: Option<String>,
+    pub ignore_ssl: bool,
+}
+
+impl ProviderConfig {
+    /// The provider implementation, `kind` or else the registered name
+    pub fn kind<'a>(&'a self, name: &'a str) -> &'a str {
+        self.kind.as_deref().unwrap_or(name)
+    }
+
+    pub fn summary(&self, name: &str) -> ProviderConfigSummary {
+        let mut headers: Vec<String> = self.headers.keys().cloned().collect();
+        headers.sort();
+        ProviderConfigSummary {
+            name: name.to_string(),
+            kind: self.kind(name).to_string(),
+            has_api_key: self.api_key.as_deref().is_some_and(|k| !k.is_empty()),
+            base_url: self.base_url.clone(),
+            headers,
+            proxy_url: self.proxy.as_ref().map(|p| p.url.clone()),
+            ignore_ssl: self.ignores_ssl(),
+        }
+    }
+
+    /// Whether certificate verification is turned off, by the config or its proxy
+    pub fn ignores_ssl(&self) -> bool {
+        self.ignore_ssl
+            .or_else(|| self.proxy.as_ref().and_then(|p| p.ignore_ssl))
+            .unwrap_or(false)
+    }
+
+    /// Whether the config only carries an API key, which every provider supports
+    fn is_key_only(&self) -> bool {
+        self.base_url.is_none()
+            && self.headers.is_empty()
+            && self.proxy.is_none()
+            && !self.ignore_ssl.unwrap_or(false)
+    }
+}
+
+/// Checks a config before it is registered under `name`
+pub fn validate_provider_config(name: &str, config: &ProviderConfig) -> Result<(), String> {
+    if name.is_empty() || name.contains('/') {
+        return Err(format!("Invalid provider name '{}'", name));
+    }
+
+    let kind = config.kind(name);
+    if kind != OPENAI_COMPATIBLE_PROVIDER && !REMOTE_PROVIDERS.contains(&kind) {
+        return Err(format!("Unsupported provider kind '{}'", kind));
+    }
+    if kind == OPENAI_COMPATIBLE_PROVIDER && config.base_url.is_none() {
+        return Err(format!("Provider '{}' requires a base_url", name));
+    }
+    if !matches!(kind, "openai" | "anthropic" | OPENAI_COMPATIBLE_PROVIDER) && !config.is_key_only()
+    {
+        return Err(format!(
+            "Provider '{}' only supports an API key, not a base URL, headers, proxy or ignore_ssl",
+            kind
+        ));
+    }
+    if let Some(base_url) = &config.base_url {
+        url::Url::parse(base_url).map_err(|e| format!("Invalid base_url: {}", e))?;
+    }
+    if let Some(proxy) = &config.proxy {
+        validate_proxy_config(proxy)?;
+    }
+
+    Ok(())
+}
+
+/// Splits a `provider/model` id whose provider is registered in `configs`
+pub fn parse_configured_model(
+    model_id: &str,
+    configs: &HashMap<String, ProviderConfig>,
+) -> Option<(String, String)> {
+    let (provider, model) = model_id.split_once('/')?;
+    if model.is_empty() || !configs.contains_key(provider) {
+        return None;
+    }
+    Some((provider.to_string(), model.to_string()))
+}
+
+/// Builds the HTTP client carrying the config's extra headers, proxy and TLS settings
+pub fn build_http_client(config: &ProviderConfig) -> Result<reqwest::Client, String> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in &config.headers {
+        let header_name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid header name '{}'", name))?;
+        // The value is left out of the error, it may hold a credential
+        let header_value = reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| format!("Invalid value for header '{}'", name))?;
+        headers.insert(header_name, header_value);
+    }
+
+    let mut builder = reqwest::Client::builder().default_headers(headers);
+    if let Some(proxy_config) = &config.proxy {
+        validate_proxy_config(proxy_config)?;
+        let mut proxy =
+            reqwest::Proxy::all(&proxy_config.url).map_err(|e| format!("Invalid proxy: {}", e))?;
+        if let (Some(username), Some(password)) = (&proxy_config.username, &proxy_config.password) {
+            proxy = proxy.basic_auth(username, password);
+        }
+        if let Some(no_proxy) = &proxy_config.no_proxy {
+            proxy = proxy.no_proxy(reqwest::NoProxy::from_string(&no_proxy.join(",")));
+        }
+        builder = builder.proxy(proxy);
+    }
+    if config.ignores_ssl() {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))
+}
+
+/// Builds an agent for `model` from a provider config registered under `name`
+pub fn build_agent<'a>(
+    client: &'a DynClientBuilder,
+    name: &str,
+    model: &str,
+    config: &ProviderConfig,
+) -> Result<AgentBuilder<CompletionModelHandle<'a>>, String> {
+    let kind = config.kind(name);
+    let api_key = config.api_key.clone().unwrap_or_default();
+
+    match kind {
+        "openai" | OPENAI_COMPATIBLE_PROVIDER => {
+            let mut builder =
+                openai::Client::builder(&api_key).custom_client(build_http_client(config)?);
+            if let Some(base_url) = &config.base_url {
+                builder = builder.base_url(base_url);
+            }
+            let provider_client = builder
+                .build()
+                .map_err(|e| format!("Failed to create {} client: {}", name, e))?;
+            Ok(CompletionClientDyn::agent(&provider_client, model))
+        }
+        "anthropic" => {
+            let mut builder =
+                anthropic::ClientBuilder::new(&api_key).custom_client(build_http_client(config)?);
+            if let Some(base_url) = &config.base_url {
+                builder = builder.base_url(base_url);
+            }
+            let provider_client = builder
+                .build()
+                .map_err(|e| format!("Failed to create {} client: {}", name, e))?;
+            Ok(CompletionClientDyn::agent(&provider_client, model))
+        }
+        _ if config.is_key_only() => client
+            .agent_with_api_key_val(kind, model, api_key)
+            .map_err(|e| e.to_string()),
+        _ => Err(format!(
+            "Provider '{}' only supports an API key, not a base URL, headers, proxy or ignore_ssl",
+            kind
+        )),
+    }
+}
+
+/// Builds an embedding model for `model` of provider `name`, from its config or else
+/// the provider's environment variables
+pub fn build_embedding_model<'a>(
+    client: &'a DynClientBuilder,
+    name: &str,
+    model: &str,
+    config: Option<&ProviderConfig>,
+) -> Result<Box<dyn EmbeddingModelDyn + 'a>, String> {
+    let Some(config) = config else {
+        return client.embeddings(name, model).map_err(|e| e.to_string());
+    };
+    let kind = config.kind(name);
+    let api_key = config.api_key.clone().unwrap_or_default();
+
+    match kind {
+        "openai" | OPENAI_COMPATIBLE_PROVIDER => {
+            let mut builder =
+                openai::Client::builder(&api_key).custom_client(build_http_client(config)?);
+            if let Some(base_url) = &config.base_url {
+                builder = builder.base_url(base_url);
+            }
+            let provider_client = builder
+                .build()
+                .map_err(|e| format!("Failed to create {} client: {}", name, e))?;
+            Ok(EmbeddingsClientDyn::embedding_model(
+                &provider_client,
+                model,
+            ))
+        }
+        _ if config.is_key_only() => {
+            let provider_client = client
+                .build_val(kind, api_key.into())
+                .map_err(|e| e.to_string())?;
+            let embeddings = provider_client
+                .as_embeddings()
+                .ok_or_else(|| format!("Provider '{}' does not offer embeddings", kind))?;
+            Ok(embeddings.embedding_model(model))
+        }
+        _ => Err(format!(
+            "Provider '{}' only supports an API key, not a base URL, headers, proxy or ignore_ssl",
+            kind
+        )),
+    }
+}
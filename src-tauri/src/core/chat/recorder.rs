@@ -0,0 +1,310 @@
+//! Persists a chat turn into a thread's messages.jsonl: the user message, then the
+//! assistant reply, which is written while it streams so a reload does not lose it.
+
+use serde_json::{json, Map, Value};
+use std::time::{Duration, Instant};
+use tauri::Runtime;
+
+use super::constants::THREAD_FLUSH_INTERVAL_MS;
+use super::content::{ChatContent, ContentPart, MediaSource};
+use super::{ChatMessage, ChatStreamEvent};
+use crate::core::threads::helpers::{
+    append_message, get_lock_for_thread, merge_metadata_object, read_messages_from_file,
+    update_message,
+};
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// A text entry of a thread message's `content`
+pub fn thread_text_content(text: &str) -> Value {
+    json!({ "type": "text", "text": { "value": text, "annotations": [] } })
+}
+
+fn thread_image_content(source: &MediaSource) -> Option<Value> {
+    let url = match (&source.url, &source.path, &source.data) {
+        (Some(url), _, _) => url.clone(),
+        (None, Some(path), _) => path.clone(),
+        (None, None, Some(data)) => format!(
+            "data:{};base64,{}",
+            source.media_type.as_deref().unwrap_or("image/png"),
+            data
+        ),
+        (None, None, None) => return None,
+    };
+    Some(json!({ "type": "image_url", "image_url": { "url": url } }))
+}
+
+/// Builds the thread message for a prompt. Images become `image_url` content,
+/// documents are kept in `metadata.attachments`.
+pub fn user_thread_message(thread_id: &str, prompt: &str, attachments: &[ContentPart]) -> Value {
+    let mut content = vec![thread_text_content(prompt)];
+    let mut documents = Vec::new();
+    for part in attachments {
+        match part {
+            ContentPart::Text { text } => content.push(thread_text_content(text)),
+            ContentPart::Image(source) => content.extend(thread_image_content(source)),
+            ContentPart::Document(_) => documents.push(part.clone()),
+        }
+    }
+
+    let metadata = if documents.is_empty() {
+        json!({})
+    } else {
+        json!({ "attachments": documents })
+    };
+
+    let now = now_ms();
+    json!({
+        "id": uuid::Uuid::new_v4().to_string(),
+        "object": "thread.message",
+        "thread_id": thread_id,
+        "role": "user",
+        "content": content,
+        "status": "ready",
+        "created_at": now,
+        "completed_at": now,
+        "metadata": metadata,
+    })
+}
+
+/// Converts one stored thread message into a chat message.
+/// Messages that failed or are still being written are skipped.
+fn chat_message_from_thread(message: &Value) -> Option<ChatMessage> {
+    let role = message.get("role")?.as_str()?;
+    if !matches!(role, "user" | "assistant" | "system") {
+        return None;
+    }
+    let status = message.get("status").and_then(|s| s.as_str());
+    if matches!(status, Some("error" | "in_progress")) {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    for item in message.get("content").and_then(|c| c.as_array())? {
+        match item.get("type").and_then(|t| t.as_str()) {
+            Some("text") => {
+                if let Some(text) = item.pointer("/text/value").and_then(|v| v.as_str()) {
+                    parts.push(ContentPart::Text {
+                        text: text.to_string(),
+                    });
+                }
+            }
+            Some("image_url") => {
+                if let Some(url) = item.pointer("/image_url/url").and_then(|v| v.as_str()) {
+                    let is_remote = ["http://", "https://", "data:"]
+                        .iter()
+                        .any(|scheme| url.starts_with(scheme));
+                    parts.push(ContentPart::Image(MediaSource {
+                        url: is_remote.then(|| url.to_string()),
+                        path: (!is_remote).then(|| url.to_string()),
+                        ..Default::default()
+                    }));
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(attachments) = message.pointer("/metadata/attachments") {
+        let documents: Vec<ContentPart> =
+            serde_json::from_value(attachments.clone()).unwrap_or_default();
+        parts.extend(documents);
+    }
+
+    let content = match parts.as_slice() {
+        [ContentPart::Text { text }] => ChatContent::Text(text.clone()),
+        _ => ChatContent::Parts(parts),
+    };
+    Some(ChatMessage {
+        role: role.to_string(),
+        content,
+        timestamp: message
+            .get("created_at")
+            .and_then(|v| v.as_u64())
+            .map(|ms| ms / 1000),
+        tool_calls: Vec::new(),
+        tool_call_id: None,
+    })
+}
+
+/// Converts the stored messages of a thread into chat history
+pub fn history_from_thread(messages: &[Value]) -> Vec<ChatMessage> {
+    messages
+        .iter()
+        .filter_map(chat_message_from_thread)
+        .collect()
+}
+
+/// Reads a thread's messages as chat history
+pub async fn load_thread_history<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    thread_id: &str,
+) -> Result<Vec<ChatMessage>, String> {
+    let lock = get_lock_for_thread(thread_id).await;
+    let _guard = lock.lock().await;
+    let messages = read_messages_from_file(app_handle, thread_id)?;
+    Ok(history_from_thread(&messages))
+}
+
+/// Writes the assistant reply of one chat turn into a thread.
+/// Text is written at most every `THREAD_FLUSH_INTERVAL_MS`; the final event sets the
+/// status to `ready`, `stopped` or `error`.
+pub struct ThreadRecorder<R: Runtime> {
+    app_handle: tauri::AppHandle<R>,
+    thread_id: String,
+    message_id: String,
+    content: String,
+    reasoning: String,
+    tool_calls: Vec<Value>,
+    last_flush: Instant,
+    finished: bool,
+}
+
+impl<R: Runtime> ThreadRecorder<R> {
+    /// Writes the user message and an `in_progress` assistant message with id
+    /// `message_id` (a new id when unset).
+    pub async fn start(
+        app_handle: tauri::AppHandle<R>,
+        thread_id: &str,
+        message_id: Option<String>,
+        assistant_id: Option<String>,
+        prompt: &str,
+        attachments: &[ContentPart],
+    ) -> Result<Self, String> {
+        let user_message = user_thread_message(thread_id, prompt, attachments);
+        append_message(app_handle.clone(), thread_id, &user_message).await?;
+
+        let message_id = message_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let assistant_message = json!({
+            "id": message_id,
+            "object": "thread.message",
+            "thread_id": thread_id,
+            "assistant_id": assistant_id,
+            "role": "assistant",
+            "content": [],
+            "status": "in_progress",
+            "created_at": now_ms(),
+            "completed_at": 0,
+            "metadata": {},
+        });
+        append_message(app_handle.clone(), thread_id, &assistant_message).await?;
+
+        Ok(Self {
+            app_handle,
+            thread_id: thread_id.to_string(),
+            message_id,
+            content: String::new(),
+            reasoning: String::new(),
+            tool_calls: Vec::new(),
+            last_flush: Instant::now(),
+            finished: false,
+        })
+    }
+
+    pub fn message_id(&self) -> &str {
+        &self.message_id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Records a stream event
+    pub async fn record(&mut self, event: &ChatStreamEvent) -> Result<(), String> {
+        match event.event_type.as_str() {
+            "text" => {
+                self.content.push_str(&event.content);
+                if self.last_flush.elapsed() >= Duration::from_millis(THREAD_FLUSH_INTERVAL_MS) {
+                    self.write("in_progress", Map::new()).await?;
+                }
+            }
+            "reasoning" => self.reasoning.push_str(&event.content),
+            // The reply failed the response format and is requested again
+            "validation" => self.content.clear(),
+            "tool_call" => {
+                if let Ok(tool_call) = serde_json::from_str::<Value>(&event.content) {
+                    self.tool_calls.push(tool_call);
+                }
+                self.write("in_progress", Map::new()).await?;
+            }
+            "tool_result" => {
+                if let Ok(result) = serde_json::from_str::<Value>(&event.content) {
+                    let call = self
+                        .tool_calls
+                        .iter_mut()
+                        .find(|call| call.get("id") == result.get("id"));
+                    if let Some(Value::Object(call)) = call {
+                        call.insert("result".to_string(), result["content"].clone());
+                        call.insert("is_error".to_string(), result["is_error"].clone());
+                    }
+                }
+                self.write("in_progress", Map::new()).await?;
+            }
+            "complete" => {
+                let metadata = match event.metadata.as_ref().map(serde_json::to_value) {
+                    Some(Ok(Value::Object(metadata))) => metadata,
+                    _ => Map::new(),
+                };
+                self.finish("ready", metadata).await?;
+            }
+            "cancelled" => self.finish("stopped", Map::new()).await?,
+            "error" => self.fail(&event.content).await?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Marks the reply as failed with `error`
+    pub async fn fail(&mut self, error: &str) -> Result<(), String> {
+        let mut metadata = Map::new();
+        metadata.insert("error".to_string(), Value::String(error.to_string()));
+        self.finish("error", metadata).await
+    }
+
+    async fn finish(&mut self, status: &str, metadata: Map<String, Value>) -> Result<(), String> {
+        self.finished = true;
+        self.write(status, metadata).await
+    }
+
+    async fn write(
+        &mut self,
+        status: &str,
+        mut metadata: Map<String, Value>,
+    ) -> Result<(), String> {
+        self.last_flush = Instant::now();
+        if !self.reasoning.is_empty() {
+            metadata.insert("reasoning".to_string(), json!(self.reasoning));
+        }
+        if !self.tool_calls.is_empty() {
+            metadata.insert("tool_calls".to_string(), json!(self.tool_calls));
+        }
+        let content = if self.content.is_empty() {
+            json!([])
+        } else {
+            json!([thread_text_content(&self.content)])
+        };
+        let finished = self.finished;
+
+        update_message(
+            self.app_handle.clone(),
+            &self.thread_id,
+            &self.message_id,
+            |message| {
+                message.insert("content".to_string(), content);
+                message.insert("status".to_string(), json!(status));
+                if finished {
+                    message.insert("completed_at".to_string(), json!(now_ms()));
+                }
+                if status == "error" {
+                    message.insert("error_code".to_string(), json!("unknown"));
+                }
+                merge_metadata_object(message, metadata);
+            },
+        )
+        .await
+    }
+}
@@ -0,0 +1,255 @@
+//! Retries transient provider errors and falls back to other provider/model pairs.
+//! Retries only happen while nothing has been streamed for the turn, fallbacks only
+//! while nothing has been streamed for the whole request, so the client never sees a
+//! reply restart halfway or continue with another model.
+
+use jan_utils::math::calculate_exponential_backoff_delay;
+use rig::completion::{Message, ToolDefinition};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use super::constants::{CHAT_MAX_RETRIES, CHAT_MAX_RETRY_DELAY_MS};
+use super::providers::ProviderConfig;
+use super::{drain_stream, ChatRequest, ChatService, ChatStreamEvent, StepOutput};
+
+/// How often a failed request is retried against the same provider
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RetryPolicy {
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Upper bound for a single wait, including waits asked for by `Retry-After`
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+fn default_max_retries() -> u32 {
+    CHAT_MAX_RETRIES
+}
+
+fn default_max_delay_ms() -> u64 {
+    CHAT_MAX_RETRY_DELAY_MS
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: default_max_retries(),
+            max_delay_ms: default_max_delay_ms(),
+        }
+    }
+}
+
+/// A provider and model to fall back to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatTarget {
+    pub provider: String,
+    pub model: String,
+    /// Resolved from the registered provider configs
+    #[serde(skip)]
+    pub provider_config: Option<ProviderConfig>,
+}
+
+const RETRYABLE_STATUS_CODES: &[u16] = &[408, 429, 500, 502, 503, 504, 529];
+/// Text a provider error puts right before its HTTP status, as in "status: 429",
+/// "HttpError: 500" or reqwest's "HTTP status server error (502 Bad Gateway)"
+const STATUS_MARKERS: &[&str] = &[
+    "status client error (",
+    "status server error (",
+    "status code",
+    "status",
+    "httperror",
+    "http error",
+    "http/1.1",
+    "http/2",
+    "http",
+];
+const RETRYABLE_PHRASES: &[&str] = &[
+    "too many requests",
+    "rate limit",
+    "rate_limit",
+    "overloaded",
+    "service unavailable",
+    "bad gateway",
+    "gateway timeout",
+    "timed out",
+    "connection reset",
+    "connection closed",
+    "error sending request",
+];
+
+/// The HTTP status a provider error reports. rig only reports errors as text, so this
+/// reads a three-digit code following "status" or "HTTP"; numbers anywhere else in the
+/// message (a limit, a model name) are not taken for one.
+pub fn error_status(error: &str) -> Option<u16> {
+    let error = error.to_ascii_lowercase();
+    STATUS_MARKERS.iter().find_map(|marker| {
+        error.match_indices(marker).find_map(|(index, _)| {
+            let rest = error[index + marker.len()..]
+                .trim_start_matches(|c: char| c == ':' || c == '=' || c.is_whitespace());
+            let digits = &rest[..rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len())];
+            if digits.len() != 3 {
+                return None;
+            }
+            digits
+                .parse()
+                .ok()
+                .filter(|status| (100..600).contains(status))
+        })
+    })
+}
+
+/// Whether a provider error looks transient: a 408/429/5xx status, a rate limit or a
+/// dropped connection
+pub fn is_retryable(error: &str) -> bool {
+    let lowercase = error.to_ascii_lowercase();
+    error_status(error).is_some_and(|status| RETRYABLE_STATUS_CODES.contains(&status))
+        || RETRYABLE_PHRASES
+            .iter()
+            .any(|phrase| lowercase.contains(phrase))
+}
+
+/// Reads the wait a provider asked for, from a `Retry-After` value or an
+/// "try again in 20s" hint in the error message
+pub fn retry_after(error: &str) -> Option<Duration> {
+    let error = error.to_ascii_lowercase();
+    ["retry-after", "retry after", "try again in"]
+        .iter()
+        .find_map(|marker| {
+            let rest = &error[error.find(marker)? + marker.len()..];
+            let rest = rest.trim_start_matches(|c: char| c == ':' || c == '"' || c.is_whitespace());
+            let number_len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let value: f64 = rest[..number_len].parse().ok()?;
+            let unit = rest[number_len..].trim_start();
+            let seconds = if unit.starts_with("ms") {
+                value / 1000.0
+            } else {
+                value
+            };
+            Some(Duration::from_secs_f64(seconds))
+        })
+}
+
+/// Wait before retry number `attempt` (1-based): the provider's `Retry-After` when it
+/// is longer than the exponential backoff, capped at `max_delay_ms`
+pub fn retry_delay(policy: &RetryPolicy, attempt: u32, error: &str) -> Duration {
+    let backoff = Duration::from_millis(calculate_exponential_backoff_delay(attempt));
+    let delay = retry_after(error).map_or(backoff, |after| after.max(backoff));
+    delay.min(Duration::from_millis(policy.max_delay_ms))
+}
+
+impl ChatService {
+    /// Runs one model turn with retries and fallbacks, emitting `retry` and `fallback`
+    /// events before each new attempt. When a fallback answers, `request` is switched to
+    /// it so later turns and the completion metadata use it. Once a turn has streamed
+    /// anything the fallbacks are dropped, so later turns of the request only retry.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) async fn run_turn(
+        &self,
+        request: &mut ChatRequest,
+        prompt: Message,
+        history: Vec<Message>,
+        tools: Vec<ToolDefinition>,
+        stream_id: &str,
+        cancel_token: &CancellationToken,
+        callback: &(dyn Fn(ChatStreamEvent) + Send + Sync),
+    ) -> Result<StepOutput, String> {
+        let policy = request.retry.clone().unwrap_or_default();
+        let fallbacks = std::mem::take(&mut request.fallbacks);
+        let mut fallbacks = fallbacks.into_iter();
+        let mut attempt = 0;
+
+        loop {
+            let emitted = AtomicBool::new(false);
+            let tracking = |event: ChatStreamEvent| {
+                emitted.store(true, Ordering::Relaxed);
+                callback(event)
+            };
+
+            let stream = tokio::select! {
+                _ = cancel_token.cancelled() => return Ok(StepOutput::cancelled()),
+                stream = self.open_stream(request, prompt.clone(), history.clone(), tools.clone()) => stream,
+            };
+            let error = match stream {
+                Ok(stream) => {
+                    match drain_stream(stream_id, stream, cancel_token, &tracking).await {
+                        Ok(step) => {
+                            if !emitted.load(Ordering::Relaxed) {
+                                request.fallbacks = fallbacks.collect();
+                            }
+                            return Ok(step);
+                        }
+                        Err(e) if emitted.load(Ordering::Relaxed) => return Err(e),
+                        Err(e) => e,
+                    }
+                }
+                Err(e) => e,
+            };
+
+            if attempt < policy.max_retries && is_retryable(&error) {
+                attempt += 1;
+                let delay = retry_delay(&policy, attempt, &error);
+                log::warn!(
+                    "{}/{} failed, retrying in {:?} ({}/{}): {}",
+                    request.provider,
+                    request.model,
+                    delay,
+                    attempt,
+                    policy.max_retries,
+                    error
+                );
+                callback(ChatStreamEvent::new(
+                    stream_id,
+                    "retry",
+                    json!({
+                        "provider": request.provider,
+                        "model": request.model,
+                        "attempt": attempt,
+                        "delay_ms": delay.as_millis() as u64,
+                        "error": error,
+                    })
+                    .to_string(),
+                    false,
+                ));
+                tokio::select! {
+                    _ = cancel_token.cancelled() => return Ok(StepOutput::cancelled()),
+                    _ = tokio::time::sleep(delay) => {}
+                }
+                continue;
+            }
+
+            let Some(target) = fallbacks.next() else {
+                return Err(error);
+            };
+            log::warn!(
+                "{}/{} failed, falling back to {}/{}: {}",
+                request.provider,
+                request.model,
+                target.provider,
+                target.model,
+                error
+            );
+            callback(ChatStreamEvent::new(
+                stream_id,
+                "fallback",
+                json!({
+                    "provider": target.provider,
+                    "model": target.model,
+                    "error": error,
+                })
+                .to_string(),
+                false,
+            ));
+            request.provider = target.provider;
+            request.model = target.model;
+            request.provider_config = target.provider_config;
+            attempt = 0;
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Running chat streams. Each stream keeps its recent events in a bounded buffer so a
+//! reloaded webview can replay what it missed with `attach_chat_stream`.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use super::ChatStreamEvent;
+
+pub type SharedChatStreams = Arc<Mutex<HashMap<String, ActiveChatStream>>>;
+
+/// Ring buffer of the latest events of a stream
+#[derive(Debug)]
+pub struct ChatStreamBuffer {
+    events: VecDeque<ChatStreamEvent>,
+    capacity: usize,
+    next_seq: u64,
+    /// Final events still expected before the stream is finished
+    open_branches: usize,
+    finished: bool,
+}
+
+impl ChatStreamBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity.min(256)),
+            capacity: capacity.max(1),
+            next_seq: 1,
+            open_branches: 1,
+            finished: false,
+        }
+    }
+
+    /// Numbers the event and stores it, dropping the oldest event when the buffer is full.
+    /// Returns the numbered event.
+    pub fn push(&mut self, mut event: ChatStreamEvent) -> ChatStreamEvent {
+        event.seq = self.next_seq;
+        self.next_seq += 1;
+        if event.is_final {
+            self.open_branches = self.open_branches.saturating_sub(1);
+            self.finished = self.open_branches == 0;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event.clone());
+        event
+    }
+
+    /// The buffered events numbered `from_seq` or later
+    pub fn events_from(&self, from_seq: u64) -> Vec<ChatStreamEvent> {
+        self.events
+            .iter()
+            .filter(|event| event.seq >= from_seq)
+            .cloned()
+            .collect()
+    }
+
+    /// Sequence number of the latest event, 0 before the first one
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    /// Makes the buffer collect the events of `branches` streams, finishing after the
+    /// final event of each
+    pub fn set_branches(&mut self, branches: usize) {
+        self.open_branches = branches.max(1);
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// A registered stream: its cancel token, event buffer and what it is generating
+pub struct ActiveChatStream {
+    pub cancel_token: CancellationToken,
+    pub buffer: Arc<std::sync::Mutex<ChatStreamBuffer>>,
+    pub provider: String,
+    pub model: String,
+    pub thread_id: Option<String>,
+    pub started_at: u64,
+}
+
+/// What `list_active_streams` returns for each stream
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ActiveStreamInfo {
+    pub stream_id: String,
+    pub provider: String,
+    pub model: String,
+    pub thread_id: Option<String>,
+    pub started_at: u64,
+    pub last_seq: u64,
+    pub finished: bool,
+}
+
+impl ActiveChatStream {
+    pub fn new(
+        cancel_token: CancellationToken,
+        capacity: usize,
+        provider: &str,
+        model: &str,
+        thread_id: Option<String>,
+    ) -> Self {
+        Self {
+            cancel_token,
+            buffer: Arc::new(std::sync::Mutex::new(ChatStreamBuffer::new(capacity))),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            thread_id,
+            started_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.buffer.lock().map(|b| b.is_finished()).unwrap_or(true)
+    }
+
+    pub fn info(&self, stream_id: &str) -> ActiveStreamInfo {
+        let (last_seq, finished) = self
+            .buffer
+            .lock()
+            .map(|b| (b.last_seq(), b.is_finished()))
+            .unwrap_or((0, true));
+        ActiveStreamInfo {
+            stream_id: stream_id.to_string(),
+            provider: self.provider.clone(),
+            model: self.model.clone(),
+            thread_id: self.thread_id.clone(),
+            started_at: self.started_at,
+            last_seq,
+            finished,
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Structured output: replies that must be JSON, optionally matching a JSON Schema.
+//! Providers with a JSON mode get the format in the request. Every reply is also checked
+//! here, and an invalid one is sent back to the model with the validation errors.
+
+use rig::completion::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio_util::sync::CancellationToken;
+
+use super::constants::{JSON_MODE_PROVIDERS, JSON_SCHEMA_PROVIDERS, STRUCTURED_OUTPUT_MAX_RETRIES};
+use super::{ChatRequest, ChatService, ChatStreamEvent, StepOutput};
+
+/// Format the reply must have, in the shape of OpenAI's `response_format`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    /// Any JSON object
+    JsonObject,
+    JsonSchema {
+        json_schema: JsonSchemaFormat,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct JsonSchemaFormat {
+    #[serde(default = "default_schema_name")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub schema: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+fn default_schema_name() -> String {
+    "response".to_string()
+}
+
+/// The parsed reply and whether it matched the requested format
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StructuredOutput {
+    /// The reply parsed as JSON, also when it does not match the schema
+    pub value: Option<Value>,
+    pub valid: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    /// Number of replies requested, 1 when the first one was valid
+    pub attempts: u32,
+}
+
+impl ResponseFormat {
+    fn is_json(&self) -> bool {
+        !matches!(self, ResponseFormat::Text)
+    }
+}
+
+/// Request fields that turn on the provider's JSON mode, `None` for providers without one
+pub fn response_format_params(kind: &str, format: &ResponseFormat) -> Option<Value> {
+    if !format.is_json() {
+        return None;
+    }
+    if JSON_SCHEMA_PROVIDERS.contains(&kind) {
+        return Some(json!({ "response_format": format }));
+    }
+    if JSON_MODE_PROVIDERS.contains(&kind) {
+        return Some(json!({ "response_format": { "type": "json_object" } }));
+    }
+    match kind {
+        "gemini" => Some(json!({
+            "generationConfig": { "responseMimeType": "application/json" }
+        })),
+        _ => None,
+    }
+}
+
+/// Merges `extra` into the additional request params, joining nested objects such as
+/// Gemini's `generationConfig`
+pub fn merge_params(params: Option<Value>, extra: Value) -> Value {
+    fn merge(target: &mut Map<String, Value>, extra: Map<String, Value>) {
+        for (key, value) in extra {
+            match (target.get_mut(&key), value) {
+                (Some(Value::Object(existing)), Value::Object(value)) => merge(existing, value),
+                (_, value) => {
+                    target.insert(key, value);
+                }
+            }
+        }
+    }
+
+    match (params, extra) {
+        (Some(Value::Object(mut params)), Value::Object(extra)) => {
+            merge(&mut params, extra);
+            Value::Object(params)
+        }
+        (_, extra) => extra,
+    }
+}
+
+/// Preamble text describing the expected reply, so models without a JSON mode follow it
+pub fn format_instructions(format: &ResponseFormat) -> Option<String> {
+    match format {
+        ResponseFormat::Text => None,
+        ResponseFormat::JsonObject => {
+            Some("Reply with a single JSON object and nothing else.".to_string())
+        }
+        ResponseFormat::JsonSchema { json_schema } => {
+            let mut text = format!(
+                "Reply with a single JSON value matching this JSON Schema and nothing else:\n{}",
+                json_schema.schema
+            );
+            if let Some(description) = &json_schema.description {
+                text = format!("{}\n\n{}", description, text);
+            }
+            Some(text)
+        }
+    }
+}
+
+/// Parses a reply as JSON, accepting a reply wrapped in a Markdown code block
+pub fn parse_json_reply(reply: &str) -> Result<Value, String> {
+    let mut text = reply.trim();
+    if let Some(rest) = text.strip_prefix("```") {
+        let rest = rest.strip_prefix("json").unwrap_or(rest);
+        text = rest.strip_suffix("```").unwrap_or(rest).trim();
+    }
+    serde_json::from_str(text).map_err(|e| format!("The reply is not valid JSON: {}", e))
+}
+
+/// Checks a reply against the format. Returns `None` for plain text replies.
+pub fn validate_reply(format: &ResponseFormat, reply: &str) -> Option<StructuredOutput> {
+    if !format.is_json() {
+        return None;
+    }
+    let value = match parse_json_reply(reply) {
+        Ok(value) => value,
+        Err(e) => {
+            return Some(StructuredOutput {
+                errors: vec![e],
+                ..Default::default()
+            })
+        }
+    };
+
+    let errors = match format {
+        ResponseFormat::JsonSchema { json_schema } => {
+            match jsonschema::validator_for(&json_schema.schema) {
+                Ok(validator) => validator
+                    .iter_errors(&value)
+                    .map(|error| {
+                        let path = error.instance_path.to_string();
+                        if path.is_empty() {
+                            error.to_string()
+                        } else {
+                            format!("{}: {}", path, error)
+                        }
+                    })
+                    .collect(),
+                Err(e) => vec![format!("Invalid JSON Schema: {}", e)],
+            }
+        }
+        _ if !value.is_object() => vec!["The reply is not a JSON object".to_string()],
+        _ => Vec::new(),
+    };
+
+    Some(StructuredOutput {
+        value: Some(value),
+        valid: errors.is_empty(),
+        errors,
+        attempts: 1,
+    })
+}
+
+/// Prompt asking the model to correct its previous reply
+pub fn repair_prompt(errors: &[String]) -> String {
+    format!(
+        "Your reply does not match the requested format:\n- {}\n\nReply again with only the corrected JSON.",
+        errors.join("\n- ")
+    )
+}
+
+impl ChatService {
+    /// Runs a turn and, with a JSON `response_format`, validates the reply. An invalid
+    /// reply is sent back with the errors, up to `validation_retries` times; a
+    /// `validation` event is emitted before each new attempt so clients can discard the
+    /// text streamed so far. Usage is summed over all attempts.
+    pub(super) async fn run_structured_turn(
+        &self,
+        request: &mut ChatRequest,
+        mut prompt: Message,
+        mut history: Vec<Message>,
+        stream_id: &str,
+        cancel_token: &CancellationToken,
+        callback: &(dyn Fn(ChatStreamEvent) + Send + Sync),
+    ) -> Result<(StepOutput, Option<StructuredOutput>), String> {
+        let Some(format) = request.response_format.clone() else {
+            let step = self
+                .run_turn(
+                    request,
+                    prompt,
+                    history,
+                    request.tools.clone(),
+                    stream_id,
+                    cancel_token,
+                    callback,
+                )
+                .await?;
+            return Ok((step, None));
+        };
+        let max_retries = request
+            .validation_retries
+            .unwrap_or(STRUCTURED_OUTPUT_MAX_RETRIES);
+        let mut usage = None;
+        let mut first_token_at = None;
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+            let mut step = self
+                .run_turn(
+                    request,
+                    prompt.clone(),
+                    history.clone(),
+                    request.tools.clone(),
+                    stream_id,
+                    cancel_token,
+                    callback,
+                )
+                .await?;
+            if let Some(step_usage) = step.usage {
+                *usage.get_or_insert_with(Default::default) += step_usage;
+            }
+            first_token_at = first_token_at.or(step.first_token_at);
+            step.usage = usage;
+            step.first_token_at = first_token_at;
+            if step.cancelled {
+                return Ok((step, None));
+            }
+
+            let Some(mut output) = validate_reply(&format, &step.content) else {
+                return Ok((step, None));
+            };
+            output.attempts = attempts;
+            if output.valid || attempts > max_retries {
+                return Ok((step, Some(output)));
+            }
+
+            log::warn!(
+                "Reply of {}/{} does not match the response format ({}/{}): {}",
+                request.provider,
+                request.model,
+                attempts,
+                max_retries,
+                output.errors.join("; ")
+            );
+            callback(ChatStreamEvent::new(
+                stream_id,
+                "validation",
+                json!({ "attempt": attempts, "errors": output.errors }).to_string(),
+                false,
+            ));
+            history.push(prompt);
+            history.push(Message::assistant(step.content));
+            prompt = Message::user(repair_prompt(&output.errors));
+        }
+    }
+}
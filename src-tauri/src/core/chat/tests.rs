@@ -0,0 +1,1361 @@
+use super::agent::*;
+use super::catalog::*;
+use super::compare::*;
+use super::content::*;
+use super::context::*;
+use super::embeddings::*;
+use super::helpers::{
+    build_additional_params, build_preamble, completion_metadata, finish_reason,
+    params_from_model_settings, run_agent, tool_choice_params,
+};
+use super::mock::*;
+use super::providers::*;
+use super::recorder::*;
+use super::retry::*;
+use super::streams::*;
+use super::structured::*;
+use super::titles::*;
+use super::{
+    ChatMessage, ChatRequest, ChatService, ChatStreamEvent, ChatUsage, GenerationParams,
+    ToolCallInfo, ToolChoice,
+};
+use crate::core::downloads::models::ProxyConfig;
+use crate::core::mcp::models::ToolWithServer;
+use crate::core::state::SharedMcpServers;
+use crate::core::threads::helpers::update_thread_metadata;
+use crate::core::threads::utils::{ensure_thread_dir_exists, get_thread_metadata_path};
+use rmcp::model::{CallToolResult, Content};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+#[test]
+fn test_parse_tool_arguments_object() {
+    let arguments = parse_tool_arguments(&json!({ "query": "rust" })).unwrap();
+    assert_eq!(arguments.unwrap()["query"], "rust");
+}
+
+#[test]
+fn test_parse_tool_arguments_encoded_string() {
+    let arguments = parse_tool_arguments(&json!("{\"path\": \"/tmp\"}")).unwrap();
+    assert_eq!(arguments.unwrap()["path"], "/tmp");
+}
+
+#[test]
+fn test_parse_tool_arguments_empty() {
+    assert!(parse_tool_arguments(&json!(null)).unwrap().is_none());
+    assert!(parse_tool_arguments(&json!("")).unwrap().is_none());
+}
+
+#[test]
+fn test_parse_tool_arguments_rejects_non_objects() {
+    assert!(parse_tool_arguments(&json!([1, 2])).is_err());
+    assert!(parse_tool_arguments(&json!("not json")).is_err());
+}
+
+#[test]
+fn test_tool_result_text_joins_text_content() {
+    let result = CallToolResult::success(vec![Content::text("first"), Content::text("second")]);
+    assert_eq!(tool_result_text(&result), "first\nsecond");
+}
+
+#[test]
+fn test_tool_definitions_from_mcp_tools() {
+    let tool = |name: &str, server: &str| ToolWithServer {
+        name: name.to_string(),
+        description: None,
+        input_schema: json!({ "type": "object" }),
+        server: server.to_string(),
+    };
+    let tools = vec![tool("fetch", "fetch"), tool("fetch", "browser")];
+    let definitions = tool_definitions(&tools);
+
+    // Duplicate names are offered once
+    assert_eq!(definitions.len(), 1);
+    assert_eq!(definitions[0].name, "fetch");
+    assert_eq!(definitions[0].description, "");
+    assert_eq!(definitions[0].parameters, json!({ "type": "object" }));
+}
+
+#[test]
+fn test_build_preamble_joins_instructions_and_system_messages() {
+    let history = Some(vec![
+        ChatMessage::new_system("Answer in French.".to_string()),
+        ChatMessage::new_user("Hi".to_string()),
+    ]);
+    let preamble = build_preamble(&Some("You are a tutor.".to_string()), &history);
+    assert_eq!(
+        preamble.as_deref(),
+        Some("You are a tutor.\n\nAnswer in French.")
+    );
+
+    assert_eq!(build_preamble(&Some("  ".to_string()), &None), None);
+}
+
+#[test]
+fn test_build_additional_params_per_provider() {
+    let params = GenerationParams {
+        top_p: Some(0.9),
+        stop: Some(vec!["END".to_string()]),
+        seed: Some(7),
+        ..Default::default()
+    };
+
+    let openai = build_additional_params("openai", &params).unwrap();
+    assert_eq!(openai, json!({ "top_p": 0.9, "stop": ["END"], "seed": 7 }));
+
+    let anthropic = build_additional_params("anthropic", &params).unwrap();
+    assert_eq!(
+        anthropic,
+        json!({ "top_p": 0.9, "stop_sequences": ["END"] })
+    );
+
+    let gemini = build_additional_params("gemini", &params).unwrap();
+    assert_eq!(gemini["generationConfig"]["stopSequences"], json!(["END"]));
+
+    assert!(build_additional_params("openai", &GenerationParams::default()).is_none());
+}
+
+#[test]
+fn test_build_additional_params_extra_params_override() {
+    let params = GenerationParams {
+        seed: Some(1),
+        extra_params: Some(json!({ "seed": 2, "repetition_penalty": 1.1 })),
+        ..Default::default()
+    };
+    let fields = build_additional_params("openai", &params).unwrap();
+    assert_eq!(fields, json!({ "seed": 2, "repetition_penalty": 1.1 }));
+}
+
+#[test]
+fn test_tool_choice_params_per_provider() {
+    let named = ToolChoice::Tool("get_weather".to_string());
+    assert_eq!(
+        tool_choice_params("openai", &named).unwrap(),
+        json!({ "tool_choice": { "type": "function", "function": { "name": "get_weather" } } })
+    );
+    assert_eq!(
+        tool_choice_params("anthropic", &ToolChoice::Required).unwrap(),
+        json!({ "tool_choice": { "type": "any" } })
+    );
+    assert_eq!(
+        tool_choice_params("gemini", &named).unwrap()["toolConfig"]["functionCallingConfig"],
+        json!({ "mode": "ANY", "allowedFunctionNames": ["get_weather"] })
+    );
+    assert!(tool_choice_params("cohere", &ToolChoice::Auto).is_none());
+}
+
+#[test]
+fn test_request_params_take_precedence_over_model_settings() {
+    let settings = json!({ "temperature": 0.2, "max_tokens": 512, "stop": "</s>" });
+    let params = GenerationParams {
+        temperature: Some(0.8),
+        ..Default::default()
+    }
+    .or(params_from_model_settings(&settings));
+
+    assert_eq!(params.temperature, Some(0.8));
+    assert_eq!(params.max_tokens, Some(512));
+    assert_eq!(params.stop, Some(vec!["</s>".to_string()]));
+}
+
+fn create_provider_config(kind: Option<&str>, base_url: Option<&str>) -> ProviderConfig {
+    ProviderConfig {
+        kind: kind.map(String::from),
+        api_key: Some("sk-secret-key".to_string()),
+        base_url: base_url.map(String::from),
+        headers: HashMap::from([("X-Api-Token".to_string(), "header-secret".to_string())]),
+        proxy: None,
+        ignore_ssl: None,
+    }
+}
+
+#[test]
+fn test_provider_config_debug_redacts_secrets() {
+    let config = create_provider_config(Some("openai-compatible"), Some("http://10.0.0.2:8000/v1"));
+    let debug = format!("{:?}", config);
+    assert!(!debug.contains("sk-secret-key"));
+    assert!(!debug.contains("header-secret"));
+    assert!(debug.contains("X-Api-Token"));
+}
+
+#[test]
+fn test_provider_config_summary() {
+    let config = create_provider_config(None, None);
+    let summary = config.summary("openai");
+    assert_eq!(summary.kind, "openai");
+    assert!(summary.has_api_key);
+    assert_eq!(summary.headers, vec!["X-Api-Token"]);
+}
+
+#[test]
+fn test_validate_provider_config() {
+    let lan = create_provider_config(Some("openai-compatible"), Some("http://10.0.0.2:8000/v1"));
+    assert!(validate_provider_config("lan", &lan).is_ok());
+    assert!(validate_provider_config("lan/vllm", &lan).is_err());
+
+    let missing_url = create_provider_config(Some("openai-compatible"), None);
+    assert!(validate_provider_config("lan", &missing_url).is_err());
+
+    let unknown = create_provider_config(Some("unknown"), None);
+    assert!(validate_provider_config("unknown", &unknown).is_err());
+
+    // Providers other than OpenAI and Anthropic only take an API key
+    let groq = create_provider_config(None, None);
+    assert!(validate_provider_config("groq", &groq).is_err());
+    let groq = ProviderConfig {
+        headers: HashMap::new(),
+        ..groq
+    };
+    assert!(validate_provider_config("groq", &groq).is_ok());
+    let groq = ProviderConfig {
+        ignore_ssl: Some(true),
+        ..groq
+    };
+    assert!(validate_provider_config("groq", &groq).is_err());
+}
+
+#[test]
+fn test_provider_config_ignore_ssl_without_proxy() {
+    let config = ProviderConfig {
+        ignore_ssl: Some(true),
+        ..create_provider_config(Some("openai-compatible"), Some("https://10.0.0.2:8443/v1"))
+    };
+    assert!(config.ignores_ssl());
+    assert!(config.summary("lan").ignore_ssl);
+    assert!(build_http_client(&config).is_ok());
+
+    let proxied = ProviderConfig {
+        proxy: Some(ProxyConfig {
+            url: "http://proxy.local:3128".to_string(),
+            username: None,
+            password: None,
+            no_proxy: None,
+            ignore_ssl: Some(true),
+        }),
+        ..create_provider_config(Some("openai-compatible"), Some("https://10.0.0.2:8443/v1"))
+    };
+    assert!(proxied.ignores_ssl());
+    assert!(!create_provider_config(None, None).ignores_ssl());
+}
+
+#[test]
+fn test_parse_configured_model() {
+    let configs = HashMap::from([(
+        "lan".to_string(),
+        create_provider_config(Some("openai-compatible"), Some("http://10.0.0.2:8000/v1")),
+    )]);
+    assert_eq!(
+        parse_configured_model("lan/qwen3-8b", &configs),
+        Some(("lan".to_string(), "qwen3-8b".to_string()))
+    );
+    assert_eq!(parse_configured_model("openai/gpt-4o", &configs), None);
+    assert_eq!(parse_configured_model("lan/", &configs), None);
+}
+
+#[test]
+fn test_finish_reason() {
+    let usage = Some(ChatUsage {
+        prompt_tokens: 10,
+        completion_tokens: 64,
+        total_tokens: 74,
+    });
+    let tool_call = ToolCallInfo {
+        id: "call_1".to_string(),
+        call_id: None,
+        name: "fetch".to_string(),
+        arguments: json!({}),
+    };
+
+    assert_eq!(finish_reason(&[tool_call], usage, Some(64)), "tool_calls");
+    assert_eq!(finish_reason(&[], usage, Some(64)), "length");
+    assert_eq!(finish_reason(&[], usage, Some(128)), "stop");
+    assert_eq!(finish_reason(&[], None, Some(64)), "stop");
+}
+
+#[test]
+fn test_completion_metadata_timing() {
+    let started = Instant::now();
+    let first_token_at = started + Duration::from_millis(250);
+    let finished = started + Duration::from_millis(2250);
+    let usage = Some(ChatUsage {
+        prompt_tokens: 20,
+        completion_tokens: 100,
+        total_tokens: 120,
+    });
+
+    let metadata = completion_metadata(usage, "stop", started, Some(first_token_at), finished);
+    assert_eq!(metadata.time_to_first_token_ms, Some(250));
+    assert_eq!(metadata.duration_ms, 2250);
+    assert_eq!(metadata.tokens_per_second, Some(50.0));
+
+    let metadata = completion_metadata(None, "stop", started, None, finished);
+    assert_eq!(metadata.time_to_first_token_ms, None);
+    assert_eq!(metadata.tokens_per_second, None);
+}
+
+#[tokio::test]
+async fn test_chat_service_limits_concurrent_chats() {
+    let service = ChatService::with_concurrency(1);
+    let cancel_token = CancellationToken::new();
+
+    let permit = service.acquire_slot(&cancel_token).await;
+    assert!(permit.is_some());
+
+    // Clones share the limit, so a second chat waits until it is cancelled
+    let waiting = service.clone();
+    let waiting_token = cancel_token.child_token();
+    let waiter = tokio::spawn(async move { waiting.acquire_slot(&waiting_token).await.is_some() });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    cancel_token.cancel();
+    assert!(!waiter.await.unwrap());
+
+    drop(permit);
+    assert!(service
+        .acquire_slot(&CancellationToken::new())
+        .await
+        .is_some());
+}
+
+#[test]
+fn test_chat_content_deserializes_text_and_parts() {
+    let message: ChatMessage =
+        serde_json::from_value(json!({ "role": "user", "content": "Hi", "timestamp": null }))
+            .unwrap();
+    assert_eq!(message.content, ChatContent::Text("Hi".to_string()));
+
+    let message: ChatMessage = serde_json::from_value(json!({
+        "role": "user",
+        "content": [
+            { "type": "text", "text": "Describe this" },
+            { "type": "image", "path": "attachments/cat.png" }
+        ],
+        "timestamp": null
+    }))
+    .unwrap();
+    assert_eq!(message.content.text(), "Describe this");
+    assert_eq!(
+        message.content.parts()[1],
+        ContentPart::Image(MediaSource {
+            path: Some("attachments/cat.png".to_string()),
+            ..Default::default()
+        })
+    );
+}
+
+#[test]
+fn test_load_local_media_reads_files_in_data_folder() {
+    let data_dir = std::env::temp_dir().join(format!("jan-chat-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(data_dir.join("attachments")).unwrap();
+    std::fs::write(data_dir.join("attachments/notes.md"), "# Notes").unwrap();
+
+    let mut source = MediaSource {
+        path: Some("attachments/notes.md".to_string()),
+        ..Default::default()
+    };
+    load_local_media(&mut source, &data_dir).unwrap();
+    assert_eq!(source.path, None);
+    assert_eq!(source.data.as_deref(), Some("IyBOb3Rlcw=="));
+    assert_eq!(source.media_type.as_deref(), Some("text/markdown"));
+    assert_eq!(source.name.as_deref(), Some("notes.md"));
+
+    let mut outside = MediaSource {
+        path: Some("../".to_string()),
+        ..Default::default()
+    };
+    assert!(load_local_media(&mut outside, &data_dir.join("attachments")).is_err());
+
+    std::fs::remove_dir_all(&data_dir).unwrap();
+}
+
+#[test]
+fn test_parse_data_uri() {
+    assert_eq!(
+        parse_data_uri("data:image/jpeg;base64,/9j/4AAQ"),
+        Some(("image/jpeg".to_string(), "/9j/4AAQ".to_string()))
+    );
+    assert_eq!(parse_data_uri("https://example.com/cat.png"), None);
+}
+
+#[test]
+fn test_to_user_content_without_vision() {
+    let image = ContentPart::Image(MediaSource {
+        data: Some("iVBORw0KGgo=".to_string()),
+        media_type: Some("image/png".to_string()),
+        name: Some("cat.png".to_string()),
+        ..Default::default()
+    });
+    assert!(matches!(
+        to_user_content(&image, false).unwrap(),
+        rig::message::UserContent::Text(_)
+    ));
+    assert!(matches!(
+        to_user_content(&image, true).unwrap(),
+        rig::message::UserContent::Image(_)
+    ));
+
+    let unloaded = ContentPart::Image(MediaSource {
+        path: Some("attachments/cat.png".to_string()),
+        ..Default::default()
+    });
+    assert!(to_user_content(&unloaded, true).is_err());
+}
+
+#[test]
+fn test_history_from_thread() {
+    let messages = vec![
+        json!({
+            "role": "user",
+            "status": "ready",
+            "created_at": 1_700_000_000_000u64,
+            "content": [
+                { "type": "text", "text": { "value": "What is this?", "annotations": [] } },
+                { "type": "image_url", "image_url": { "url": "attachments/cat.png" } }
+            ]
+        }),
+        json!({
+            "role": "assistant",
+            "status": "ready",
+            "content": [{ "type": "text", "text": { "value": "A cat.", "annotations": [] } }]
+        }),
+        json!({
+            "role": "assistant",
+            "status": "error",
+            "content": [{ "type": "text", "text": { "value": "Partial", "annotations": [] } }]
+        }),
+    ];
+
+    let history = history_from_thread(&messages);
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].timestamp, Some(1_700_000_000));
+    assert_eq!(
+        history[0].content.parts()[1],
+        ContentPart::Image(MediaSource {
+            path: Some("attachments/cat.png".to_string()),
+            ..Default::default()
+        })
+    );
+    assert_eq!(history[1].content, ChatContent::Text("A cat.".to_string()));
+}
+
+#[test]
+fn test_user_thread_message_round_trips_attachments() {
+    let attachments = vec![
+        ContentPart::Image(MediaSource {
+            url: Some("https://example.com/cat.png".to_string()),
+            ..Default::default()
+        }),
+        ContentPart::Document(MediaSource {
+            path: Some("files/report.pdf".to_string()),
+            ..Default::default()
+        }),
+    ];
+    let message = user_thread_message("thread-1", "Summarize", &attachments);
+    assert_eq!(message["thread_id"], "thread-1");
+    assert_eq!(message["content"][0]["text"]["value"], "Summarize");
+    assert_eq!(
+        message["content"][1]["image_url"]["url"],
+        "https://example.com/cat.png"
+    );
+
+    let history = history_from_thread(&[message]);
+    assert_eq!(history[0].content.text(), "Summarize");
+    assert_eq!(&history[0].content.parts()[1..], attachments.as_slice());
+}
+
+#[test]
+fn test_chat_stream_buffer_numbers_and_bounds_events() {
+    let mut buffer = ChatStreamBuffer::new(3);
+    for i in 0..5 {
+        let event = buffer.push(ChatStreamEvent::new("s", "text", i.to_string(), false));
+        assert_eq!(event.seq, i + 1);
+    }
+    assert_eq!(buffer.last_seq(), 5);
+    assert!(!buffer.is_finished());
+
+    // Only the latest three events are kept
+    let seqs: Vec<u64> = buffer.events_from(0).iter().map(|e| e.seq).collect();
+    assert_eq!(seqs, vec![3, 4, 5]);
+    let replay = buffer.events_from(5);
+    assert_eq!(replay.len(), 1);
+    assert_eq!(replay[0].content, "4");
+
+    buffer.push(ChatStreamEvent::new("s", "complete", String::new(), true));
+    assert!(buffer.is_finished());
+    assert!(buffer.events_from(7).is_empty());
+}
+
+#[test]
+fn test_compare_buffer_finishes_after_every_branch() {
+    let mut buffer = ChatStreamBuffer::new(16);
+    buffer.set_branches(2);
+    buffer.push(ChatStreamEvent::new("c:0", "complete", String::new(), true));
+    assert!(!buffer.is_finished());
+    buffer.push(ChatStreamEvent::new(
+        "c:1",
+        "cancelled",
+        String::new(),
+        true,
+    ));
+    assert!(buffer.is_finished());
+}
+
+#[test]
+fn test_compare_result_collects_branch_events() {
+    let branch = CompareBranch {
+        compare_id: "c".to_string(),
+        index: 1,
+        provider: "anthropic".to_string(),
+        model: "claude-sonnet-4".to_string(),
+    };
+    let mut result = CompareResult::new(&branch, "c:1");
+    result.record(&ChatStreamEvent::new(
+        "c:1",
+        "text",
+        "{oops".to_string(),
+        false,
+    ));
+    result.record(&ChatStreamEvent::new(
+        "c:1",
+        "validation",
+        String::new(),
+        false,
+    ));
+    result.record(&ChatStreamEvent::new(
+        "c:1",
+        "text",
+        "{}".to_string(),
+        false,
+    ));
+    result.record(&ChatStreamEvent::new(
+        "c:1",
+        "complete",
+        String::new(),
+        true,
+    ));
+    assert_eq!(result.content, "{}");
+    assert_eq!(result.status, "completed");
+
+    // A failed task does not override a final event
+    result.fail("Task failed".to_string());
+    assert_eq!(result.status, "completed");
+    let mut result = CompareResult::new(&branch, "c:1");
+    result.fail("Task failed".to_string());
+    assert_eq!(result.status, "error");
+    assert_eq!(result.error.as_deref(), Some("Task failed"));
+}
+
+fn create_history(turns: usize, words_per_message: usize) -> Vec<ChatMessage> {
+    let text = "word ".repeat(words_per_message);
+    let mut history = vec![ChatMessage::new_system("Be brief.".to_string())];
+    for _ in 0..turns {
+        history.push(ChatMessage::new_user(text.clone()));
+        history.push(ChatMessage::new_assistant(text.clone()));
+    }
+    history
+}
+
+#[test]
+fn test_context_window_lookup() {
+    assert_eq!(context_window("gpt-4o-mini"), 128_000);
+    assert_eq!(context_window("gpt-4"), 8_192);
+    assert_eq!(context_window("claude-3-5-haiku-latest"), 200_000);
+    assert_eq!(context_window("some-local-model"), 32_768);
+}
+
+#[test]
+fn test_context_window_ambiguous_ids() {
+    // A version number only matches as a whole
+    assert_eq!(context_window("gpt-4-0613"), 8_192);
+    assert_eq!(context_window("gpt-4.1-mini"), 1_047_576);
+    assert_eq!(context_window("gpt-4.5-preview"), 128_000);
+    assert_eq!(context_window("gpt-4-32k"), 32_768);
+    assert_eq!(context_window("gpt-5-mini"), 400_000);
+    assert_eq!(context_window("o3-mini"), 200_000);
+    assert_eq!(context_window("o1"), 200_000);
+    // Families match at the start of the name only
+    assert_eq!(context_window("phi-o1-reasoning"), 32_768);
+    assert_eq!(context_window("o1x"), 32_768);
+    assert_eq!(context_window("llama-3.1-gpt-4-distill"), 128_000);
+    // Provider and organization prefixes are ignored
+    assert_eq!(context_window("openai/gpt-4o"), 128_000);
+    assert_eq!(context_window("models/gemini-1.5-pro-002"), 2_097_152);
+    assert_eq!(context_window("Gemini-2.0-Flash"), 1_048_576);
+    assert_eq!(context_window("llama3.1:8b"), 128_000);
+    assert_eq!(context_window("qwen2.5-coder-7b"), 32_768);
+}
+
+#[test]
+fn test_plan_context_keeps_history_that_fits() {
+    let history = create_history(3, 10);
+    let plan = plan_context(history.clone(), 10_000, &ContextStrategy::DropOldest);
+    assert_eq!(plan.keep.len(), history.len());
+    assert_eq!(plan.dropped, 0);
+    assert!(plan.summarize.is_empty());
+}
+
+#[test]
+fn test_plan_context_drop_oldest() {
+    // Each message is 4 overhead + 13 text tokens
+    let history = create_history(5, 10);
+    let plan = plan_context(history, 80, &ContextStrategy::DropOldest);
+
+    assert_eq!(plan.keep[0].role, "system");
+    // The kept conversation starts with a user message
+    assert_eq!(plan.keep[1].role, "user");
+    assert_eq!(plan.keep.len() - 1 + plan.dropped, 10);
+    let tokens: u64 = plan.keep.iter().map(estimate_message_tokens).sum();
+    assert!(tokens <= 80);
+}
+
+#[test]
+fn test_plan_context_keep_last() {
+    let history = create_history(5, 10);
+    let plan = plan_context(history, 10_000, &ContextStrategy::KeepLast { count: 4 });
+    assert_eq!(plan.keep.len(), 5);
+    assert_eq!(plan.dropped, 6);
+}
+
+#[test]
+fn test_plan_context_summarize_only_when_over_budget() {
+    let strategy = ContextStrategy::Summarize { keep_last: 2 };
+    // Each message is 4 overhead + 125 text tokens, 1297 tokens in all
+    let plan = plan_context(create_history(5, 100), 10_000, &strategy);
+    assert!(plan.summarize.is_empty());
+
+    let plan = plan_context(create_history(5, 100), 1_000, &strategy);
+    assert_eq!(plan.summarize.len(), 8);
+    assert_eq!(plan.keep.len(), 3);
+    assert_eq!(plan.dropped, 0);
+}
+
+#[test]
+fn test_is_retryable() {
+    assert!(is_retryable("HttpError: 429 Too Many Requests"));
+    assert!(is_retryable(
+        "ProviderError: status 503, body: upstream unavailable"
+    ));
+    assert!(is_retryable("Rate limit reached for gpt-4o"));
+    assert!(is_retryable("Anthropic API is overloaded"));
+    assert!(!is_retryable("HttpError: 401 Unauthorized"));
+    assert!(!is_retryable("Invalid model: gpt-5000"));
+    // Numbers that are not a status
+    assert!(!is_retryable("max_tokens must be <= 4096, got 500"));
+    assert!(!is_retryable("invalid model gpt-429"));
+}
+
+#[test]
+fn test_error_status() {
+    assert_eq!(error_status("HttpError: 429 Too Many Requests"), Some(429));
+    assert_eq!(
+        error_status("ProviderError: status: 503, body: busy"),
+        Some(503)
+    );
+    assert_eq!(error_status("status code 401"), Some(401));
+    assert_eq!(
+        error_status(
+            "HTTP status client error (404 Not Found) for url (https://api.openai.com/v1)"
+        ),
+        Some(404)
+    );
+    assert_eq!(error_status("max_tokens must be <= 4096, got 500"), None);
+    assert_eq!(error_status("invalid model gpt-429"), None);
+    assert_eq!(error_status("status 12345"), None);
+}
+
+#[test]
+fn test_retry_after() {
+    assert_eq!(
+        retry_after("Rate limit reached. Please try again in 20s."),
+        Some(std::time::Duration::from_secs(20))
+    );
+    assert_eq!(
+        retry_after("429 Too Many Requests, retry-after: 1500ms"),
+        Some(std::time::Duration::from_millis(1500))
+    );
+    assert_eq!(
+        retry_after("Retry after 2.5 seconds"),
+        Some(std::time::Duration::from_millis(2500))
+    );
+    assert_eq!(retry_after("503 Service Unavailable"), None);
+}
+
+#[test]
+fn test_retry_delay() {
+    let policy = RetryPolicy::default();
+    // The provider's hint wins over the 1s backoff of the first retry
+    assert_eq!(
+        retry_delay(&policy, 1, "429: try again in 20s"),
+        std::time::Duration::from_secs(20)
+    );
+
+    let delay = retry_delay(&policy, 1, "503 Service Unavailable");
+    assert!(delay >= std::time::Duration::from_millis(750));
+    assert!(delay <= std::time::Duration::from_millis(1250));
+
+    let policy = RetryPolicy {
+        max_delay_ms: 5_000,
+        ..Default::default()
+    };
+    assert_eq!(
+        retry_delay(&policy, 1, "429: try again in 20s"),
+        std::time::Duration::from_secs(5)
+    );
+}
+
+fn person_format() -> ResponseFormat {
+    serde_json::from_value(json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "person",
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "age": { "type": "integer" }
+                },
+                "required": ["name", "age"]
+            }
+        }
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_parse_json_reply_strips_code_block() {
+    let value = parse_json_reply("```json\n{\"name\": \"Ada\"}\n```").unwrap();
+    assert_eq!(value, json!({ "name": "Ada" }));
+    assert!(parse_json_reply("Sure! Here it is").is_err());
+}
+
+#[test]
+fn test_validate_reply_against_schema() {
+    let format = person_format();
+    let output = validate_reply(&format, r#"{"name": "Ada", "age": 36}"#).unwrap();
+    assert!(output.valid);
+    assert_eq!(output.value, Some(json!({ "name": "Ada", "age": 36 })));
+
+    let output = validate_reply(&format, r#"{"name": "Ada", "age": "36"}"#).unwrap();
+    assert!(!output.valid);
+    assert_eq!(output.errors.len(), 1);
+    assert!(output.errors[0].starts_with("/age"));
+    // The parsed value is kept for invalid replies
+    assert!(output.value.is_some());
+
+    let output = validate_reply(&format, "not json").unwrap();
+    assert!(!output.valid);
+    assert!(output.value.is_none());
+
+    assert!(validate_reply(&ResponseFormat::Text, "anything").is_none());
+    assert!(
+        !validate_reply(&ResponseFormat::JsonObject, "[1, 2]")
+            .unwrap()
+            .valid
+    );
+}
+
+#[test]
+fn test_response_format_params() {
+    let format = person_format();
+    let params = response_format_params("openai", &format).unwrap();
+    assert_eq!(params["response_format"]["type"], "json_schema");
+    assert_eq!(params["response_format"]["json_schema"]["name"], "person");
+
+    let params = response_format_params("deepseek", &format).unwrap();
+    assert_eq!(params["response_format"], json!({ "type": "json_object" }));
+    assert!(response_format_params("anthropic", &format).is_none());
+    assert!(response_format_params("openai", &ResponseFormat::Text).is_none());
+
+    // Gemini's generationConfig is merged with the sampling parameters
+    let base = build_additional_params(
+        "gemini",
+        &GenerationParams {
+            top_p: Some(0.9),
+            ..Default::default()
+        },
+    );
+    let merged = merge_params(base, response_format_params("gemini", &format).unwrap());
+    assert_eq!(merged["generationConfig"]["topP"], 0.9);
+    assert_eq!(
+        merged["generationConfig"]["responseMimeType"],
+        "application/json"
+    );
+}
+
+#[test]
+fn test_mock_select_response() {
+    let fixture: MockFixture = serde_yaml::from_str(
+        r#"
+responses:
+  - chunks: [{ type: text, text: "first" }]
+  - prompt_contains: "weather"
+    chunks: [{ type: text, text: "sunny" }]
+  - chunks: [{ type: text, text: "second" }]
+"#,
+    )
+    .unwrap();
+    let text = |response: Option<&MockResponse>| match &response.unwrap().chunks[0] {
+        MockChunk::Text { text } => text.clone(),
+        chunk => panic!("unexpected chunk {:?}", chunk),
+    };
+
+    assert_eq!(text(select_response(&fixture, "Hi", 0)), "first");
+    assert_eq!(text(select_response(&fixture, "Hi", 1)), "second");
+    // The last response repeats once the script runs out
+    assert_eq!(text(select_response(&fixture, "Hi", 5)), "second");
+    assert_eq!(text(select_response(&fixture, "The weather?", 0)), "sunny");
+}
+
+fn mock_request(model: &str, prompt: &str) -> ChatRequest {
+    serde_json::from_value(json!({
+        "prompt": prompt,
+        "provider": "mock",
+        "model": model,
+        "stream_id": "mock-stream",
+        "chat_history": null,
+    }))
+    .unwrap()
+}
+
+fn write_fixture(fixture: &str) -> String {
+    let path = std::env::temp_dir().join(format!("jan-mock-{}.yaml", uuid::Uuid::new_v4()));
+    std::fs::write(&path, fixture).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+/// Callback collecting the events of a stream
+fn collect_events() -> (
+    Arc<Mutex<Vec<ChatStreamEvent>>>,
+    impl Fn(ChatStreamEvent) + Send + Sync + 'static,
+) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = events.clone();
+    (events, move |event| sink.lock().unwrap().push(event))
+}
+
+fn event_types(events: &[ChatStreamEvent]) -> Vec<&str> {
+    events.iter().map(|e| e.event_type.as_str()).collect()
+}
+
+#[tokio::test]
+async fn test_mock_echo_streams_the_prompt() {
+    let (events, callback) = collect_events();
+    ChatService::new()
+        .stream_chat(
+            mock_request("echo", "Hello from Jan"),
+            CancellationToken::new(),
+            callback,
+        )
+        .await
+        .unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(
+        event_types(&events),
+        vec!["start", "text", "text", "text", "complete"]
+    );
+    let text: String = events
+        .iter()
+        .filter(|e| e.event_type == "text")
+        .map(|e| e.content.as_str())
+        .collect();
+    assert_eq!(text, "Hello from Jan");
+    let metadata = events.last().unwrap().metadata.clone().unwrap();
+    assert_eq!(metadata.provider.as_deref(), Some("mock"));
+    assert!(metadata.usage.unwrap().completion_tokens > 0);
+}
+
+#[tokio::test]
+async fn test_mock_fixture_replays_reasoning_and_errors() {
+    let fixture = write_fixture(
+        r#"
+responses:
+  - prompt_contains: "break"
+    chunks:
+      - { type: text, text: "Partial" }
+      - { type: error, message: "connection reset by peer" }
+  - chunks:
+      - { type: reasoning, text: "Thinking" }
+      - { type: text, text: "Answer" }
+    usage: { prompt_tokens: 3, completion_tokens: 2, total_tokens: 5 }
+"#,
+    );
+    let service = ChatService::new();
+
+    let response = service
+        .chat_non_streaming(mock_request(&fixture, "Question"))
+        .await
+        .unwrap();
+    assert_eq!(response.content, "Answer");
+    assert_eq!(response.metadata.unwrap().usage.unwrap().total_tokens, 5);
+
+    // The error comes after streamed text, so it is not retried
+    let (events, callback) = collect_events();
+    let result = service
+        .stream_chat(
+            mock_request(&fixture, "Please break"),
+            CancellationToken::new(),
+            callback,
+        )
+        .await;
+    assert!(result.is_err());
+    let events = events.lock().unwrap();
+    assert_eq!(event_types(&events), vec!["start", "text", "error"]);
+    assert_eq!(events[2].content, "connection reset by peer");
+}
+
+#[tokio::test]
+async fn test_mock_failure_falls_back_before_streaming() {
+    let fixture = write_fixture("responses:\n  - error: \"503 Service Unavailable\"\n");
+    let mut request = mock_request(&fixture, "Hi there");
+    request.retry = Some(RetryPolicy {
+        max_retries: 0,
+        ..Default::default()
+    });
+    request.fallbacks = vec![ChatTarget {
+        provider: "mock".to_string(),
+        model: "echo".to_string(),
+        provider_config: None,
+    }];
+
+    let (events, callback) = collect_events();
+    ChatService::new()
+        .stream_chat(request, CancellationToken::new(), callback)
+        .await
+        .unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(
+        event_types(&events),
+        vec!["start", "fallback", "text", "text", "complete"]
+    );
+    let metadata = events.last().unwrap().metadata.clone().unwrap();
+    assert_eq!(metadata.model.as_deref(), Some("echo"));
+}
+
+#[tokio::test]
+async fn test_agent_does_not_fall_back_after_streaming() {
+    let fixture = write_fixture(
+        r#"
+responses:
+  - chunks:
+      - { type: tool_call, id: call_1, name: search, arguments: { query: "rust" } }
+  - error: "503 Service Unavailable"
+"#,
+    );
+    let mut request = mock_request(&fixture, "Find something");
+    request.retry = Some(RetryPolicy {
+        max_retries: 0,
+        ..Default::default()
+    });
+    request.fallbacks = vec![ChatTarget {
+        provider: "mock".to_string(),
+        model: "echo".to_string(),
+        provider_config: None,
+    }];
+    let servers: SharedMcpServers = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+
+    let (events, callback) = collect_events();
+    let result = ChatService::new()
+        .stream_agent(
+            request,
+            Vec::new(),
+            servers,
+            4,
+            CancellationToken::new(),
+            callback,
+        )
+        .await;
+
+    // The first step already streamed a tool call, so the second one fails for good
+    assert!(result.is_err());
+    let events = events.lock().unwrap();
+    assert_eq!(
+        event_types(&events),
+        vec!["start", "tool_call", "tool_result", "error"]
+    );
+}
+
+#[tokio::test]
+async fn test_mock_agent_runs_tool_loop() {
+    let fixture = write_fixture(
+        r#"
+responses:
+  - chunks:
+      - { type: tool_call, id: call_1, name: search, arguments: { query: "rust" } }
+  - chunks:
+      - { type: text, text: "Done" }
+"#,
+    );
+    let servers: SharedMcpServers = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+
+    let (events, callback) = collect_events();
+    ChatService::new()
+        .stream_agent(
+            mock_request(&fixture, "Find something"),
+            Vec::new(),
+            servers,
+            4,
+            CancellationToken::new(),
+            callback,
+        )
+        .await
+        .unwrap();
+
+    let events = events.lock().unwrap();
+    assert_eq!(
+        event_types(&events),
+        vec!["start", "tool_call", "tool_result", "text", "complete"]
+    );
+    // No MCP server offers the tool, the error goes back to the model
+    let result: serde_json::Value = serde_json::from_str(&events[2].content).unwrap();
+    assert_eq!(result["id"], "call_1");
+    assert_eq!(result["is_error"], true);
+    assert_eq!(events[3].content, "Done");
+}
+
+#[tokio::test]
+async fn test_run_agent_without_streaming() {
+    let fixture = write_fixture(
+        r#"
+responses:
+  - chunks:
+      - { type: text, text: "Searching. " }
+      - { type: tool_call, id: call_1, name: search, arguments: { query: "rust" } }
+  - chunks:
+      - { type: text, text: "Done" }
+"#,
+    );
+    let servers: SharedMcpServers = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+
+    let (response, events) = run_agent(
+        &ChatService::new(),
+        mock_request(&fixture, "Find something"),
+        Vec::new(),
+        servers,
+        4,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(response.content, "Searching. Done");
+    assert_eq!(response.status, "completed");
+    assert_eq!(response.metadata.unwrap().finish_reason, "stop");
+    assert_eq!(
+        event_types(&events),
+        vec![
+            "start",
+            "text",
+            "tool_call",
+            "tool_result",
+            "text",
+            "complete"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_mock_stream_can_be_cancelled() {
+    let fixture = write_fixture(
+        r#"
+responses:
+  - delay_ms: 50
+    chunks:
+      - { type: text, text: "one " }
+      - { type: text, text: "two " }
+      - { type: delay, ms: 5000 }
+      - { type: text, text: "three" }
+"#,
+    );
+    let cancel_token = CancellationToken::new();
+    let (events, callback) = collect_events();
+    let service = ChatService::new();
+    let stream = tokio::spawn({
+        let cancel_token = cancel_token.clone();
+        async move {
+            service
+                .stream_chat(mock_request(&fixture, "Count"), cancel_token, callback)
+                .await
+                .is_ok()
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    cancel_token.cancel();
+    assert!(stream.await.unwrap());
+
+    let events = events.lock().unwrap();
+    assert_eq!(
+        event_types(&events),
+        vec!["start", "text", "text", "cancelled"]
+    );
+}
+
+#[tokio::test]
+async fn test_mock_stream_is_persisted_to_thread() {
+    let app = tauri::test::mock_app();
+    let thread_id = format!("mock-thread-{}", uuid::Uuid::new_v4());
+    let mut recorder = ThreadRecorder::start(
+        app.handle().clone(),
+        &thread_id,
+        None,
+        None,
+        "Hello thread",
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let (events, callback) = collect_events();
+    ChatService::new()
+        .stream_chat(
+            mock_request("echo", "Hello thread"),
+            CancellationToken::new(),
+            callback,
+        )
+        .await
+        .unwrap();
+    let events = events.lock().unwrap().clone();
+    for event in &events {
+        recorder.record(event).await.unwrap();
+    }
+    assert!(recorder.is_finished());
+
+    let history = load_thread_history(app.handle().clone(), &thread_id)
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].role, "user");
+    assert_eq!(history[1].role, "assistant");
+    assert_eq!(history[1].content.text(), "Hello thread");
+}
+
+#[test]
+fn test_normalize_models_across_providers() {
+    let openrouter = json!({ "data": [{
+        "id": "openai/gpt-4o",
+        "name": "OpenAI: GPT-4o",
+        "context_length": 128000,
+        "architecture": { "input_modalities": ["text", "image"] },
+        "supported_parameters": ["tools", "temperature"]
+    }]});
+    let models = normalize_models(&openrouter);
+    assert_eq!(models[0].id, "openai/gpt-4o");
+    assert_eq!(models[0].name.as_deref(), Some("OpenAI: GPT-4o"));
+    assert_eq!(models[0].context_length, Some(128000));
+    assert_eq!(models[0].capabilities, vec!["vision", "tools"]);
+
+    let gemini = json!({ "models": [{
+        "name": "models/gemini-2.5-flash",
+        "displayName": "Gemini 2.5 Flash",
+        "inputTokenLimit": 1048576,
+        "supportedGenerationMethods": ["generateContent", "countTokens"]
+    }]});
+    let models = normalize_models(&gemini);
+    assert_eq!(models[0].id, "gemini-2.5-flash");
+    assert_eq!(models[0].name.as_deref(), Some("Gemini 2.5 Flash"));
+    assert_eq!(models[0].context_length, Some(1048576));
+    assert_eq!(models[0].capabilities, vec!["chat"]);
+
+    // OpenAI lists bare ids, unsorted
+    let openai = json!({ "data": [{ "id": "gpt-4o" }, { "id": "gpt-4.1" }] });
+    let ids: Vec<String> = normalize_models(&openai)
+        .into_iter()
+        .map(|m| m.id)
+        .collect();
+    assert_eq!(ids, vec!["gpt-4.1", "gpt-4o"]);
+}
+
+#[test]
+fn test_models_url() {
+    assert_eq!(
+        models_url("openai", None).unwrap(),
+        "https://api.openai.com/v1/models"
+    );
+    assert_eq!(
+        models_url("openai-compatible", Some("http://localhost:1234/v1/")).unwrap(),
+        "http://localhost:1234/v1/models"
+    );
+    assert!(models_url("openai-compatible", None).is_err());
+    assert!(models_url("perplexity", None).is_err());
+}
+
+#[tokio::test]
+async fn test_list_models_uses_disk_cache() {
+    let data_dir = std::env::temp_dir().join(format!("jan-models-{}", uuid::Uuid::new_v4()));
+    assert!(model_cache_path(&data_dir, "../etc").is_err());
+
+    let list = list_models(&data_dir, "mock", None, false).await.unwrap();
+    assert_eq!(list.models[0].id, "echo");
+    assert!(model_cache_path(&data_dir, "mock").unwrap().exists());
+
+    // A fresh cache is returned as is
+    let cached = list_models(&data_dir, "mock", None, false).await.unwrap();
+    assert_eq!(cached.fetched_at, list.fetched_at);
+
+    // An expired cache is used when the provider cannot be reached
+    let path = model_cache_path(&data_dir, "lan").unwrap();
+    let expired = ProviderModelList {
+        provider: "lan".to_string(),
+        models: list.models.clone(),
+        fetched_at: 0,
+        stale: false,
+    };
+    write_model_cache(&path, &expired).unwrap();
+    let config = ProviderConfig {
+        kind: Some("openai-compatible".to_string()),
+        base_url: Some("http://127.0.0.1:9/v1".to_string()),
+        ..Default::default()
+    };
+    let stale = list_models(&data_dir, "lan", Some(&config), false)
+        .await
+        .unwrap();
+    assert!(stale.stale);
+    assert!(!is_cache_fresh(&stale, 1));
+
+    std::fs::remove_dir_all(&data_dir).unwrap();
+}
+
+#[test]
+fn test_needs_title_after_first_reply() {
+    let thread = json!({ "id": "t1", "title": "New Thread", "metadata": {} });
+    let user = json!({ "role": "user", "status": "ready" });
+    let reply = json!({ "role": "assistant", "status": "ready" });
+    let failed = json!({ "role": "assistant", "status": "error" });
+
+    assert!(!needs_title(&thread, &[user.clone()]));
+    assert!(needs_title(
+        &thread,
+        &[user.clone(), failed.clone(), reply.clone()]
+    ));
+    assert!(!needs_title(
+        &thread,
+        &[user.clone(), reply.clone(), user.clone(), reply.clone()]
+    ));
+
+    let opted_out = json!({ "metadata": { "auto_title": false } });
+    assert!(!needs_title(&opted_out, &[user.clone(), reply.clone()]));
+    let titled = json!({ "metadata": { "title_generated": true } });
+    assert!(!needs_title(&titled, &[user, reply]));
+}
+
+#[test]
+fn test_parse_and_apply_title() {
+    let title = parse_title(&json!({
+        "title": "  \"Planning a trip to Lisbon\" ",
+        "summary": " The user asks for a three-day itinerary. "
+    }))
+    .unwrap();
+    assert_eq!(title.title, "Planning a trip to Lisbon");
+    assert_eq!(title.summary, "The user asks for a three-day itinerary.");
+
+    let long = parse_title(&json!({ "title": "é".repeat(100), "summary": "" })).unwrap();
+    assert_eq!(long.title.chars().count(), 60);
+    assert!(parse_title(&json!({ "title": " ", "summary": "x" })).is_none());
+
+    let mut thread = json!({ "id": "t1", "title": "New Thread", "updated": 1, "metadata": null });
+    apply_title(&mut thread, &title, 1_700_000_000);
+    assert_eq!(thread["title"], "Planning a trip to Lisbon");
+    assert_eq!(thread["updated"], 1_700_000_000);
+    assert_eq!(
+        thread["metadata"]["summary"],
+        "The user asks for a three-day itinerary."
+    );
+    assert!(!auto_title_enabled(&thread));
+}
+
+#[tokio::test]
+async fn test_generate_thread_title_writes_thread_json() {
+    let app = tauri::test::mock_app();
+    let thread_id = format!("mock-thread-{}", uuid::Uuid::new_v4());
+    ensure_thread_dir_exists(app.handle().clone(), &thread_id).unwrap();
+    let thread = json!({ "id": thread_id, "title": "New Thread", "updated": 0, "metadata": {} });
+    update_thread_metadata(app.handle().clone(), &thread_id, &thread).unwrap();
+
+    let mut recorder = ThreadRecorder::start(
+        app.handle().clone(),
+        &thread_id,
+        None,
+        None,
+        "How do I boil an egg?",
+        &[],
+    )
+    .await
+    .unwrap();
+    let (events, callback) = collect_events();
+    ChatService::new()
+        .stream_chat(
+            mock_request("echo", "Boil it for nine minutes."),
+            CancellationToken::new(),
+            callback,
+        )
+        .await
+        .unwrap();
+    for event in events.lock().unwrap().clone().iter() {
+        recorder.record(event).await.unwrap();
+    }
+
+    let fixture = write_fixture(
+        r#"
+responses:
+  - chunks:
+      - type: text
+        text: '{"title": "Boiling eggs", "summary": "The user asks how to boil an egg."}'
+"#,
+    );
+    let target = ChatTarget {
+        provider: "mock".to_string(),
+        model: fixture,
+        provider_config: None,
+    };
+    let service = ChatService::new();
+    let title = generate_thread_title(app.handle(), &service, &thread_id, &target)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(title.title, "Boiling eggs");
+
+    let path = get_thread_metadata_path(app.handle().clone(), &thread_id);
+    let stored: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(stored["title"], "Boiling eggs");
+    assert_eq!(
+        stored["metadata"]["summary"],
+        "The user asks how to boil an egg."
+    );
+    assert_eq!(stored["metadata"]["title_generated"], true);
+
+    // Titled once, the thread is not titled again
+    let again = generate_thread_title(app.handle(), &service, &thread_id, &target)
+        .await
+        .unwrap();
+    assert!(again.is_none());
+}
+
+#[test]
+fn test_validate_embedding_inputs() {
+    let tokens = validate_inputs(&["hello world".to_string()]).unwrap();
+    assert!(tokens > 0);
+    assert!(validate_inputs(&[]).is_err());
+    assert!(validate_inputs(&["ok".to_string(), "  ".to_string()])
+        .unwrap_err()
+        .contains("Input 1"));
+    assert!(validate_inputs(&["word ".repeat(40_000)]).is_err());
+    assert!(validate_inputs(&vec!["x".to_string(); 2049]).is_err());
+}
+
+#[tokio::test]
+async fn test_mock_embeddings() {
+    let inputs = vec!["rust".to_string(), "python".to_string(), "rust".to_string()];
+    let response = ChatService::new()
+        .embed("mock", "embed", inputs, None)
+        .await
+        .unwrap();
+    assert_eq!(response.embeddings.len(), 3);
+    assert_eq!(response.dimensions, 16);
+    assert_eq!(response.embeddings[0], response.embeddings[2]);
+    assert_ne!(response.embeddings[0], response.embeddings[1]);
+    let norm: f64 = response.embeddings[0].iter().map(|v| v * v).sum();
+    assert!((norm - 1.0).abs() < 1e-9);
+}
@@ -0,0 +1,225 @@
+//! Automatic thread titles: after the first reply of a thread, the title model writes a
+//! short title and a one-paragraph summary into the thread's `thread.json`. The job runs
+//! in the background once the reply is stored, so the chat stream never waits for it.
+//! Threads with `metadata.auto_title` set to `false` are left alone.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tauri::{Emitter, Runtime};
+
+use super::constants::{
+    THREAD_UPDATED_EVENT, TITLE_INSTRUCTIONS, TITLE_MAX_CHARS, TITLE_MAX_TOKENS,
+};
+use super::context::transcript;
+use super::recorder::history_from_thread;
+use super::retry::ChatTarget;
+use super::structured::{JsonSchemaFormat, ResponseFormat};
+use super::{ChatMessage, ChatRequest, ChatService, GenerationParams};
+use crate::core::threads::helpers::{
+    get_lock_for_thread, read_messages_from_file, update_thread_metadata,
+};
+use crate::core::threads::utils::get_thread_metadata_path;
+
+/// Title and summary written by the title model
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ThreadTitle {
+    pub title: String,
+    pub summary: String,
+}
+
+/// Schema of the title model's reply
+pub fn title_response_format() -> ResponseFormat {
+    ResponseFormat::JsonSchema {
+        json_schema: JsonSchemaFormat {
+            name: "thread_title".to_string(),
+            description: None,
+            schema: json!({
+                "type": "object",
+                "properties": {
+                    "title": { "type": "string" },
+                    "summary": { "type": "string" }
+                },
+                "required": ["title", "summary"]
+            }),
+            strict: None,
+        },
+    }
+}
+
+/// Whether the thread allows a generated title: not turned off with
+/// `metadata.auto_title` and not titled already
+pub fn auto_title_enabled(thread: &Value) -> bool {
+    let flag = |key: &str| {
+        thread
+            .pointer(&format!("/metadata/{}", key))
+            .and_then(|v| v.as_bool())
+    };
+    flag("auto_title") != Some(false) && flag("title_generated") != Some(true)
+}
+
+/// Whether a title is due: the thread allows one and has exactly one completed reply
+pub fn needs_title(thread: &Value, messages: &[Value]) -> bool {
+    let replies = messages
+        .iter()
+        .filter(|message| {
+            message.get("role").and_then(|v| v.as_str()) == Some("assistant")
+                && message.get("status").and_then(|v| v.as_str()) == Some("ready")
+        })
+        .count();
+    auto_title_enabled(thread) && replies == 1
+}
+
+/// The request asking `target` for the title of a conversation
+pub fn title_request(history: &[ChatMessage], target: &ChatTarget) -> ChatRequest {
+    ChatRequest {
+        prompt: transcript(history),
+        provider: target.provider.clone(),
+        model: target.model.clone(),
+        stream_id: None,
+        chat_history: None,
+        attachments: Vec::new(),
+        supports_vision: Some(false),
+        instructions: Some(TITLE_INSTRUCTIONS.to_string()),
+        params: GenerationParams {
+            max_tokens: Some(TITLE_MAX_TOKENS),
+            ..Default::default()
+        },
+        provider_config: target.provider_config.clone(),
+        context: None,
+        retry: None,
+        fallbacks: Vec::new(),
+        response_format: Some(title_response_format()),
+        validation_retries: Some(1),
+        tools: Vec::new(),
+        tool_choice: None,
+    }
+}
+
+/// Reads the title model's reply, trimming quotes and cutting the title to
+/// `TITLE_MAX_CHARS` characters. `None` when the title is empty.
+pub fn parse_title(value: &Value) -> Option<ThreadTitle> {
+    let title = value.get("title")?.as_str()?;
+    let title = title
+        .trim()
+        .trim_matches(|c: char| c == '"' || c == '\'')
+        .trim();
+    if title.is_empty() {
+        return None;
+    }
+    let title = match title.char_indices().nth(TITLE_MAX_CHARS) {
+        Some((end, _)) => title[..end].trim_end().to_string(),
+        None => title.to_string(),
+    };
+    let summary = value
+        .get("summary")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+    Some(ThreadTitle { title, summary })
+}
+
+/// Writes the title and summary into a thread object; `now` is in seconds
+pub fn apply_title(thread: &mut Value, title: &ThreadTitle, now: u64) {
+    let Some(thread) = thread.as_object_mut() else {
+        return;
+    };
+    thread.insert("title".to_string(), json!(title.title));
+    thread.insert("updated".to_string(), json!(now));
+    let metadata = thread
+        .entry("metadata")
+        .or_insert_with(|| Value::Object(Map::new()));
+    if !metadata.is_object() {
+        *metadata = Value::Object(Map::new());
+    }
+    if let Some(metadata) = metadata.as_object_mut() {
+        metadata.insert("summary".to_string(), json!(title.summary));
+        metadata.insert("title_generated".to_string(), json!(true));
+    }
+}
+
+fn read_thread<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    thread_id: &str,
+) -> Result<Value, String> {
+    let path = get_thread_metadata_path(app_handle.clone(), thread_id);
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read thread {}: {}", thread_id, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("Invalid thread {}: {}", thread_id, e))
+}
+
+/// Asks `target` for the thread's title and summary when one is due, writes them to
+/// `thread.json` and emits `thread-updated`. Returns `None` when no title was due.
+pub async fn generate_thread_title<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    chat_service: &ChatService,
+    thread_id: &str,
+    target: &ChatTarget,
+) -> Result<Option<ThreadTitle>, String> {
+    let thread = read_thread(app_handle, thread_id)?;
+    let messages = {
+        let lock = get_lock_for_thread(thread_id).await;
+        let _guard = lock.lock().await;
+        read_messages_from_file(app_handle.clone(), thread_id)?
+    };
+    if !needs_title(&thread, &messages) {
+        return Ok(None);
+    }
+
+    let request = title_request(&history_from_thread(&messages), target);
+    let response = chat_service
+        .chat_non_streaming(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    let title = response
+        .structured
+        .and_then(|output| output.value)
+        .as_ref()
+        .and_then(parse_title)
+        .ok_or_else(|| format!("{}/{} returned no title", target.provider, target.model))?;
+
+    {
+        // Held across the read and the write so concurrent thread updates are not lost
+        let lock = get_lock_for_thread(thread_id).await;
+        let _guard = lock.lock().await;
+        // The thread may have been renamed or opted out while the title was generated
+        let mut current = read_thread(app_handle, thread_id)?;
+        if !auto_title_enabled(&current) || current.get("title") != thread.get("title") {
+            return Ok(None);
+        }
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        apply_title(&mut current, &title, now);
+        update_thread_metadata(app_handle.clone(), thread_id, &current)?;
+    }
+
+    if let Err(e) = app_handle.emit(
+        THREAD_UPDATED_EVENT,
+        json!({
+            "thread_id": thread_id,
+            "title": title.title,
+            "summary": title.summary,
+        }),
+    ) {
+        log::warn!("Failed to emit thread update: {}", e);
+    }
+    Ok(Some(title))
+}
+
+/// Runs `generate_thread_title` in the background, logging failures
+pub fn spawn_title_job<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    chat_service: ChatService,
+    thread_id: String,
+    target: ChatTarget,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        match generate_thread_title(&app_handle, &chat_service, &thread_id, &target).await {
+            Ok(Some(title)) => log::info!("Thread {} titled '{}'", thread_id, title.title),
+            Ok(None) => {}
+            Err(e) => log::warn!("Failed to generate a title for thread {}: {}", thread_id, e),
+        }
+    })
+}
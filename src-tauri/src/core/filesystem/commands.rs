@@ -123,8 +123,8 @@ pub fn readdir_sync<R: Runtime>(
 }
 
 #[tauri::command]
-pub fn write_yaml(
-    app: tauri::AppHandle,
+pub fn write_yaml<R: Runtime>(
+    app: tauri::AppHandle<R>,
     data: serde_json::Value,
     save_path: &str,
 ) -> Result<(), String> {
@@ -145,7 +145,10 @@ pub fn write_yaml(
 }
 
 #[tauri::command]
-pub fn read_yaml(app: tauri::AppHandle, path: &str) -> Result<serde_json::Value, String> {
+pub fn read_yaml<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    path: &str,
+) -> Result<serde_json::Value, String> {
     let jan_data_folder = crate::core::app::commands::get_jan_data_folder_path(app.clone());
     let path = jan_utils::normalize_path(&jan_data_folder.join(path));
     if !path.starts_with(&jan_data_folder) {
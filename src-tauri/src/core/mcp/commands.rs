@@ -1,12 +1,14 @@
-use rmcp::model::{CallToolRequestParam, CallToolResult};
+use rmcp::model::CallToolResult;
 use serde_json::{Map, Value};
 use tauri::{AppHandle, Emitter, Runtime, State};
 use tokio::sync::oneshot;
-use tokio::time::timeout;
 
 use super::{
-    constants::{DEFAULT_MCP_CONFIG, MCP_TOOL_CALL_TIMEOUT},
-    helpers::{restart_active_mcp_servers, start_mcp_server_with_restart, stop_mcp_servers},
+    constants::DEFAULT_MCP_CONFIG,
+    helpers::{
+        call_mcp_tool, list_tools_with_server, restart_active_mcp_servers,
+        start_mcp_server_with_restart, stop_mcp_servers,
+    },
 };
 use crate::core::{app::commands::get_jan_data_folder_path, state::AppState};
 use crate::core::{
@@ -149,42 +151,7 @@ pub async fn get_tools(
     state: State<'_, AppState>,
     server_name: Option<String>,
 ) -> Result<Vec<ToolWithServer>, String> {
-    let servers = state.mcp_servers.lock().await;
-    let mut all_tools: Vec<ToolWithServer> = Vec::new();
-
-    for (name, service) in servers.iter() {
-        // If server_name is provided, only process that specific server
-        if let Some(ref target_server) = server_name {
-            if name != target_server {
-                continue;
-            }
-        }
-
-        // List tools with timeout
-        let tools_future = service.list_all_tools();
-        let tools = match timeout(MCP_TOOL_CALL_TIMEOUT, tools_future).await {
-            Ok(result) => result.map_err(|e| e.to_string())?,
-            Err(_) => {
-                log::warn!(
-                    "Listing tools timed out after {} seconds for server {}",
-                    MCP_TOOL_CALL_TIMEOUT.as_secs(),
-                    name
-                );
-                continue; // Skip this server and continue with others
-            }
-        };
-
-        for tool in tools {
-            all_tools.push(ToolWithServer {
-                name: tool.name.to_string(),
-                description: tool.description.as_ref().map(|d| d.to_string()),
-                input_schema: serde_json::Value::Object((*tool.input_schema).clone()),
-                server: name.clone(),
-            });
-        }
-    }
-
-    Ok(all_tools)
+    list_tools_with_server(&state.mcp_servers, server_name.as_deref()).await
 }
 
 /// Calls a tool on an MCP server by name with optional arguments
@@ -219,65 +186,27 @@ pub async fn call_tool(
         cancellations.insert(token.clone(), cancel_tx);
     }
 
-    let servers = state.mcp_servers.lock().await;
-
-    // Iterate through servers and find the first one that contains the tool
-    for (_, service) in servers.iter() {
-        let tools = match service.list_all_tools().await {
-            Ok(tools) => tools,
-            Err(_) => continue, // Skip this server if we can't list tools
-        };
+    let tool_call = call_mcp_tool(&state.mcp_servers, &tool_name, arguments);
 
-        if !tools.iter().any(|t| t.name == tool_name) {
-            continue; // Tool not found in this server, try next
-        }
-
-        println!("Found tool {} in server", tool_name);
-
-        // Call the tool with timeout and cancellation support
-        let tool_call = service.call_tool(CallToolRequestParam {
-            name: tool_name.clone().into(),
-            arguments,
-        });
-
-        // Race between timeout, tool call, and cancellation
-        let result = if cancellation_token.is_some() {
-            tokio::select! {
-                result = timeout(MCP_TOOL_CALL_TIMEOUT, tool_call) => {
-                    match result {
-                        Ok(call_result) => call_result.map_err(|e| e.to_string()),
-                        Err(_) => Err(format!(
-                            "Tool call '{}' timed out after {} seconds",
-                            tool_name,
-                            MCP_TOOL_CALL_TIMEOUT.as_secs()
-                        )),
-                    }
-                }
-                _ = cancel_rx => {
-                    Err(format!("Tool call '{}' was cancelled", tool_name))
-                }
+    // Race between the tool call (which has its own timeout) and cancellation
+    let result = if cancellation_token.is_some() {
+        tokio::select! {
+            result = tool_call => result,
+            _ = cancel_rx => {
+                Err(format!("Tool call '{}' was cancelled", tool_name))
             }
-        } else {
-            match timeout(MCP_TOOL_CALL_TIMEOUT, tool_call).await {
-                Ok(call_result) => call_result.map_err(|e| e.to_string()),
-                Err(_) => Err(format!(
-                    "Tool call '{}' timed out after {} seconds",
-                    tool_name,
-                    MCP_TOOL_CALL_TIMEOUT.as_secs()
-                )),
-            }
-        };
-
-        // Clean up cancellation token
-        if let Some(token) = &cancellation_token {
-            let mut cancellations = state.tool_call_cancellations.lock().await;
-            cancellations.remove(token);
         }
+    } else {
+        tool_call.await
+    };
 
-        return result;
+    // Clean up cancellation token
+    if let Some(token) = &cancellation_token {
+        let mut cancellations = state.tool_call_cancellations.lock().await;
+        cancellations.remove(token);
     }
 
-    Err(format!("Tool {} not found", tool_name))
+    result
 }
 
 /// Cancels a running tool call by its cancellation token
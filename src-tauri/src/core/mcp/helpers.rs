@@ -1,12 +1,13 @@
 use rmcp::{
-    model::{ClientCapabilities, ClientInfo, Implementation},
+    model::{CallToolRequestParam, CallToolResult, ClientCapabilities, ClientInfo, Implementation},
+    service::Peer,
     transport::{
         streamable_http_client::StreamableHttpClientTransportConfig, SseClientTransport,
         StreamableHttpClientTransport, TokioChildProcess,
     },
-    ServiceExt,
+    RoleClient, ServiceExt,
 };
-use serde_json::Value;
+use serde_json::{Map, Value};
 use std::{collections::HashMap, env, process::Stdio, sync::Arc, time::Duration};
 use tauri::{AppHandle, Emitter, Manager, Runtime, State};
 use tauri_plugin_http::reqwest;
@@ -19,10 +20,11 @@ use tokio::{
 
 use super::constants::{
     MCP_BACKOFF_MULTIPLIER, MCP_BASE_RESTART_DELAY_MS, MCP_MAX_RESTART_DELAY_MS,
+    MCP_TOOL_CALL_TIMEOUT,
 };
 use crate::core::{
     app::commands::get_jan_data_folder_path,
-    mcp::models::McpServerConfig,
+    mcp::models::{McpServerConfig, ToolWithServer},
     state::{AppState, RunningServiceEnum, SharedMcpServers},
 };
 use jan_utils::can_override_npx;
@@ -842,6 +844,114 @@ pub async fn stop_mcp_servers(servers_state: SharedMcpServers) -> Result<(), Str
     Ok(())
 }
 
+/// Clones the request handles of the connected servers, or only of `server_name` when
+/// given, so that requests are awaited without holding the servers lock
+async fn server_peers(
+    servers_state: &SharedMcpServers,
+    server_name: Option<&str>,
+) -> Vec<(String, Peer<RoleClient>)> {
+    let servers = servers_state.lock().await;
+    servers
+        .iter()
+        .filter(|(name, _)| server_name.is_none() || server_name == Some(name.as_str()))
+        .map(|(name, service)| (name.clone(), service.peer()))
+        .collect()
+}
+
+/// Calls `tool_name` through `peer`, failing after `MCP_TOOL_CALL_TIMEOUT`
+async fn call_peer_tool(
+    peer: &Peer<RoleClient>,
+    tool_name: &str,
+    arguments: Option<Map<String, Value>>,
+) -> Result<CallToolResult, String> {
+    let tool_call = peer.call_tool(CallToolRequestParam {
+        name: tool_name.to_string().into(),
+        arguments,
+    });
+    match timeout(MCP_TOOL_CALL_TIMEOUT, tool_call).await {
+        Ok(call_result) => call_result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!(
+            "Tool call '{}' timed out after {} seconds",
+            tool_name,
+            MCP_TOOL_CALL_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+/// Lists the tools of every connected server, or only of `server_name` when given.
+/// Servers that do not answer within `MCP_TOOL_CALL_TIMEOUT` are skipped.
+pub async fn list_tools_with_server(
+    servers_state: &SharedMcpServers,
+    server_name: Option<&str>,
+) -> Result<Vec<ToolWithServer>, String> {
+    let mut all_tools: Vec<ToolWithServer> = Vec::new();
+
+    for (name, peer) in server_peers(servers_state, server_name).await {
+        // List tools with timeout
+        let tools = match timeout(MCP_TOOL_CALL_TIMEOUT, peer.list_all_tools()).await {
+            Ok(result) => result.map_err(|e| e.to_string())?,
+            Err(_) => {
+                log::warn!(
+                    "Listing tools timed out after {} seconds for server {}",
+                    MCP_TOOL_CALL_TIMEOUT.as_secs(),
+                    name
+                );
+                continue; // Skip this server and continue with others
+            }
+        };
+
+        for tool in tools {
+            all_tools.push(ToolWithServer {
+                name: tool.name.to_string(),
+                description: tool.description.as_ref().map(|d| d.to_string()),
+                input_schema: serde_json::Value::Object((*tool.input_schema).clone()),
+                server: name.clone(),
+            });
+        }
+    }
+
+    Ok(all_tools)
+}
+
+/// Calls a tool on the first connected server that provides it. The call fails after
+/// `MCP_TOOL_CALL_TIMEOUT`; cancellation is left to the caller.
+pub async fn call_mcp_tool(
+    servers_state: &SharedMcpServers,
+    tool_name: &str,
+    arguments: Option<Map<String, Value>>,
+) -> Result<CallToolResult, String> {
+    for (name, peer) in server_peers(servers_state, None).await {
+        let tools = match peer.list_all_tools().await {
+            Ok(tools) => tools,
+            Err(_) => continue, // Skip this server if we can't list tools
+        };
+        if !tools.iter().any(|t| t.name == tool_name) {
+            continue; // Tool not found in this server, try next
+        }
+
+        log::debug!("Calling tool {} on MCP server {}", tool_name, name);
+        return call_peer_tool(&peer, tool_name, arguments).await;
+    }
+
+    Err(format!("Tool {} not found", tool_name))
+}
+
+/// Calls a tool on the server `server_name`, for callers that already know which
+/// server provides it. The call fails after `MCP_TOOL_CALL_TIMEOUT`; cancellation is
+/// left to the caller.
+pub async fn call_server_tool(
+    servers_state: &SharedMcpServers,
+    server_name: &str,
+    tool_name: &str,
+    arguments: Option<Map<String, Value>>,
+) -> Result<CallToolResult, String> {
+    let (_, peer) = server_peers(servers_state, Some(server_name))
+        .await
+        .pop()
+        .ok_or_else(|| format!("MCP server {} is not running", server_name))?;
+    call_peer_tool(&peer, tool_name, arguments).await
+}
+
 /// Store active server configuration for restart purposes
 pub async fn store_active_server_config(
     active_servers_state: &Arc<Mutex<HashMap<String, Value>>>,
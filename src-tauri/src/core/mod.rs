@@ -4,6 +4,8 @@ pub mod downloads;
 pub mod extensions;
 pub mod filesystem;
 pub mod mcp;
+pub mod prompts;
+pub mod runtime;
 pub mod server;
 pub mod setup;
 pub mod state;
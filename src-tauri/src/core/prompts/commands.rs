@@ -0,0 +1,110 @@
+use serde_json::{Map, Value};
+use std::fs;
+use tauri::Runtime;
+
+use super::constants::{PROMPTS_DIR, PROMPT_FILE_EXTENSION};
+use super::helpers::{
+    load_template, render_template_by_id, save_template, template_path, validate_template,
+};
+use super::models::PromptTemplate;
+use crate::core::app::commands::get_jan_data_folder_path;
+
+fn now_secs() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Lists the prompt templates in the data folder, sorted by name.
+/// Files that cannot be read as a template are skipped.
+#[tauri::command]
+pub async fn list_prompt_templates<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+) -> Result<Vec<PromptTemplate>, String> {
+    let dir = get_jan_data_folder_path(app_handle.clone()).join(PROMPTS_DIR);
+    let mut templates = Vec::new();
+    if !dir.exists() {
+        return Ok(templates);
+    }
+
+    for entry in fs::read_dir(&dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(PROMPT_FILE_EXTENSION) {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        match load_template(app_handle.clone(), id) {
+            Ok(template) => templates.push(template),
+            Err(e) => log::warn!("Skipping prompt template {}: {}", path.display(), e),
+        }
+    }
+    templates.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+    Ok(templates)
+}
+
+#[tauri::command]
+pub async fn get_prompt_template<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    id: String,
+) -> Result<PromptTemplate, String> {
+    load_template(app_handle, &id)
+}
+
+/// Stores a new prompt template; a new id is assigned when `id` is empty
+#[tauri::command]
+pub async fn create_prompt_template<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    mut template: PromptTemplate,
+) -> Result<PromptTemplate, String> {
+    if template.id.is_empty() {
+        template.id = uuid::Uuid::new_v4().to_string();
+    }
+    validate_template(&template)?;
+    let path = get_jan_data_folder_path(app_handle.clone()).join(template_path(&template.id)?);
+    if path.exists() {
+        return Err(format!("Prompt template '{}' exists", template.id));
+    }
+    template.created = now_secs();
+    template.updated = template.created;
+    save_template(app_handle, &template)?;
+    Ok(template)
+}
+
+/// Replaces a stored prompt template, keeping its creation time
+#[tauri::command]
+pub async fn update_prompt_template<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    mut template: PromptTemplate,
+) -> Result<PromptTemplate, String> {
+    let existing = load_template(app_handle.clone(), &template.id)?;
+    validate_template(&template)?;
+    template.created = existing.created;
+    template.updated = now_secs();
+    save_template(app_handle, &template)?;
+    Ok(template)
+}
+
+#[tauri::command]
+pub async fn delete_prompt_template<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    id: String,
+) -> Result<(), String> {
+    let path = get_jan_data_folder_path(app_handle).join(template_path(&id)?);
+    if !path.exists() {
+        return Err(format!("Prompt template '{}' not found", id));
+    }
+    fs::remove_file(path).map_err(|e| e.to_string())
+}
+
+/// Renders a stored template with `variables`, applying defaults and checking types
+#[tauri::command]
+pub async fn render_prompt<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    template_id: String,
+    variables: Option<Map<String, Value>>,
+) -> Result<String, String> {
+    render_template_by_id(app_handle, &template_id, &variables.unwrap_or_default())
+}
@@ -0,0 +1,3 @@
+// Prompt Template Constants
+pub const PROMPTS_DIR: &str = "prompts";
+pub const PROMPT_FILE_EXTENSION: &str = "yaml";
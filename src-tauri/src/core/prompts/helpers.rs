@@ -0,0 +1,199 @@
+use serde_json::{Map, Value};
+use std::fs;
+use tauri::Runtime;
+
+use super::constants::{PROMPTS_DIR, PROMPT_FILE_EXTENSION};
+use super::models::{PromptTemplate, TemplateVariable, VariableType};
+use crate::core::app::commands::get_jan_data_folder_path;
+use crate::core::filesystem::commands::{read_yaml, write_yaml};
+
+/// A variable or template id: letters, digits, `_` and `-`
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Path of a template file, relative to the data folder
+pub fn template_path(id: &str) -> Result<String, String> {
+    if !is_valid_name(id) {
+        return Err(format!("Invalid prompt template id '{}'", id));
+    }
+    Ok(format!("{}/{}.{}", PROMPTS_DIR, id, PROMPT_FILE_EXTENSION))
+}
+
+/// Splits a template into literal text and placeholder names, in order.
+/// `{{` without a valid name and closing `}}` is kept as text.
+fn segments(template: &str) -> Vec<(bool, &str)> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        let name = after[..end].trim();
+        if is_valid_name(name) {
+            segments.push((false, &rest[..start]));
+            segments.push((true, name));
+        } else {
+            segments.push((false, &rest[..start + 2 + end + 2]));
+        }
+        rest = &after[end + 2..];
+    }
+    segments.push((false, rest));
+    segments
+}
+
+/// Names of the placeholders of a template, without duplicates
+pub fn placeholders(template: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for (is_placeholder, text) in segments(template) {
+        if is_placeholder && !names.iter().any(|name| name == text) {
+            names.push(text.to_string());
+        }
+    }
+    names
+}
+
+/// Checks the names, defaults and options of a template's variables
+pub fn validate_template(template: &PromptTemplate) -> Result<(), String> {
+    if template.name.trim().is_empty() {
+        return Err("Prompt template name is empty".to_string());
+    }
+    let used = placeholders(&template.template);
+    for (index, variable) in template.variables.iter().enumerate() {
+        if !is_valid_name(&variable.name) {
+            return Err(format!("Invalid variable name '{}'", variable.name));
+        }
+        if !used.contains(&variable.name) {
+            return Err(format!(
+                "Variable '{}' is not used in the template",
+                variable.name
+            ));
+        }
+        if template.variables[..index]
+            .iter()
+            .any(|other| other.name == variable.name)
+        {
+            return Err(format!("Variable '{}' is declared twice", variable.name));
+        }
+        if let Some(default) = &variable.default {
+            coerce_value(variable, default)
+                .map_err(|e| format!("Invalid default of '{}': {}", variable.name, e))?;
+        }
+    }
+    Ok(())
+}
+
+fn type_name(kind: VariableType) -> &'static str {
+    match kind {
+        VariableType::String => "a string",
+        VariableType::Number => "a number",
+        VariableType::Integer => "an integer",
+        VariableType::Boolean => "a boolean",
+    }
+}
+
+/// Text of a variable value, checked against the variable's type and options
+fn coerce_value(variable: &TemplateVariable, value: &Value) -> Result<String, String> {
+    let text = match (variable.kind, value) {
+        (VariableType::String, Value::String(text)) => text.clone(),
+        (VariableType::String, Value::Number(_) | Value::Bool(_)) => value.to_string(),
+        (VariableType::Number, Value::Number(number)) => number.to_string(),
+        (VariableType::Number, Value::String(text)) => text
+            .trim()
+            .parse::<f64>()
+            .map(|_| text.trim().to_string())
+            .map_err(|_| format!("'{}' is not a number", text))?,
+        (VariableType::Integer, Value::Number(number)) if number.as_i64().is_some() => {
+            number.to_string()
+        }
+        (VariableType::Integer, Value::String(text)) => text
+            .trim()
+            .parse::<i64>()
+            .map(|number| number.to_string())
+            .map_err(|_| format!("'{}' is not an integer", text))?,
+        (VariableType::Boolean, Value::Bool(flag)) => flag.to_string(),
+        (VariableType::Boolean, Value::String(text)) => match text.trim() {
+            "true" | "false" => text.trim().to_string(),
+            _ => return Err(format!("'{}' is not a boolean", text)),
+        },
+        (kind, value) => return Err(format!("expected {}, got {}", type_name(kind), value)),
+    };
+    if let Some(options) = &variable.options {
+        if !options.contains(&text) {
+            return Err(format!("'{}' is not one of {}", text, options.join(", ")));
+        }
+    }
+    Ok(text)
+}
+
+/// Fills in the placeholders of a template. Values are checked against the declared
+/// types; missing values take the default, and a missing value without one is an error.
+pub fn render_template(
+    template: &PromptTemplate,
+    variables: &Map<String, Value>,
+) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.template.len());
+    for (is_placeholder, text) in segments(&template.template) {
+        if !is_placeholder {
+            rendered.push_str(text);
+            continue;
+        }
+        let declared = template.variables.iter().find(|v| v.name == text);
+        let variable = declared.cloned().unwrap_or_else(|| TemplateVariable {
+            name: text.to_string(),
+            kind: VariableType::String,
+            default: None,
+            description: None,
+            options: None,
+        });
+        let value = variables
+            .get(text)
+            .filter(|value| !value.is_null())
+            .or(variable.default.as_ref())
+            .ok_or_else(|| format!("Missing variable '{}'", text))?;
+        let value = coerce_value(&variable, value)
+            .map_err(|e| format!("Invalid variable '{}': {}", text, e))?;
+        rendered.push_str(&value);
+    }
+    Ok(rendered)
+}
+
+/// Reads a stored template
+pub fn load_template<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    id: &str,
+) -> Result<PromptTemplate, String> {
+    let path = template_path(id)?;
+    let data = read_yaml(app_handle, &path)
+        .map_err(|e| format!("Prompt template '{}' not found: {}", id, e))?;
+    let mut template: PromptTemplate = serde_json::from_value(data)
+        .map_err(|e| format!("Invalid prompt template '{}': {}", id, e))?;
+    template.id = id.to_string();
+    Ok(template)
+}
+
+/// Writes a template to `prompts/<id>.yaml`, creating the folder if needed
+pub fn save_template<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    template: &PromptTemplate,
+) -> Result<(), String> {
+    let path = template_path(&template.id)?;
+    let dir = get_jan_data_folder_path(app_handle.clone()).join(PROMPTS_DIR);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let data = serde_json::to_value(template).map_err(|e| e.to_string())?;
+    write_yaml(app_handle, data, &path)
+}
+
+/// Renders the stored template `id` with `variables`
+pub fn render_template_by_id<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    id: &str,
+    variables: &Map<String, Value>,
+) -> Result<String, String> {
+    let template = load_template(app_handle, id)?;
+    render_template(&template, variables)
+}
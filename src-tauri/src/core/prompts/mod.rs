@@ -0,0 +1,17 @@
+/*!
+   Prompt Template Module
+
+   Reusable prompts stored as YAML files under `prompts/` in the data folder, one file per
+   template. Templates contain `{{variable}}` placeholders; declared variables carry a
+   type and an optional default, and are checked when the template is rendered.
+   Chat requests can name a template with `template_id` and `variables`, so the prompt is
+   rendered by the backend before it is sent.
+*/
+
+pub mod commands;
+mod constants;
+pub mod helpers;
+pub mod models;
+
+#[cfg(test)]
+mod tests;
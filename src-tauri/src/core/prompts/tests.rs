@@ -0,0 +1,145 @@
+use super::commands::*;
+use super::helpers::*;
+use super::models::*;
+use crate::core::app::commands::get_jan_data_folder_path;
+use serde_json::{json, Map, Value};
+use tauri::test::mock_app;
+
+fn variables(value: Value) -> Map<String, Value> {
+    value.as_object().cloned().unwrap_or_default()
+}
+
+fn review_template(id: &str) -> PromptTemplate {
+    serde_json::from_value(json!({
+        "id": id,
+        "name": "Code review",
+        "template": "Review this {{ language }} code in at most {{words}} words, strict: {{strict}}.\n{{code}}",
+        "variables": [
+            { "name": "language", "options": ["rust", "python"], "default": "rust" },
+            { "name": "words", "type": "integer", "default": 100 },
+            { "name": "strict", "type": "boolean", "default": false },
+            { "name": "code" }
+        ]
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_placeholders() {
+    assert_eq!(
+        placeholders("{{a}} {{ b }} {{a}} {{not valid}} {{c"),
+        vec!["a", "b"]
+    );
+}
+
+#[test]
+fn test_render_template_with_defaults_and_types() {
+    let template = review_template("review");
+    let rendered = render_template(
+        &template,
+        &variables(json!({ "code": "fn main() {}", "words": "50" })),
+    )
+    .unwrap();
+    assert_eq!(
+        rendered,
+        "Review this rust code in at most 50 words, strict: false.\nfn main() {}"
+    );
+
+    // Text that is not a placeholder is kept as-is
+    let literal = PromptTemplate {
+        template: "Use {{ two words }} and {{}} here".to_string(),
+        variables: Vec::new(),
+        ..template.clone()
+    };
+    assert_eq!(
+        render_template(&literal, &Map::new()).unwrap(),
+        "Use {{ two words }} and {{}} here"
+    );
+}
+
+#[test]
+fn test_render_template_errors() {
+    let template = review_template("review");
+    let missing = render_template(&template, &Map::new()).unwrap_err();
+    assert!(missing.contains("Missing variable 'code'"));
+
+    let wrong_type = render_template(
+        &template,
+        &variables(json!({ "code": "x", "words": "many" })),
+    )
+    .unwrap_err();
+    assert!(wrong_type.contains("'words'"));
+
+    let wrong_option = render_template(
+        &template,
+        &variables(json!({ "code": "x", "language": "go" })),
+    )
+    .unwrap_err();
+    assert!(wrong_option.contains("not one of rust, python"));
+}
+
+#[test]
+fn test_validate_template() {
+    assert!(validate_template(&review_template("review")).is_ok());
+
+    let mut unused = review_template("review");
+    unused.variables.push(TemplateVariable {
+        name: "tone".to_string(),
+        kind: VariableType::String,
+        default: None,
+        description: None,
+        options: None,
+    });
+    assert!(validate_template(&unused).is_err());
+
+    let mut bad_default = review_template("review");
+    bad_default.variables[1].default = Some(json!("lots"));
+    assert!(validate_template(&bad_default).is_err());
+
+    assert!(template_path("../secrets").is_err());
+}
+
+#[tokio::test]
+async fn test_prompt_template_crud() {
+    let app = mock_app();
+    let id = format!("test-{}", uuid::Uuid::new_v4());
+
+    let created = create_prompt_template(app.handle().clone(), review_template(&id))
+        .await
+        .unwrap();
+    assert!(created.created > 0);
+    assert!(get_jan_data_folder_path(app.handle().clone())
+        .join(format!("prompts/{}.yaml", id))
+        .exists());
+    assert!(
+        create_prompt_template(app.handle().clone(), review_template(&id))
+            .await
+            .is_err()
+    );
+
+    let mut changed = created.clone();
+    changed.name = "Strict review".to_string();
+    let updated = update_prompt_template(app.handle().clone(), changed)
+        .await
+        .unwrap();
+    assert_eq!(updated.created, created.created);
+
+    let listed = list_prompt_templates(app.handle().clone()).await.unwrap();
+    assert!(listed
+        .iter()
+        .any(|template| template.id == id && template.name == "Strict review"));
+
+    let rendered = render_prompt(
+        app.handle().clone(),
+        id.clone(),
+        Some(variables(json!({ "code": "x = 1", "language": "python" }))),
+    )
+    .await
+    .unwrap();
+    assert!(rendered.starts_with("Review this python code"));
+
+    delete_prompt_template(app.handle().clone(), id.clone())
+        .await
+        .unwrap();
+    assert!(get_prompt_template(app.handle().clone(), id).await.is_err());
+}
@@ -0,0 +1,31 @@
+use tauri::State;
+
+use super::helpers::{load_model_session, unload_model_session};
+use super::models::LoadModelRequest;
+use crate::core::server::proxy::SessionInfo;
+use crate::core::state::AppState;
+
+/// Starts an inference server for a model and makes it routable through the API server.
+/// Returns the existing session if the model is already loaded.
+#[tauri::command]
+pub async fn load_model(
+    state: State<'_, AppState>,
+    request: LoadModelRequest,
+) -> Result<SessionInfo, String> {
+    let api_secret = state.app_token.clone().unwrap_or_default();
+    load_model_session(state.model_sessions.clone(), &api_secret, request).await
+}
+
+/// Stops the inference server of a loaded model.
+#[tauri::command]
+pub async fn unload_model(state: State<'_, AppState>, model_id: String) -> Result<(), String> {
+    unload_model_session(state.model_sessions.clone(), &model_id).await?;
+    Ok(())
+}
+
+/// Lists the currently loaded model sessions.
+#[tauri::command]
+pub async fn list_sessions(state: State<'_, AppState>) -> Result<Vec<SessionInfo>, String> {
+    let sessions = state.model_sessions.lock().await;
+    Ok(sessions.values().map(|s| s.info.clone()).collect())
+}
@@ -0,0 +1,10 @@
+use std::time::Duration;
+
+// Model Runtime Constants
+pub const DEFAULT_SERVER_COMMAND: &str = "llama-server";
+pub const DEFAULT_HEALTH_PATH: &str = "/health";
+pub const MODEL_SERVER_HOST: &str = "127.0.0.1";
+/// Environment variable llama-server reads its API key from, set for every model server
+pub const LLAMA_API_KEY_ENV: &str = "LLAMA_API_KEY";
+pub const MODEL_LOAD_TIMEOUT: Duration = Duration::from_secs(120);
+pub const MODEL_HEALTH_CHECK_INTERVAL: Duration = Duration::from_millis(500);
@@ -0,0 +1,336 @@
+use jan_utils::{
+    generate_api_key, generate_random_port, setup_library_path, setup_windows_process_flags,
+};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+use super::constants::{
+    DEFAULT_HEALTH_PATH, DEFAULT_SERVER_COMMAND, LLAMA_API_KEY_ENV, MODEL_HEALTH_CHECK_INTERVAL,
+    MODEL_LOAD_TIMEOUT, MODEL_SERVER_HOST,
+};
+use super::models::LoadModelRequest;
+use crate::core::server::proxy::{BackendSession, SessionInfo};
+use crate::core::state::SharedModelSessions;
+
+/// Resolves the inference server binary.
+/// A binary bundled next to the app executable wins over one found on PATH.
+pub fn resolve_server_command(command: Option<&str>) -> PathBuf {
+    let command = command.unwrap_or(DEFAULT_SERVER_COMMAND);
+    let path = PathBuf::from(command);
+    if path.components().count() > 1 {
+        return path;
+    }
+
+    let file_name = if cfg!(windows) && path.extension().is_none() {
+        format!("{}.exe", command)
+    } else {
+        command.to_string()
+    };
+    if let Some(bin_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|p| p.to_path_buf()))
+    {
+        let bundled = bin_dir.join(&file_name);
+        if bundled.exists() {
+            return bundled;
+        }
+    }
+
+    path
+}
+
+/// Replaces `{model_id}`, `{model_path}`, `{host}`, `{port}` and `{api_key}` in an argument
+pub fn substitute_placeholders(
+    arg: &str,
+    request: &LoadModelRequest,
+    port: u16,
+    api_key: &str,
+) -> String {
+    arg.replace("{model_id}", &request.model_id)
+        .replace("{model_path}", &request.model_path)
+        .replace("{host}", MODEL_SERVER_HOST)
+        .replace("{port}", &port.to_string())
+        .replace("{api_key}", api_key)
+}
+
+/// Builds the inference server argument list.
+/// The default llama-server gets its model, alias and address flags filled in; custom
+/// commands only receive the configured arguments. The API key is not an argument, so
+/// it does not show in the process list; see `LLAMA_API_KEY_ENV`.
+pub fn build_server_args(request: &LoadModelRequest, port: u16, api_key: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    if request.command.is_none() {
+        args.extend([
+            "-m".to_string(),
+            request.model_path.clone(),
+            "-a".to_string(),
+            request.model_id.clone(),
+            "--host".to_string(),
+            MODEL_SERVER_HOST.to_string(),
+            "--port".to_string(),
+            port.to_string(),
+        ]);
+    }
+    args.extend(
+        request
+            .args
+            .iter()
+            .map(|arg| substitute_placeholders(arg, request, port, api_key)),
+    );
+    args
+}
+
+/// Forwards a child process output stream to the app log
+fn forward_process_output<T>(stream: T, model_id: String)
+where
+    T: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stream).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            log::debug!("[{}] {}", model_id, line);
+        }
+    });
+}
+
+/// Polls the server health endpoint until it answers successfully
+///
+/// # Returns
+/// * `Err(String)` if the process exits or the server is not healthy before `timeout`
+pub async fn wait_for_server_health(
+    child: &mut Child,
+    port: u16,
+    api_key: &str,
+    health_path: &str,
+    timeout: Duration,
+) -> Result<(), String> {
+    let client = reqwest::Client::new();
+    let url = format!("http://{}:{}{}", MODEL_SERVER_HOST, port, health_path);
+    let started = Instant::now();
+
+    loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            return Err(format!(
+                "Model server exited during startup with {}",
+                status
+            ));
+        }
+
+        match client
+            .get(&url)
+            .bearer_auth(api_key)
+            .timeout(Duration::from_secs(2))
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => log::trace!("Health check on port {}: {}", port, response.status()),
+            Err(e) => log::trace!("Health check on port {} failed: {}", port, e),
+        }
+
+        if started.elapsed() >= timeout {
+            return Err(format!(
+                "Model server did not become healthy within {} seconds",
+                timeout.as_secs()
+            ));
+        }
+        sleep(MODEL_HEALTH_CHECK_INTERVAL).await;
+    }
+}
+
+// Per-model locks so overlapping loads of one model start a single server
+static MODEL_LOAD_LOCKS: Lazy<Mutex<HashMap<String, Arc<Mutex<()>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Ports picked by loads whose session is not registered yet
+static RESERVED_PORTS: Lazy<std::sync::Mutex<HashSet<u16>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashSet::new()));
+
+async fn get_lock_for_model(model_id: &str) -> Arc<Mutex<()>> {
+    let mut locks = MODEL_LOAD_LOCKS.lock().await;
+    locks
+        .entry(model_id.to_string())
+        .or_insert_with(|| Arc::new(Mutex::new(())))
+        .clone()
+}
+
+/// A port held for a starting model server, so that other loads do not pick it.
+/// Released when dropped, whether the load succeeded, failed or was cancelled.
+pub(crate) struct PortReservation {
+    pub port: u16,
+}
+
+impl PortReservation {
+    /// Reserves a free port that is neither in `used_ports` nor reserved by another load
+    pub(crate) fn new(used_ports: &HashSet<u16>) -> Result<Self, String> {
+        let mut reserved = RESERVED_PORTS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let taken: HashSet<u16> = used_ports.union(&reserved).copied().collect();
+        let port = generate_random_port(&taken)?;
+        reserved.insert(port);
+        Ok(Self { port })
+    }
+}
+
+impl Drop for PortReservation {
+    fn drop(&mut self) {
+        RESERVED_PORTS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.port);
+    }
+}
+
+/// Spawns an inference server for a model and registers it in the sessions map
+///
+/// # Arguments
+/// * `sessions` - Shared map of running model sessions
+/// * `api_secret` - Secret used to derive the per-model API key
+/// * `request` - Model and process configuration
+///
+/// # Returns
+/// * `Ok(SessionInfo)` of the new session, or of the existing one if the model is already loaded
+pub async fn load_model_session(
+    sessions: SharedModelSessions,
+    api_secret: &str,
+    request: LoadModelRequest,
+) -> Result<SessionInfo, String> {
+    // A second load of the model waits here, then finds the session of the first
+    let load_lock = get_lock_for_model(&request.model_id).await;
+    let _loading = load_lock.lock().await;
+
+    let reservation = {
+        let sessions_guard = sessions.lock().await;
+        if let Some(session) = sessions_guard
+            .values()
+            .find(|s| s.info.model_id == request.model_id)
+        {
+            log::info!("Model {} is already loaded", request.model_id);
+            return Ok(session.info.clone());
+        }
+        let used_ports: HashSet<u16> = sessions_guard
+            .values()
+            .map(|s| s.info.port as u16)
+            .collect();
+        PortReservation::new(&used_ports)?
+    };
+    let port = reservation.port;
+    let api_key = generate_api_key(request.model_id.clone(), api_secret.to_string())?;
+
+    let program = resolve_server_command(request.command.as_deref());
+    let mut cmd = Command::new(&program);
+    cmd.args(build_server_args(&request, port, &api_key));
+    cmd.envs(&request.envs);
+    cmd.env(LLAMA_API_KEY_ENV, &api_key);
+    setup_library_path(request.library_path.as_deref(), &mut cmd);
+    setup_windows_process_flags(&mut cmd);
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    cmd.kill_on_drop(true);
+
+    log::info!(
+        "Starting model server for {} on port {} using {}",
+        request.model_id,
+        port,
+        program.display()
+    );
+    let mut child = cmd.spawn().map_err(|e| {
+        log::error!("Failed to start model server {}: {}", program.display(), e);
+        format!("Failed to start model server {}: {}", program.display(), e)
+    })?;
+    let pid = child
+        .id()
+        .ok_or_else(|| "Model server exited immediately".to_string())? as i32;
+
+    if let Some(stdout) = child.stdout.take() {
+        forward_process_output(stdout, request.model_id.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_process_output(stderr, request.model_id.clone());
+    }
+
+    let health_path = request
+        .health_path
+        .as_deref()
+        .unwrap_or(DEFAULT_HEALTH_PATH);
+    let timeout = request
+        .timeout
+        .map(Duration::from_secs)
+        .unwrap_or(MODEL_LOAD_TIMEOUT);
+    if let Err(e) = wait_for_server_health(&mut child, port, &api_key, health_path, timeout).await {
+        log::error!("Failed to load model {}: {}", request.model_id, e);
+        let _ = child.kill().await;
+        return Err(e);
+    }
+
+    let info = SessionInfo {
+        pid,
+        port: port as i32,
+        model_id: request.model_id.clone(),
+        model_path: request.model_path.clone(),
+        api_key,
+    };
+
+    // Registered before the port reservation is dropped
+    sessions.lock().await.insert(
+        pid,
+        BackendSession {
+            child,
+            info: info.clone(),
+        },
+    );
+    log::info!(
+        "Model {} loaded (pid {}, port {})",
+        info.model_id,
+        info.pid,
+        info.port
+    );
+
+    Ok(info)
+}
+
+/// Stops the inference server for a model and removes its session
+pub async fn unload_model_session(
+    sessions: SharedModelSessions,
+    model_id: &str,
+) -> Result<SessionInfo, String> {
+    let session = {
+        let mut sessions_guard = sessions.lock().await;
+        let pid = sessions_guard
+            .iter()
+            .find(|(_, s)| s.info.model_id == model_id)
+            .map(|(pid, _)| *pid)
+            .ok_or_else(|| format!("Model {} is not loaded", model_id))?;
+        sessions_guard
+            .remove(&pid)
+            .ok_or_else(|| format!("Model {} is not loaded", model_id))?
+    };
+
+    let BackendSession { mut child, info } = session;
+    child.kill().await.map_err(|e| {
+        log::error!("Failed to stop model server {}: {}", info.pid, e);
+        format!("Failed to stop model server for {}: {}", model_id, e)
+    })?;
+    log::info!("Model {} unloaded (pid {})", info.model_id, info.pid);
+
+    Ok(info)
+}
+
+/// Stops every running model server
+pub async fn clean_up_model_sessions(sessions: SharedModelSessions) {
+    let mut sessions_guard = sessions.lock().await;
+    for (pid, mut session) in sessions_guard.drain() {
+        log::info!("Stopping model server {} ({})", session.info.model_id, pid);
+        let _ = session.child.kill().await;
+    }
+}
@@ -0,0 +1,15 @@
+/*!
+   Model Runtime Module
+
+   Spawns OpenAI-compatible inference servers (llama-server by default, or any configured
+   command) for local models and registers them as `BackendSession`s in `AppState`, so the
+   local API server can route requests to them.
+*/
+
+pub mod commands;
+mod constants;
+pub mod helpers;
+pub mod models;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,24 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// Parameters for spawning an inference server for a single model
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoadModelRequest {
+    pub model_id: String,
+    pub model_path: String,
+    /// Binary to run. Defaults to a bundled or PATH `llama-server`.
+    pub command: Option<String>,
+    /// Extra arguments. Supports `{model_id}`, `{model_path}`, `{host}`, `{port}` and
+    /// `{api_key}` placeholders. The key is also in the `LLAMA_API_KEY` environment
+    /// variable, which keeps it out of the process list.
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub envs: HashMap<String, String>,
+    pub library_path: Option<String>,
+    /// Path polled until the server answers 200. Defaults to `/health`.
+    pub health_path: Option<String>,
+    /// Seconds to wait for the server to become healthy
+    pub timeout: Option<u64>,
+}
@@ -0,0 +1,92 @@
+use super::helpers::*;
+use super::models::LoadModelRequest;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+fn create_test_request(command: Option<&str>, args: Vec<&str>) -> LoadModelRequest {
+    LoadModelRequest {
+        model_id: "qwen3-4b".to_string(),
+        model_path: "/models/qwen3-4b/model.gguf".to_string(),
+        command: command.map(String::from),
+        args: args.into_iter().map(String::from).collect(),
+        envs: HashMap::new(),
+        library_path: None,
+        health_path: None,
+        timeout: None,
+    }
+}
+
+#[test]
+fn test_build_server_args_default_command() {
+    let request = create_test_request(None, vec!["--ctx-size", "4096"]);
+    let args = build_server_args(&request, 3456, "secret");
+
+    assert_eq!(args[0..2], ["-m", "/models/qwen3-4b/model.gguf"]);
+    assert!(args.windows(2).any(|w| w == ["-a", "qwen3-4b"]));
+    assert!(args.windows(2).any(|w| w == ["--port", "3456"]));
+    // The key goes through LLAMA_API_KEY so other users cannot read it from `ps`
+    assert!(!args.iter().any(|arg| arg == "--api-key" || arg == "secret"));
+    assert_eq!(args[args.len() - 2..], ["--ctx-size", "4096"]);
+}
+
+#[test]
+fn test_build_server_args_custom_command() {
+    let request = create_test_request(
+        Some("vllm"),
+        vec![
+            "serve",
+            "{model_path}",
+            "--port={port}",
+            "--api-key",
+            "{api_key}",
+        ],
+    );
+    let args = build_server_args(&request, 3001, "key");
+
+    assert_eq!(
+        args,
+        vec![
+            "serve",
+            "/models/qwen3-4b/model.gguf",
+            "--port=3001",
+            "--api-key",
+            "key"
+        ]
+    );
+}
+
+#[test]
+fn test_substitute_placeholders() {
+    let request = create_test_request(None, vec![]);
+    let result = substitute_placeholders("{model_id}@{host}:{port}", &request, 3100, "k");
+    assert_eq!(result, "qwen3-4b@127.0.0.1:3100");
+}
+
+#[test]
+fn test_resolve_server_command_keeps_explicit_path() {
+    let path = PathBuf::from("/opt/llama/bin/llama-server");
+    assert_eq!(resolve_server_command(path.to_str()), path);
+}
+
+#[tokio::test]
+async fn test_unload_model_session_not_loaded() {
+    let sessions = std::sync::Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+    let result = unload_model_session(sessions, "missing-model").await;
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_port_reservations() {
+    let reservations: Vec<PortReservation> = (0..20)
+        .map(|_| PortReservation::new(&HashSet::new()).unwrap())
+        .collect();
+    let ports: HashSet<u16> = reservations.iter().map(|r| r.port).collect();
+    // Loads starting at the same time never share a port
+    assert_eq!(ports.len(), reservations.len());
+
+    let port = reservations[0].port;
+    drop(reservations);
+    // Dropping a reservation makes its port available again
+    let all_others: HashSet<u16> = (3000..4000).filter(|p| *p != port).collect();
+    assert_eq!(PortReservation::new(&all_others).unwrap().port, port);
+}
@@ -0,0 +1,643 @@
+//! Anthropic Messages API (`POST /v1/messages`) on the local server. Requests are
+//! translated to OpenAI chat completions and served by the model's session or by
+//! `ChatService` like any other request; replies and stream events are translated back.
+
+use futures_util::{Stream, StreamExt};
+use hyper::body::Bytes;
+use hyper::http::response::Builder;
+use hyper::{Body, Response, StatusCode};
+use serde_json::{json, Map, Value};
+
+/// Text of a `system` field or a `tool_result` content: a string or text blocks
+fn blocks_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("text"))
+            .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// A `base64` or `url` source as a URL the OpenAI format accepts
+fn source_url(source: &Value) -> Option<String> {
+    match source.get("type").and_then(|t| t.as_str())? {
+        "base64" => Some(format!(
+            "data:{};base64,{}",
+            source.get("media_type")?.as_str()?,
+            source.get("data")?.as_str()?
+        )),
+        "url" => Some(source.get("url")?.as_str()?.to_string()),
+        _ => None,
+    }
+}
+
+/// Converts one Anthropic message into OpenAI messages. Tool results become `tool`
+/// messages ahead of the rest of the user's content.
+fn openai_messages(message: &Value) -> Result<Vec<Value>, String> {
+    let role = message
+        .get("role")
+        .and_then(|r| r.as_str())
+        .ok_or("Each message must have a 'role'")?;
+    let blocks = match message.get("content") {
+        Some(Value::String(text)) => return Ok(vec![json!({ "role": role, "content": text })]),
+        Some(Value::Array(blocks)) => blocks,
+        _ => return Err("Each message must have a 'content'".to_string()),
+    };
+
+    let mut messages = Vec::new();
+    let mut parts = Vec::new();
+    let mut tool_calls = Vec::new();
+    for block in blocks {
+        match block.get("type").and_then(|t| t.as_str()) {
+            Some("text") => {
+                if let Some(text) = block.get("text").and_then(|t| t.as_str()) {
+                    parts.push(json!({ "type": "text", "text": text }));
+                }
+            }
+            Some("image") => {
+                if let Some(url) = block.get("source").and_then(source_url) {
+                    parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+                }
+            }
+            Some("document") => {
+                if let Some(url) = block.get("source").and_then(source_url) {
+                    let mut file = json!({ "file_data": url });
+                    if let Some(title) = block.get("title") {
+                        file["filename"] = title.clone();
+                    }
+                    parts.push(json!({ "type": "file", "file": file }));
+                }
+            }
+            Some("tool_use") => tool_calls.push(json!({
+                "id": block.get("id").cloned().unwrap_or(Value::Null),
+                "type": "function",
+                "function": {
+                    "name": block.get("name").cloned().unwrap_or(Value::Null),
+                    "arguments": block.get("input").unwrap_or(&json!({})).to_string(),
+                }
+            })),
+            Some("tool_result") => {
+                let mut content = blocks_text(block.get("content").unwrap_or(&Value::Null));
+                if block.get("is_error").and_then(|v| v.as_bool()) == Some(true) {
+                    content = format!("Error: {}", content);
+                }
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": block.get("tool_use_id").cloned().unwrap_or(Value::Null),
+                    "content": content,
+                }));
+            }
+            // Thinking blocks of earlier turns are not sent back
+            _ => {}
+        }
+    }
+
+    let only_text = parts
+        .iter()
+        .all(|part| part.get("type").and_then(|t| t.as_str()) == Some("text"));
+    let content = if only_text {
+        let text: Vec<&str> = parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+            .collect();
+        (!text.is_empty()).then(|| json!(text.join("\n\n")))
+    } else {
+        Some(Value::Array(parts))
+    };
+    if role == "assistant" {
+        if content.is_some() || !tool_calls.is_empty() {
+            let mut message = json!({ "role": "assistant", "content": content });
+            if !tool_calls.is_empty() {
+                message["tool_calls"] = Value::Array(tool_calls);
+            }
+            messages.push(message);
+        }
+    } else if let Some(content) = content {
+        messages.push(json!({ "role": role, "content": content }));
+    }
+    Ok(messages)
+}
+
+/// Translates a Messages request body into an OpenAI chat completion request body
+pub fn openai_request_from_anthropic(body: &Value) -> Result<Value, String> {
+    let model = body
+        .get("model")
+        .and_then(|m| m.as_str())
+        .ok_or("Request body must contain a 'model' field")?;
+    let source_messages = body
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .ok_or("Request body must contain a 'messages' array")?;
+
+    let mut messages = Vec::new();
+    if let Some(system) = body.get("system") {
+        let system = blocks_text(system);
+        if !system.is_empty() {
+            messages.push(json!({ "role": "system", "content": system }));
+        }
+    }
+    for message in source_messages {
+        messages.extend(openai_messages(message)?);
+    }
+
+    let mut request = Map::new();
+    request.insert("model".to_string(), json!(model));
+    request.insert("messages".to_string(), Value::Array(messages));
+    for (from, to) in [
+        ("max_tokens", "max_tokens"),
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("top_k", "top_k"),
+        ("stop_sequences", "stop"),
+    ] {
+        if let Some(value) = body.get(from).filter(|v| !v.is_null()) {
+            request.insert(to.to_string(), value.clone());
+        }
+    }
+    if let Some(user) = body.pointer("/metadata/user_id") {
+        request.insert("user".to_string(), user.clone());
+    }
+    if body.get("stream").and_then(|v| v.as_bool()) == Some(true) {
+        request.insert("stream".to_string(), json!(true));
+        request.insert(
+            "stream_options".to_string(),
+            json!({ "include_usage": true }),
+        );
+    }
+
+    if let Some(tools) = body.get("tools").and_then(|t| t.as_array()) {
+        let tools: Vec<Value> = tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.get("name").cloned().unwrap_or(Value::Null),
+                        "description": tool.get("description").cloned().unwrap_or(Value::Null),
+                        "parameters": tool
+                            .get("input_schema")
+                            .cloned()
+                            .unwrap_or_else(|| json!({ "type": "object" })),
+                    }
+                })
+            })
+            .collect();
+        request.insert("tools".to_string(), Value::Array(tools));
+    }
+    if let Some(choice) = body.get("tool_choice") {
+        let choice = match choice.get("type").and_then(|t| t.as_str()) {
+            Some("any") => json!("required"),
+            Some("none") => json!("none"),
+            Some("tool") => json!({
+                "type": "function",
+                "function": { "name": choice.get("name").cloned().unwrap_or(Value::Null) }
+            }),
+            _ => json!("auto"),
+        };
+        request.insert("tool_choice".to_string(), choice);
+        if body.pointer("/tool_choice/disable_parallel_tool_use") == Some(&json!(true)) {
+            request.insert("parallel_tool_calls".to_string(), json!(false));
+        }
+    }
+
+    Ok(Value::Object(request))
+}
+
+/// Maps an OpenAI `finish_reason` onto an Anthropic `stop_reason`
+pub fn stop_reason(finish_reason: &str) -> &'static str {
+    match finish_reason {
+        "length" => "max_tokens",
+        "tool_calls" | "function_call" => "tool_use",
+        "content_filter" => "refusal",
+        _ => "end_turn",
+    }
+}
+
+fn message_id(openai_id: Option<&str>) -> String {
+    match openai_id {
+        Some(id) => format!("msg_{}", id.trim_start_matches("chatcmpl-")),
+        None => format!("msg_{}", uuid::Uuid::new_v4().simple()),
+    }
+}
+
+/// Parses tool call arguments, an empty object when they are not valid JSON
+fn tool_input(arguments: Option<&Value>) -> Value {
+    match arguments {
+        Some(Value::String(text)) if !text.trim().is_empty() => {
+            serde_json::from_str(text).unwrap_or_else(|_| json!({}))
+        }
+        Some(value @ Value::Object(_)) => value.clone(),
+        _ => json!({}),
+    }
+}
+
+/// Translates a `chat.completion` response body into a Messages response
+pub fn anthropic_response_from_openai(body: &Value, model: &str) -> Value {
+    let choice = body.pointer("/choices/0").unwrap_or(&Value::Null);
+    let message = choice.get("message").unwrap_or(&Value::Null);
+
+    let mut content = Vec::new();
+    if let Some(thinking) = message
+        .get("reasoning_content")
+        .and_then(|t| t.as_str())
+        .filter(|t| !t.is_empty())
+    {
+        content.push(json!({ "type": "thinking", "thinking": thinking, "signature": "" }));
+    }
+    if let Some(text) = message
+        .get("content")
+        .and_then(|t| t.as_str())
+        .filter(|t| !t.is_empty())
+    {
+        content.push(json!({ "type": "text", "text": text }));
+    }
+    for call in message
+        .get("tool_calls")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+    {
+        content.push(json!({
+            "type": "tool_use",
+            "id": call.get("id").cloned().unwrap_or(Value::Null),
+            "name": call.pointer("/function/name").cloned().unwrap_or(Value::Null),
+            "input": tool_input(call.pointer("/function/arguments")),
+        }));
+    }
+
+    let finish_reason = choice
+        .get("finish_reason")
+        .and_then(|r| r.as_str())
+        .unwrap_or("stop");
+    json!({
+        "id": message_id(body.get("id").and_then(|id| id.as_str())),
+        "type": "message",
+        "role": "assistant",
+        "model": model,
+        "content": content,
+        "stop_reason": stop_reason(finish_reason),
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": body.pointer("/usage/prompt_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
+            "output_tokens": body.pointer("/usage/completion_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
+        }
+    })
+}
+
+/// Anthropic error type for an HTTP status
+pub fn error_type(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 | 422 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        529 | 503 => "overloaded_error",
+        _ => "api_error",
+    }
+}
+
+pub fn anthropic_error(error_type: &str, message: &str) -> Value {
+    json!({ "type": "error", "error": { "type": error_type, "message": message } })
+}
+
+/// A JSON error response in the Anthropic format
+pub fn error_response(builder: Builder, status: StatusCode, message: &str) -> Response<Body> {
+    builder
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            anthropic_error(error_type(status), message).to_string(),
+        ))
+        .unwrap()
+}
+
+pub fn sse_event(data: &Value) -> Bytes {
+    let name = data
+        .get("type")
+        .and_then(|t| t.as_str())
+        .unwrap_or("message");
+    Bytes::from(format!("event: {}\ndata: {}\n\n", name, data))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BlockKind {
+    Text,
+    Thinking,
+    /// A tool call, by its index in the OpenAI stream
+    ToolUse(u64),
+}
+
+/// Turns OpenAI `chat.completion.chunk`s into Messages stream events: `message_start`,
+/// a `content_block_start`/`_delta`/`_stop` run per block, `message_delta` and
+/// `message_stop`
+pub struct MessageStream {
+    model: String,
+    id: Option<String>,
+    started: bool,
+    finished: bool,
+    next_index: usize,
+    open: Option<(BlockKind, usize)>,
+    stop_reason: Option<&'static str>,
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl MessageStream {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            id: None,
+            started: false,
+            finished: false,
+            next_index: 0,
+            open: None,
+            stop_reason: None,
+            input_tokens: 0,
+            output_tokens: 0,
+        }
+    }
+
+    fn start(&mut self, events: &mut Vec<Value>) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        events.push(json!({
+            "type": "message_start",
+            "message": {
+                "id": message_id(self.id.as_deref()),
+                "type": "message",
+                "role": "assistant",
+                "model": self.model,
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": { "input_tokens": self.input_tokens, "output_tokens": 0 }
+            }
+        }));
+    }
+
+    fn close_block(&mut self, events: &mut Vec<Value>) {
+        if let Some((_, index)) = self.open.take() {
+            events.push(json!({ "type": "content_block_stop", "index": index }));
+        }
+    }
+
+    /// Opens a block of `kind` unless it is the open one; returns the block's index
+    fn open_block(&mut self, kind: BlockKind, block: Value, events: &mut Vec<Value>) -> usize {
+        if let Some((open_kind, index)) = self.open {
+            if open_kind == kind {
+                return index;
+            }
+        }
+        self.close_block(events);
+        let index = self.next_index;
+        self.next_index += 1;
+        self.open = Some((kind, index));
+        events.push(json!({
+            "type": "content_block_start",
+            "index": index,
+            "content_block": block,
+        }));
+        index
+    }
+
+    fn delta(index: usize, delta: Value) -> Value {
+        json!({ "type": "content_block_delta", "index": index, "delta": delta })
+    }
+
+    /// Events for one OpenAI stream payload
+    pub fn push_chunk(&mut self, chunk: &Value) -> Vec<Value> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        if let Some(error) = chunk.get("error") {
+            let message = error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .map(String::from)
+                .unwrap_or_else(|| error.to_string());
+            self.finished = true;
+            events.push(anthropic_error("api_error", &message));
+            return events;
+        }
+        if self.id.is_none() {
+            self.id = chunk.get("id").and_then(|id| id.as_str()).map(String::from);
+        }
+        if let Some(usage) = chunk.get("usage").filter(|u| u.is_object()) {
+            let count = |key: &str| usage.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+            self.input_tokens = count("prompt_tokens");
+            self.output_tokens = count("completion_tokens");
+        }
+        self.start(&mut events);
+
+        let choice = chunk.pointer("/choices/0").unwrap_or(&Value::Null);
+        let delta = choice.get("delta").unwrap_or(&Value::Null);
+        if let Some(thinking) = delta
+            .get("reasoning_content")
+            .and_then(|t| t.as_str())
+            .filter(|t| !t.is_empty())
+        {
+            let block = json!({ "type": "thinking", "thinking": "", "signature": "" });
+            let index = self.open_block(BlockKind::Thinking, block, &mut events);
+            events.push(Self::delta(
+                index,
+                json!({ "type": "thinking_delta", "thinking": thinking }),
+            ));
+        }
+        if let Some(text) = delta
+            .get("content")
+            .and_then(|t| t.as_str())
+            .filter(|t| !t.is_empty())
+        {
+            let block = json!({ "type": "text", "text": "" });
+            let index = self.open_block(BlockKind::Text, block, &mut events);
+            events.push(Self::delta(
+                index,
+                json!({ "type": "text_delta", "text": text }),
+            ));
+        }
+        for call in delta
+            .get("tool_calls")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+        {
+            let call_index = call.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+            let block = json!({
+                "type": "tool_use",
+                "id": call.get("id").cloned().unwrap_or(Value::Null),
+                "name": call.pointer("/function/name").cloned().unwrap_or(Value::Null),
+                "input": {},
+            });
+            let index = self.open_block(BlockKind::ToolUse(call_index), block, &mut events);
+            if let Some(arguments) = call
+                .pointer("/function/arguments")
+                .and_then(|a| a.as_str())
+                .filter(|a| !a.is_empty())
+            {
+                events.push(Self::delta(
+                    index,
+                    json!({ "type": "input_json_delta", "partial_json": arguments }),
+                ));
+            }
+        }
+        if let Some(finish_reason) = choice.get("finish_reason").and_then(|r| r.as_str()) {
+            self.stop_reason = Some(stop_reason(finish_reason));
+        }
+        events
+    }
+
+    /// Closing events once the OpenAI stream is done; nothing after an error
+    pub fn finish(&mut self) -> Vec<Value> {
+        let mut events = Vec::new();
+        if self.finished {
+            return events;
+        }
+        self.finished = true;
+        self.start(&mut events);
+        self.close_block(&mut events);
+        events.push(json!({
+            "type": "message_delta",
+            "delta": {
+                "stop_reason": self.stop_reason.unwrap_or("end_turn"),
+                "stop_sequence": null,
+            },
+            "usage": { "output_tokens": self.output_tokens }
+        }));
+        events.push(json!({ "type": "message_stop" }));
+        events
+    }
+}
+
+/// Reads `data:` payloads from an OpenAI SSE stream and sends the translated events
+async fn translate_stream<S, E>(upstream: S, model: String, mut sender: hyper::body::Sender)
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: std::fmt::Display + Send + 'static,
+{
+    let mut upstream = upstream;
+    let mut messages = MessageStream::new(&model);
+    let mut buffer = String::new();
+    let mut done = false;
+
+    'read: while let Some(chunk) = upstream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let message = e.to_string();
+                log::error!("Stream error: {}", message);
+                let error = json!({ "error": { "message": message } });
+                for event in messages.push_chunk(&error) {
+                    let _ = sender.send_data(sse_event(&event)).await;
+                }
+                return;
+            }
+        };
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        while let Some(end) = buffer.find('\n') {
+            let line: String = buffer.drain(..=end).collect();
+            let Some(data) = line.trim().strip_prefix("data:") else {
+                continue;
+            };
+            let data = data.trim();
+            if data == "[DONE]" {
+                done = true;
+                break 'read;
+            }
+            let Ok(payload) = serde_json::from_str::<Value>(data) else {
+                continue;
+            };
+            for event in messages.push_chunk(&payload) {
+                if sender.send_data(sse_event(&event)).await.is_err() {
+                    log::debug!("Client disconnected during streaming");
+                    return;
+                }
+            }
+        }
+    }
+    if !done {
+        log::debug!("Upstream stream ended without [DONE]");
+    }
+    for event in messages.finish() {
+        let _ = sender.send_data(sse_event(&event)).await;
+    }
+}
+
+/// Translates an OpenAI chat completion response, streaming or not, into the
+/// Messages format. Error responses become Anthropic error objects.
+///
+/// # Arguments
+/// * `builder` - Response builder with CORS headers already applied
+/// * `status` - Status of the OpenAI response
+/// * `body` - Body of the OpenAI response
+/// * `stream` - Whether the client asked for a stream
+/// * `model` - The requested model id, echoed back in responses
+pub async fn translate_response<S, E>(
+    builder: Builder,
+    status: StatusCode,
+    body: S,
+    stream: bool,
+    model: String,
+) -> Response<Body>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: std::fmt::Display + Send + 'static,
+{
+    if status.is_success() && stream {
+        let (sender, body) = Body::channel();
+        tokio::spawn(translate_stream(body, model, sender));
+        return builder
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+            .header(hyper::header::CACHE_CONTROL, "no-cache")
+            .body(body)
+            .unwrap();
+    }
+
+    let mut bytes = Vec::new();
+    let mut body = body;
+    while let Some(chunk) = body.next().await {
+        match chunk {
+            Ok(chunk) => bytes.extend_from_slice(&chunk),
+            Err(e) => {
+                return error_response(
+                    builder,
+                    StatusCode::BAD_GATEWAY,
+                    &format!("Failed to read the model response: {}", e),
+                )
+            }
+        }
+    }
+    let parsed = serde_json::from_slice::<Value>(&bytes).ok();
+
+    if !status.is_success() {
+        let message = parsed
+            .as_ref()
+            .and_then(|body| body.pointer("/error/message").and_then(|m| m.as_str()))
+            .map(String::from)
+            .unwrap_or_else(|| String::from_utf8_lossy(&bytes).to_string());
+        return error_response(builder, status, &message);
+    }
+    match parsed {
+        Some(body) => builder
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                anthropic_response_from_openai(&body, &model).to_string(),
+            ))
+            .unwrap(),
+        None => error_response(
+            builder,
+            StatusCode::BAD_GATEWAY,
+            "The model returned an invalid response",
+        ),
+    }
+}
@@ -1,14 +1,12 @@
-use std::collections::HashMap;
 use std::sync::Arc;
 use tauri::{AppHandle, Runtime, State};
-use tokio::sync::Mutex;
 
-use crate::core::server::proxy::{self, BackendSession};
+use crate::core::server::proxy;
 use crate::core::state::AppState;
 
 #[tauri::command]
 pub async fn start_server<R: Runtime>(
-    _app_handle: AppHandle<R>,
+    app_handle: AppHandle<R>,
     state: State<'_, AppState>,
     host: String,
     port: u16,
@@ -16,23 +14,26 @@ pub async fn start_server<R: Runtime>(
     api_key: String,
     trusted_hosts: Vec<String>,
     proxy_timeout: u64,
+    remote_models: Option<Vec<String>>,
 ) -> Result<bool, String> {
-    let server_handle = state.server_handle.clone();
-    // Create empty sessions map since we don't have llamacpp plugin anymore
-    let sessions: Arc<Mutex<HashMap<i32, BackendSession>>> = Arc::new(Mutex::new(HashMap::new()));
-
-    proxy::start_server(
-        server_handle,
-        sessions,
+    let config = proxy::ProxyConfig {
         host,
         port,
         prefix,
-        api_key,
-        vec![trusted_hosts],
+        proxy_api_key: api_key,
+        trusted_hosts: vec![trusted_hosts],
         proxy_timeout,
-    )
-    .await
-    .map_err(|e| e.to_string())?;
+        remote_models: remote_models.unwrap_or_default(),
+        sessions: state.model_sessions.clone(),
+        provider_configs: state.provider_configs.clone(),
+        chat_service: state.chat_service.clone(),
+        mcp_servers: state.mcp_servers.clone(),
+        tool_call_cancellations: state.tool_call_cancellations.clone(),
+    };
+
+    proxy::start_server(app_handle, state.server_handle.clone(), Arc::new(config))
+        .await
+        .map_err(|e| e.to_string())?;
     Ok(true)
 }
 
@@ -0,0 +1,316 @@
+//! Streamable-HTTP MCP endpoint (`/mcp`) that offers the tools of every MCP server
+//! running in Jan to other MCP clients. Tool names are prefixed with their server's
+//! name; calls are forwarded to that server with the usual timeout and can be
+//! cancelled with `notifications/cancelled`.
+//!
+//! The endpoint is stateless: every POST carries complete JSON-RPC messages and gets a
+//! JSON reply. The session id handed out by `initialize` only scopes cancellations.
+
+use hyper::body::Bytes;
+use hyper::http::response::Builder;
+use hyper::{Body, Method, Response, StatusCode};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+use crate::core::mcp::helpers::{call_server_tool, list_tools_with_server};
+use crate::core::state::{SharedMcpServers, SharedToolCallCancellations};
+
+/// Separates the server name from the tool name in the tools offered to clients
+pub const TOOL_NAMESPACE_SEPARATOR: &str = "__";
+
+/// Protocol versions the endpoint speaks, newest first
+pub const SUPPORTED_PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+pub const SESSION_HEADER: &str = "mcp-session-id";
+pub const PROTOCOL_VERSION_HEADER: &str = "mcp-protocol-version";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// The name a client sees for `tool` of `server`
+pub fn namespaced_tool_name(server: &str, tool: &str) -> String {
+    format!("{}{}{}", server, TOOL_NAMESPACE_SEPARATOR, tool)
+}
+
+/// Splits a namespaced tool name into server and tool. Server names may contain the
+/// separator too, so the name is matched against the names in `servers`; the longest
+/// one that fits wins.
+pub fn split_tool_name<'a, 'b>(
+    name: &'a str,
+    servers: impl IntoIterator<Item = &'b str>,
+) -> Option<(&'a str, &'a str)> {
+    servers
+        .into_iter()
+        .filter(|server| !server.is_empty())
+        .filter_map(|server| {
+            let tool = name
+                .strip_prefix(server)?
+                .strip_prefix(TOOL_NAMESPACE_SEPARATOR)?;
+            (!tool.is_empty()).then_some((&name[..server.len()], tool))
+        })
+        .max_by_key(|(server, _)| server.len())
+}
+
+/// Splits a namespaced tool name against the running MCP servers
+pub async fn resolve_tool_name<'a>(
+    servers: &SharedMcpServers,
+    name: &'a str,
+) -> Option<(&'a str, &'a str)> {
+    let servers = servers.lock().await;
+    split_tool_name(name, servers.keys().map(String::as_str))
+}
+
+fn success(id: &Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn failure(id: &Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Key of a call in the cancellation map. Request ids are only unique per client, so
+/// they are scoped by session.
+fn cancellation_key(session_id: Option<&str>, request_id: &Value) -> String {
+    format!("mcp:{}:{}", session_id.unwrap_or_default(), request_id)
+}
+
+fn initialize_result(params: &Value) -> Value {
+    let requested = params
+        .get("protocolVersion")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let version = SUPPORTED_PROTOCOL_VERSIONS
+        .iter()
+        .find(|v| **v == requested)
+        .unwrap_or(&SUPPORTED_PROTOCOL_VERSIONS[0]);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": { "listChanged": false } },
+        "serverInfo": { "name": "jan", "version": env!("CARGO_PKG_VERSION") },
+        "instructions": format!(
+            "Tools of the MCP servers running in Jan, named <server>{}<tool>.",
+            TOOL_NAMESPACE_SEPARATOR
+        ),
+    })
+}
+
+async fn list_tools(servers: &SharedMcpServers) -> Result<Value, String> {
+    let tools: Vec<Value> = list_tools_with_server(servers, None)
+        .await?
+        .into_iter()
+        .map(|tool| {
+            let mut entry = json!({
+                "name": namespaced_tool_name(&tool.server, &tool.name),
+                "inputSchema": tool.input_schema,
+            });
+            if let Some(description) = tool.description {
+                entry["description"] = json!(description);
+            }
+            entry
+        })
+        .collect();
+    Ok(json!({ "tools": tools }))
+}
+
+/// Runs `tools/call`. Failures of the tool itself are reported in the result with
+/// `isError`, as MCP asks, so the calling model can see them.
+async fn call_tool(
+    servers: &SharedMcpServers,
+    cancellations: &SharedToolCallCancellations,
+    session_id: Option<&str>,
+    id: &Value,
+    params: &Value,
+) -> Value {
+    let Some(name) = params.get("name").and_then(|n| n.as_str()) else {
+        return failure(id, INVALID_PARAMS, "params.name is required");
+    };
+    let Some((server, tool)) = resolve_tool_name(servers, name).await else {
+        return failure(id, INVALID_PARAMS, &format!("Unknown tool: {}", name));
+    };
+    let arguments = match params.get("arguments") {
+        None | Some(Value::Null) => None,
+        Some(Value::Object(arguments)) => Some(arguments.clone()),
+        Some(_) => return failure(id, INVALID_PARAMS, "params.arguments must be an object"),
+    };
+
+    let key = cancellation_key(session_id, id);
+    let (cancel_tx, cancel_rx) = oneshot::channel::<()>();
+    cancellations.lock().await.insert(key.clone(), cancel_tx);
+
+    let result = tokio::select! {
+        result = call_server_tool(servers, server, tool, arguments) => result,
+        _ = cancel_rx => Err(format!("Tool call '{}' was cancelled", name)),
+    };
+    cancellations.lock().await.remove(&key);
+
+    match result.and_then(|result| serde_json::to_value(result).map_err(|e| e.to_string())) {
+        Ok(result) => success(id, result),
+        Err(e) => {
+            log::warn!("MCP endpoint call of {} failed: {}", name, e);
+            success(
+                id,
+                json!({ "content": [{ "type": "text", "text": e }], "isError": true }),
+            )
+        }
+    }
+}
+
+/// Handles one JSON-RPC message. Returns the response, or `None` for notifications.
+pub async fn handle_message(
+    servers: &SharedMcpServers,
+    cancellations: &SharedToolCallCancellations,
+    session_id: Option<&str>,
+    message: &Value,
+) -> Option<Value> {
+    let Some(method) = message.get("method").and_then(|m| m.as_str()) else {
+        // Replies to requests we never send
+        if message.get("result").is_some() || message.get("error").is_some() {
+            return None;
+        }
+        return Some(failure(
+            message.get("id").unwrap_or(&Value::Null),
+            INVALID_REQUEST,
+            "Invalid request",
+        ));
+    };
+    let params = message.get("params").unwrap_or(&Value::Null);
+    let Some(id) = message.get("id").filter(|id| !id.is_null()) else {
+        if method == "notifications/cancelled" {
+            if let Some(request_id) = params.get("requestId") {
+                let key = cancellation_key(session_id, request_id);
+                if let Some(cancel_tx) = cancellations.lock().await.remove(&key) {
+                    let _ = cancel_tx.send(());
+                }
+            }
+        }
+        return None;
+    };
+
+    Some(match method {
+        "initialize" => success(id, initialize_result(params)),
+        "ping" => success(id, json!({})),
+        "tools/list" => match list_tools(servers).await {
+            Ok(result) => success(id, result),
+            Err(e) => failure(id, INTERNAL_ERROR, &e),
+        },
+        "tools/call" => call_tool(servers, cancellations, session_id, id, params).await,
+        _ => failure(
+            id,
+            METHOD_NOT_FOUND,
+            &format!("Method not found: {}", method),
+        ),
+    })
+}
+
+/// Handles a request to `/mcp`
+///
+/// # Arguments
+/// * `servers` - The running MCP servers whose tools are offered
+/// * `cancellations` - Cancellation senders shared with the `call_tool` command
+/// * `builder` - Response builder with CORS headers already applied
+/// * `method` / `headers` / `body` - The request
+pub async fn handle_mcp_request(
+    servers: &SharedMcpServers,
+    cancellations: &SharedToolCallCancellations,
+    builder: Builder,
+    method: &Method,
+    headers: &hyper::HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    if *method != Method::POST {
+        // No server-initiated streams and no session state to delete
+        return builder
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(hyper::header::ALLOW, "POST")
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    let http_error = |builder: Builder, status: StatusCode, message: &str| {
+        builder
+            .status(status)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                failure(&Value::Null, INVALID_REQUEST, message).to_string(),
+            ))
+            .unwrap()
+    };
+    // Replies are always JSON, never an event stream
+    let accepts_json = match headers.get(hyper::header::ACCEPT) {
+        Some(accept) => accept.to_str().unwrap_or_default().split(',').any(|range| {
+            let media_type = range.split(';').next().unwrap_or_default().trim();
+            matches!(media_type, "application/json" | "application/*" | "*/*")
+        }),
+        None => true,
+    };
+    if !accepts_json {
+        return http_error(
+            builder,
+            StatusCode::NOT_ACCEPTABLE,
+            "Not Acceptable: the client must accept application/json",
+        );
+    }
+    if let Some(version) = headers.get(PROTOCOL_VERSION_HEADER) {
+        let version = version.to_str().unwrap_or_default();
+        if !SUPPORTED_PROTOCOL_VERSIONS.contains(&version) {
+            return http_error(
+                builder,
+                StatusCode::BAD_REQUEST,
+                &format!("Unsupported MCP protocol version: {}", version),
+            );
+        }
+    }
+
+    let json_reply = |builder: Builder, session_id: Option<&str>, value: &Value| {
+        let mut builder = builder
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "application/json");
+        if let Some(session_id) = session_id {
+            builder = builder.header(SESSION_HEADER, session_id);
+        }
+        builder.body(Body::from(value.to_string())).unwrap()
+    };
+
+    let payload = match serde_json::from_slice::<Value>(&body) {
+        Ok(payload) => payload,
+        Err(e) => {
+            return json_reply(
+                builder,
+                None,
+                &failure(&Value::Null, PARSE_ERROR, &format!("Parse error: {}", e)),
+            )
+        }
+    };
+
+    let is_initialize =
+        |message: &Value| message.get("method").and_then(|m| m.as_str()) == Some("initialize");
+    let session_id = match headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(session_id) => Some(session_id.to_string()),
+        None if is_initialize(&payload) => Some(uuid::Uuid::new_v4().to_string()),
+        None => None,
+    };
+    let session_id = session_id.as_deref();
+
+    let reply = match &payload {
+        Value::Array(messages) if !messages.is_empty() => {
+            let mut replies = Vec::new();
+            for message in messages {
+                replies.extend(handle_message(servers, cancellations, session_id, message).await);
+            }
+            (!replies.is_empty()).then_some(Value::Array(replies))
+        }
+        Value::Object(_) => handle_message(servers, cancellations, session_id, &payload).await,
+        _ => Some(failure(&Value::Null, INVALID_REQUEST, "Invalid request")),
+    };
+
+    match reply {
+        Some(reply) => json_reply(builder, session_id, &reply),
+        None => builder
+            .status(StatusCode::ACCEPTED)
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
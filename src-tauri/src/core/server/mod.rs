@@ -1,2 +1,11 @@
+pub mod anthropic;
 pub mod commands;
+pub mod mcp;
+pub mod ollama;
 pub mod proxy;
+pub mod remote;
+pub mod threads;
+pub mod tools;
+
+#[cfg(test)]
+mod tests;
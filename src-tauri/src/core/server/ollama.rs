@@ -0,0 +1,667 @@
+//! Ollama API (`/api/chat`, `/api/generate`, `/api/embed(dings)`, `/api/tags`,
+//! `/api/show`) on the local server, for tools that only speak Ollama. Requests are
+//! translated to the OpenAI format and served like any other request; replies are
+//! translated back, streams as newline-delimited JSON.
+
+use futures_util::StreamExt;
+use hyper::body::Bytes;
+use hyper::http::response::Builder;
+use hyper::{Body, Response, StatusCode};
+use serde_json::{json, Map, Value};
+
+use super::remote::read_json_response;
+
+/// Version reported by `/api/version`; clients check it before using newer endpoints
+pub const OLLAMA_COMPAT_VERSION: &str = "0.6.0";
+
+/// Which Ollama endpoint a translated request came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OllamaEndpoint {
+    Chat,
+    Generate,
+}
+
+/// RFC 3339 UTC time for `created_at` and `modified_at` fields
+pub fn rfc3339(unix_secs: u64) -> String {
+    let days = (unix_secs / 86_400) as i64;
+    let secs = unix_secs % 86_400;
+    // Civil date from days since the epoch (H. Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+fn now() -> String {
+    rfc3339(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    )
+}
+
+/// Ollama sends images as bare base64; the media type is read from the first bytes
+fn image_url(data: &str) -> String {
+    let media_type = match data.get(..5) {
+        Some(prefix) if prefix.starts_with("/9j/") => "image/jpeg",
+        Some("R0lGO") => "image/gif",
+        Some("UklGR") => "image/webp",
+        _ => "image/png",
+    };
+    format!("data:{};base64,{}", media_type, data)
+}
+
+/// OpenAI content of a message with `images`
+fn content_with_images(text: &str, images: Option<&Value>) -> Value {
+    let images: Vec<&str> = images
+        .and_then(|i| i.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|image| image.as_str())
+        .collect();
+    if images.is_empty() {
+        return json!(text);
+    }
+    let mut parts = vec![json!({ "type": "text", "text": text })];
+    parts.extend(
+        images
+            .into_iter()
+            .map(|data| json!({ "type": "image_url", "image_url": { "url": image_url(data) } })),
+    );
+    Value::Array(parts)
+}
+
+/// Copies `options`, `format` and `stream` onto an OpenAI request. Ollama streams
+/// unless `stream` is `false`.
+fn apply_options(body: &Value, request: &mut Map<String, Value>) -> bool {
+    let options = body.get("options").unwrap_or(&Value::Null);
+    for (from, to) in [
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("top_k", "top_k"),
+        ("num_predict", "max_tokens"),
+        ("stop", "stop"),
+        ("seed", "seed"),
+        ("presence_penalty", "presence_penalty"),
+        ("frequency_penalty", "frequency_penalty"),
+    ] {
+        if let Some(value) = options.get(from).filter(|v| !v.is_null()) {
+            request.insert(to.to_string(), value.clone());
+        }
+    }
+    match body.get("format") {
+        Some(Value::String(format)) if format == "json" => {
+            request.insert(
+                "response_format".to_string(),
+                json!({ "type": "json_object" }),
+            );
+        }
+        Some(schema @ Value::Object(_)) => {
+            request.insert(
+                "response_format".to_string(),
+                json!({ "type": "json_schema", "json_schema": { "name": "response", "schema": schema } }),
+            );
+        }
+        _ => {}
+    }
+    let stream = body.get("stream").and_then(|v| v.as_bool()).unwrap_or(true);
+    if stream {
+        request.insert("stream".to_string(), json!(true));
+        request.insert(
+            "stream_options".to_string(),
+            json!({ "include_usage": true }),
+        );
+    }
+    stream
+}
+
+fn model_of(body: &Value) -> Result<&str, String> {
+    body.get("model")
+        .or_else(|| body.get("name"))
+        .and_then(|m| m.as_str())
+        .filter(|m| !m.is_empty())
+        .ok_or_else(|| "model is required".to_string())
+}
+
+/// Translates an `/api/chat` request into an OpenAI chat completion request.
+/// Returns the request and whether the client wants a stream.
+pub fn openai_request_from_chat(body: &Value) -> Result<(Value, bool), String> {
+    let model = model_of(body)?;
+    let source = body
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .ok_or("messages is required")?;
+
+    let mut messages = Vec::new();
+    // Ollama tool calls have no ids; results are matched to calls in order
+    let mut pending_calls: Vec<String> = Vec::new();
+    for (index, message) in source.iter().enumerate() {
+        let role = message
+            .get("role")
+            .and_then(|r| r.as_str())
+            .unwrap_or("user");
+        let text = message
+            .get("content")
+            .and_then(|c| c.as_str())
+            .unwrap_or_default();
+        match role {
+            "assistant" => {
+                let mut converted = json!({ "role": "assistant", "content": text });
+                let calls: Vec<Value> = message
+                    .get("tool_calls")
+                    .and_then(|c| c.as_array())
+                    .into_iter()
+                    .flatten()
+                    .enumerate()
+                    .map(|(call_index, call)| {
+                        let id = format!("call_{}_{}", index, call_index);
+                        pending_calls.push(id.clone());
+                        let arguments = match call.pointer("/function/arguments") {
+                            Some(Value::String(arguments)) => arguments.clone(),
+                            Some(arguments) => arguments.to_string(),
+                            None => "{}".to_string(),
+                        };
+                        json!({
+                            "id": id,
+                            "type": "function",
+                            "function": {
+                                "name": call.pointer("/function/name").cloned().unwrap_or(Value::Null),
+                                "arguments": arguments,
+                            }
+                        })
+                    })
+                    .collect();
+                if !calls.is_empty() {
+                    converted["tool_calls"] = Value::Array(calls);
+                }
+                messages.push(converted);
+            }
+            "tool" => {
+                let id = if pending_calls.is_empty() {
+                    format!("call_{}", index)
+                } else {
+                    pending_calls.remove(0)
+                };
+                messages.push(json!({ "role": "tool", "tool_call_id": id, "content": text }));
+            }
+            _ => messages.push(json!({
+                "role": role,
+                "content": content_with_images(text, message.get("images")),
+            })),
+        }
+    }
+
+    let mut request = Map::new();
+    request.insert("model".to_string(), json!(model));
+    request.insert("messages".to_string(), Value::Array(messages));
+    if let Some(tools) = body.get("tools").filter(|t| t.is_array()) {
+        request.insert("tools".to_string(), tools.clone());
+    }
+    let stream = apply_options(body, &mut request);
+    Ok((Value::Object(request), stream))
+}
+
+/// Translates an `/api/generate` request into an OpenAI chat completion request
+/// with `system` and `prompt` as messages. Returns `None` for the empty prompt that
+/// Ollama clients send to load a model.
+pub fn openai_request_from_generate(body: &Value) -> Result<Option<(Value, bool)>, String> {
+    let model = model_of(body)?;
+    let prompt = body
+        .get("prompt")
+        .and_then(|p| p.as_str())
+        .unwrap_or_default();
+    if prompt.is_empty() && body.get("images").is_none() {
+        return Ok(None);
+    }
+
+    let mut messages = Vec::new();
+    if let Some(system) = body
+        .get("system")
+        .and_then(|s| s.as_str())
+        .filter(|s| !s.is_empty())
+    {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    let mut prompt = prompt.to_string();
+    if let Some(suffix) = body
+        .get("suffix")
+        .and_then(|s| s.as_str())
+        .filter(|s| !s.is_empty())
+    {
+        prompt = format!("{}\n\n{}", prompt, suffix);
+    }
+    messages.push(json!({
+        "role": "user",
+        "content": content_with_images(&prompt, body.get("images")),
+    }));
+
+    let mut request = Map::new();
+    request.insert("model".to_string(), json!(model));
+    request.insert("messages".to_string(), Value::Array(messages));
+    let stream = apply_options(body, &mut request);
+    Ok(Some((Value::Object(request), stream)))
+}
+
+/// The reply to an empty `/api/generate` prompt
+pub fn load_response(model: &str) -> Value {
+    json!({
+        "model": model,
+        "created_at": now(),
+        "response": "",
+        "done": true,
+        "done_reason": "load",
+    })
+}
+
+fn done_reason(finish_reason: Option<&str>) -> &'static str {
+    match finish_reason {
+        Some("length") => "length",
+        _ => "stop",
+    }
+}
+
+/// Converts OpenAI tool calls, with arguments as JSON text, into Ollama's
+fn ollama_tool_calls(calls: &[Value]) -> Vec<Value> {
+    calls
+        .iter()
+        .map(|call| {
+            let arguments = match call.pointer("/function/arguments") {
+                Some(Value::String(text)) => {
+                    serde_json::from_str(text).unwrap_or_else(|_| json!({}))
+                }
+                Some(value) => value.clone(),
+                None => json!({}),
+            };
+            json!({
+                "function": {
+                    "name": call.pointer("/function/name").cloned().unwrap_or(Value::Null),
+                    "arguments": arguments,
+                }
+            })
+        })
+        .collect()
+}
+
+/// The last object of a reply: `done`, the reason and the token counts
+fn final_object(
+    endpoint: OllamaEndpoint,
+    model: &str,
+    finish_reason: Option<&str>,
+    usage: Option<&Value>,
+) -> Value {
+    let count = |key: &str| {
+        usage
+            .and_then(|u| u.get(key))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0)
+    };
+    let mut object = json!({
+        "model": model,
+        "created_at": now(),
+        "done": true,
+        "done_reason": done_reason(finish_reason),
+        "total_duration": 0,
+        "load_duration": 0,
+        "prompt_eval_count": count("prompt_tokens"),
+        "eval_count": count("completion_tokens"),
+    });
+    match endpoint {
+        OllamaEndpoint::Chat => {
+            object["message"] = json!({ "role": "assistant", "content": "" });
+        }
+        OllamaEndpoint::Generate => {
+            object["response"] = json!("");
+            object["context"] = json!([]);
+        }
+    }
+    object
+}
+
+/// Translates a `chat.completion` body into the reply of `endpoint`
+pub fn ollama_response_from_openai(endpoint: OllamaEndpoint, body: &Value, model: &str) -> Value {
+    let choice = body.pointer("/choices/0").unwrap_or(&Value::Null);
+    let message = choice.get("message").unwrap_or(&Value::Null);
+    let content = message
+        .get("content")
+        .and_then(|c| c.as_str())
+        .unwrap_or_default();
+    let thinking = message
+        .get("reasoning_content")
+        .and_then(|t| t.as_str())
+        .filter(|t| !t.is_empty());
+
+    let mut reply = final_object(
+        endpoint,
+        model,
+        choice.get("finish_reason").and_then(|r| r.as_str()),
+        body.get("usage"),
+    );
+    match endpoint {
+        OllamaEndpoint::Chat => {
+            reply["message"]["content"] = json!(content);
+            if let Some(thinking) = thinking {
+                reply["message"]["thinking"] = json!(thinking);
+            }
+            if let Some(calls) = message.get("tool_calls").and_then(|c| c.as_array()) {
+                reply["message"]["tool_calls"] = Value::Array(ollama_tool_calls(calls));
+            }
+        }
+        OllamaEndpoint::Generate => {
+            reply["response"] = json!(content);
+            if let Some(thinking) = thinking {
+                reply["thinking"] = json!(thinking);
+            }
+        }
+    }
+    reply
+}
+
+/// Turns OpenAI `chat.completion.chunk`s into Ollama's streamed objects. Tool call
+/// fragments are collected and sent whole in one object before the final one.
+pub struct OllamaStream {
+    endpoint: OllamaEndpoint,
+    model: String,
+    tool_calls: Vec<Value>,
+    finish_reason: Option<String>,
+    usage: Option<Value>,
+    finished: bool,
+}
+
+impl OllamaStream {
+    pub fn new(endpoint: OllamaEndpoint, model: &str) -> Self {
+        Self {
+            endpoint,
+            model: model.to_string(),
+            tool_calls: Vec::new(),
+            finish_reason: None,
+            usage: None,
+            finished: false,
+        }
+    }
+
+    fn delta_object(&self, content: &str, thinking: Option<&str>) -> Value {
+        let mut object = json!({ "model": self.model, "created_at": now(), "done": false });
+        match self.endpoint {
+            OllamaEndpoint::Chat => {
+                object["message"] = json!({ "role": "assistant", "content": content });
+                if let Some(thinking) = thinking {
+                    object["message"]["thinking"] = json!(thinking);
+                }
+            }
+            OllamaEndpoint::Generate => {
+                object["response"] = json!(content);
+                if let Some(thinking) = thinking {
+                    object["thinking"] = json!(thinking);
+                }
+            }
+        }
+        object
+    }
+
+    /// Objects for one OpenAI stream payload
+    pub fn push_chunk(&mut self, chunk: &Value) -> Vec<Value> {
+        let mut objects = Vec::new();
+        if self.finished {
+            return objects;
+        }
+        if let Some(error) = chunk.get("error") {
+            self.finished = true;
+            let message = error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .map(String::from)
+                .unwrap_or_else(|| error.to_string());
+            objects.push(json!({ "error": message }));
+            return objects;
+        }
+        if let Some(usage) = chunk.get("usage").filter(|u| u.is_object()) {
+            self.usage = Some(usage.clone());
+        }
+
+        let choice = chunk.pointer("/choices/0").unwrap_or(&Value::Null);
+        let delta = choice.get("delta").unwrap_or(&Value::Null);
+        let content = delta
+            .get("content")
+            .and_then(|c| c.as_str())
+            .unwrap_or_default();
+        let thinking = delta
+            .get("reasoning_content")
+            .and_then(|t| t.as_str())
+            .filter(|t| !t.is_empty());
+        if !content.is_empty() || thinking.is_some() {
+            objects.push(self.delta_object(content, thinking));
+        }
+
+        for call in delta
+            .get("tool_calls")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+        {
+            let index = call.get("index").and_then(|i| i.as_u64()).unwrap_or(0) as usize;
+            while self.tool_calls.len() <= index {
+                self.tool_calls
+                    .push(json!({ "function": { "name": "", "arguments": "" } }));
+            }
+            let function = &mut self.tool_calls[index]["function"];
+            for key in ["name", "arguments"] {
+                if let Some(fragment) = call
+                    .pointer(&format!("/function/{}", key))
+                    .and_then(|v| v.as_str())
+                {
+                    let joined = format!("{}{}", function[key].as_str().unwrap_or(""), fragment);
+                    function[key] = json!(joined);
+                }
+            }
+        }
+        if let Some(finish_reason) = choice.get("finish_reason").and_then(|r| r.as_str()) {
+            self.finish_reason = Some(finish_reason.to_string());
+        }
+        objects
+    }
+
+    /// The collected tool calls, if any, and the final object
+    pub fn finish(&mut self) -> Vec<Value> {
+        let mut objects = Vec::new();
+        if self.finished {
+            return objects;
+        }
+        self.finished = true;
+        if !self.tool_calls.is_empty() && self.endpoint == OllamaEndpoint::Chat {
+            let mut object = self.delta_object("", None);
+            object["message"]["tool_calls"] = Value::Array(ollama_tool_calls(&self.tool_calls));
+            objects.push(object);
+        }
+        objects.push(final_object(
+            self.endpoint,
+            &self.model,
+            self.finish_reason.as_deref(),
+            self.usage.as_ref(),
+        ));
+        objects
+    }
+}
+
+pub fn ndjson_line(value: &Value) -> Bytes {
+    Bytes::from(format!("{}\n", value))
+}
+
+/// An error response in Ollama's format
+pub fn error_response(builder: Builder, status: StatusCode, message: &str) -> Response<Body> {
+    builder
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "error": message }).to_string()))
+        .unwrap()
+}
+
+pub fn json_response(builder: Builder, value: &Value) -> Response<Body> {
+    builder
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(value.to_string()))
+        .unwrap()
+}
+
+async fn translate_stream(
+    endpoint: OllamaEndpoint,
+    model: String,
+    mut upstream: Body,
+    mut sender: hyper::body::Sender,
+) {
+    let mut stream = OllamaStream::new(endpoint, &model);
+    let mut buffer = String::new();
+
+    'read: while let Some(chunk) = upstream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let error = json!({ "error": { "message": e.to_string() } });
+                for object in stream.push_chunk(&error) {
+                    let _ = sender.send_data(ndjson_line(&object)).await;
+                }
+                return;
+            }
+        };
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        while let Some(end) = buffer.find('\n') {
+            let line: String = buffer.drain(..=end).collect();
+            let Some(data) = line.trim().strip_prefix("data:") else {
+                continue;
+            };
+            let data = data.trim();
+            if data == "[DONE]" {
+                break 'read;
+            }
+            let Ok(payload) = serde_json::from_str::<Value>(data) else {
+                continue;
+            };
+            for object in stream.push_chunk(&payload) {
+                if sender.send_data(ndjson_line(&object)).await.is_err() {
+                    log::debug!("Client disconnected during streaming");
+                    return;
+                }
+            }
+        }
+    }
+    for object in stream.finish() {
+        let _ = sender.send_data(ndjson_line(&object)).await;
+    }
+}
+
+/// Translates an OpenAI chat completion response into the reply of `endpoint`:
+/// newline-delimited JSON objects when streaming, one object otherwise
+pub async fn translate_chat_response(
+    builder: Builder,
+    endpoint: OllamaEndpoint,
+    response: Response<Body>,
+    stream: bool,
+    model: String,
+) -> Response<Body> {
+    if response.status().is_success() && stream {
+        let (sender, body) = Body::channel();
+        tokio::spawn(translate_stream(
+            endpoint,
+            model,
+            response.into_body(),
+            sender,
+        ));
+        return builder
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "application/x-ndjson")
+            .body(body)
+            .unwrap();
+    }
+    match read_json_response(response).await {
+        Ok(body) => json_response(
+            builder,
+            &ollama_response_from_openai(endpoint, &body, &model),
+        ),
+        Err((status, message)) => error_response(builder, status, &message),
+    }
+}
+
+/// Inputs of `/api/embed` (`input`, a string or strings) or `/api/embeddings` (`prompt`)
+pub fn embedding_input(body: &Value) -> Result<Value, String> {
+    match body.get("input").or_else(|| body.get("prompt")) {
+        Some(input @ (Value::String(_) | Value::Array(_))) => Ok(input.clone()),
+        _ => Err("input is required".to_string()),
+    }
+}
+
+/// Translates an OpenAI embeddings response: `/api/embed` replies with all vectors,
+/// the older `/api/embeddings` with the first one
+pub async fn translate_embeddings_response(
+    builder: Builder,
+    response: Response<Body>,
+    legacy: bool,
+    model: String,
+) -> Response<Body> {
+    let body = match read_json_response(response).await {
+        Ok(body) => body,
+        Err((status, message)) => return error_response(builder, status, &message),
+    };
+    let embeddings: Vec<Value> = body
+        .get("data")
+        .and_then(|d| d.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("embedding").cloned())
+        .collect();
+    let reply = if legacy {
+        json!({ "embedding": embeddings.first().cloned().unwrap_or_else(|| json!([])) })
+    } else {
+        json!({
+            "model": model,
+            "embeddings": embeddings,
+            "prompt_eval_count": body.pointer("/usage/prompt_tokens").cloned().unwrap_or(json!(0)),
+        })
+    };
+    json_response(builder, &reply)
+}
+
+/// One entry of `/api/tags`
+pub fn tag_entry(model_id: &str, owned_by: &str, size: u64, modified: u64) -> Value {
+    json!({
+        "name": model_id,
+        "model": model_id,
+        "modified_at": rfc3339(modified),
+        "size": size,
+        "digest": "",
+        "details": {
+            "format": if owned_by == "user" { "gguf" } else { "" },
+            "family": "",
+            "families": null,
+            "parameter_size": "",
+            "quantization_level": "",
+        }
+    })
+}
+
+/// The `/api/show` reply; model files and templates are not exposed
+pub fn show_response(model_id: &str, owned_by: &str) -> Value {
+    json!({
+        "modelfile": "",
+        "parameters": "",
+        "template": "",
+        "details": tag_entry(model_id, owned_by, 0, 0)["details"].clone(),
+        "model_info": {},
+        "capabilities": ["completion"],
+        "modified_at": now(),
+    })
+}
@@ -4,34 +4,359 @@ use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server, StatusCode};
 use jan_utils::{is_cors_header, is_valid_host, remove_prefix};
 use reqwest::Client;
+use serde::Serialize;
 use serde_json;
-use std::collections::HashMap;
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use tauri::{AppHandle, Runtime};
+use tokio::process::Child;
 use tokio::sync::Mutex;
 
-use crate::core::state::ServerHandle;
+use super::{anthropic, mcp, ollama, remote, threads, tools};
+use crate::core::chat::helpers::parse_remote_model;
+use crate::core::chat::providers::{parse_configured_model, ProviderConfig};
+use crate::core::chat::ChatService;
+use crate::core::state::{
+    ServerHandle, SharedMcpServers, SharedModelSessions, SharedProviderConfigs,
+    SharedToolCallCancellations,
+};
 
-/// Simple backend session for proxy routing
-#[derive(Clone)]
+/// Backend session for proxy routing, owning the inference server process
 pub struct BackendSession {
+    pub child: Child,
     pub info: SessionInfo,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize)]
 pub struct SessionInfo {
-    pub model_id: String,
+    pub pid: i32,
     pub port: i32,
+    pub model_id: String,
+    pub model_path: String,
     pub api_key: String,
 }
 
-/// Configuration for the proxy server
-#[derive(Clone)]
-struct ProxyConfig {
-    prefix: String,
-    proxy_api_key: String,
-    trusted_hosts: Vec<Vec<String>>,
+/// Configuration of the proxy server and the app state it serves from, shared by all
+/// requests
+pub struct ProxyConfig {
+    pub host: String,
+    pub port: u16,
+    pub prefix: String,
+    pub proxy_api_key: String,
+    pub trusted_hosts: Vec<Vec<String>>,
+    /// Timeout in seconds of requests to model sessions
+    pub proxy_timeout: u64,
+    /// Remote `provider/model` ids listed by the models endpoints
+    pub remote_models: Vec<String>,
+    pub sessions: SharedModelSessions,
+    pub provider_configs: SharedProviderConfigs,
+    pub chat_service: ChatService,
+    pub mcp_servers: SharedMcpServers,
+    pub tool_call_cancellations: SharedToolCallCancellations,
+}
+
+/// Resolves a `provider/model` id served by a remote provider, either one registered
+/// with a provider config or a built-in provider using its environment variables
+async fn resolve_remote_model(
+    config: &ProxyConfig,
+    model_id: &str,
+) -> Option<(String, String, Option<ProviderConfig>)> {
+    let provider_configs = config.provider_configs.lock().await;
+    let (provider, model) = parse_configured_model(model_id, &provider_configs)
+        .or_else(|| parse_remote_model(model_id))?;
+    let provider_config = provider_configs.get(&provider).cloned();
+    Some((provider, model, provider_config))
+}
+
+/// The API key a request carries: a `Bearer` token, or the `x-api-key` header that
+/// Anthropic clients send. An `Authorization` header of another scheme yields `""`.
+fn request_api_key(headers: &hyper::HeaderMap) -> Option<&str> {
+    if let Some(authorization) = headers.get(hyper::header::AUTHORIZATION) {
+        let auth_str = authorization.to_str().unwrap_or("");
+        return Some(auth_str.strip_prefix("Bearer ").unwrap_or(""));
+    }
+    headers
+        .get("x-api-key")
+        .map(|key| key.to_str().unwrap_or(""))
+}
+
+/// Serves `POST /messages` in the Anthropic format: the request is translated to a chat
+/// completion for the model's session or, for remote models, `ChatService`, and the
+/// response is translated back
+async fn handle_anthropic_messages(
+    client: &Client,
+    config: &ProxyConfig,
+    builder: hyper::http::response::Builder,
+    body: Bytes,
+) -> Response<Body> {
+    let body = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(body) => body,
+        Err(e) => {
+            return anthropic::error_response(
+                builder,
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid JSON body: {}", e),
+            )
+        }
+    };
+    let openai_body = match anthropic::openai_request_from_anthropic(&body) {
+        Ok(openai_body) => openai_body,
+        Err(e) => return anthropic::error_response(builder, StatusCode::BAD_REQUEST, &e),
+    };
+    let model_id = openai_body["model"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let stream = openai_body["stream"].as_bool().unwrap_or(false);
+
+    match openai_response(client, config, "/chat/completions", openai_body).await {
+        Ok(response) => {
+            let status = response.status();
+            anthropic::translate_response(builder, status, response.into_body(), stream, model_id)
+                .await
+        }
+        Err((status, message)) => anthropic::error_response(builder, status, &message),
+    }
+}
+
+/// Serves an OpenAI request (`/chat/completions` or `/embeddings`) made on behalf of
+/// another API: forwarded to the model's session, or to `ChatService` for remote models
+async fn openai_response(
+    client: &Client,
+    config: &ProxyConfig,
+    path: &str,
+    body: serde_json::Value,
+) -> Result<Response<Body>, (StatusCode, String)> {
+    let model_id = body["model"].as_str().unwrap_or_default().to_string();
+    let session = config
+        .sessions
+        .lock()
+        .await
+        .values()
+        .find(|s| s.info.model_id == model_id)
+        .map(|s| (s.info.port, s.info.api_key.clone()));
+    if let Some((port, api_key)) = session {
+        log::debug!("Routing {} for {} to port {}", path, model_id, port);
+        let upstream_url = format!("http://127.0.0.1:{}{}", port, path);
+        return match client
+            .post(&upstream_url)
+            .header("Authorization", format!("Bearer {}", api_key))
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+        {
+            Ok(response) => {
+                let status = StatusCode::from_u16(response.status().as_u16())
+                    .unwrap_or(StatusCode::BAD_GATEWAY);
+                let mut builder = Response::builder().status(status);
+                if let Some(content_type) = response.headers().get(reqwest::header::CONTENT_TYPE) {
+                    builder = builder.header(hyper::header::CONTENT_TYPE, content_type.as_bytes());
+                }
+                Ok(builder
+                    .body(Body::wrap_stream(response.bytes_stream()))
+                    .unwrap())
+            }
+            Err(e) => {
+                let error_msg = format!("Proxy request to model failed: {}", e);
+                log::error!("{}", error_msg);
+                Err((StatusCode::BAD_GATEWAY, error_msg))
+            }
+        };
+    }
+
+    let Some((provider, model, provider_config)) = resolve_remote_model(config, &model_id).await
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("No running session found for model '{}'", model_id),
+        ));
+    };
+    log::debug!(
+        "Routing {} for {} to remote provider {}",
+        path,
+        model_id,
+        provider
+    );
+    let handled = if path == "/embeddings" {
+        remote::handle_embeddings(
+            config.chat_service.clone(),
+            Response::builder(),
+            body,
+            model_id,
+            provider,
+            model,
+            provider_config,
+        )
+        .await
+    } else {
+        remote::handle_chat_completion(
+            config.chat_service.clone(),
+            Response::builder(),
+            body,
+            model_id,
+            provider,
+            model,
+            provider_config,
+        )
+        .await
+    };
+    Ok(handled)
+}
+
+/// Serves a chat completion with server-side tool execution. Every model request of
+/// the tool loop goes through `openai_response`.
+async fn handle_tool_request(
+    client: &Client,
+    config: &Arc<ProxyConfig>,
+    builder: hyper::http::response::Builder,
+    body: serde_json::Value,
+    selection: tools::ToolSelection,
+) -> Response<Body> {
+    let (client, config) = (client.clone(), config.clone());
+    let servers = config.mcp_servers.clone();
+    tools::respond_with_tools(builder, servers, body, selection, move |request| {
+        let (client, config) = (client.clone(), config.clone());
+        async move { openai_response(&client, &config, "/chat/completions", request).await }
+    })
+    .await
+}
+
+/// The models the server offers: running sessions, with their model file, and the
+/// configured remote models, as `(id, owned_by, model_path)`
+async fn available_models(config: &ProxyConfig) -> Vec<(String, String, Option<String>)> {
+    let mut models: Vec<_> = config
+        .sessions
+        .lock()
+        .await
+        .values()
+        .map(|session| {
+            (
+                session.info.model_id.clone(),
+                "user".to_string(),
+                Some(session.info.model_path.clone()),
+            )
+        })
+        .collect();
+    let provider_configs = config.provider_configs.lock().await;
+    models.extend(config.remote_models.iter().filter_map(|model_id| {
+        parse_configured_model(model_id, &provider_configs)
+            .or_else(|| parse_remote_model(model_id))
+            .map(|(provider, _)| (model_id.clone(), provider, None))
+    }));
+    models
+}
+
+/// Serves the Ollama API (`/api/...`) by translating to and from the OpenAI requests
+/// the server already handles
+async fn handle_ollama(
+    client: &Client,
+    config: &ProxyConfig,
+    builder: hyper::http::response::Builder,
+    path: &str,
+    body: Bytes,
+) -> Response<Body> {
+    match path {
+        "/api/version" => {
+            return ollama::json_response(
+                builder,
+                &serde_json::json!({ "version": ollama::OLLAMA_COMPAT_VERSION }),
+            )
+        }
+        "/api/tags" | "/api/ps" => {
+            let models: Vec<_> = available_models(config)
+                .await
+                .into_iter()
+                .map(|(model_id, owned_by, model_path)| {
+                    let metadata = model_path.and_then(|path| std::fs::metadata(path).ok());
+                    let size = metadata.as_ref().map_or(0, |m| m.len());
+                    let modified = metadata
+                        .and_then(|m| m.modified().ok())
+                        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map_or(0, |d| d.as_secs());
+                    ollama::tag_entry(&model_id, &owned_by, size, modified)
+                })
+                .collect();
+            return ollama::json_response(builder, &serde_json::json!({ "models": models }));
+        }
+        _ => {}
+    }
+
+    let body = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(body) => body,
+        Err(e) => {
+            return ollama::error_response(
+                builder,
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid JSON body: {}", e),
+            )
+        }
+    };
+    let model_id = body
+        .get("model")
+        .or_else(|| body.get("name"))
+        .and_then(|m| m.as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    let (endpoint, translated) = match path {
+        "/api/show" => {
+            return match available_models(config)
+                .await
+                .into_iter()
+                .find(|(id, _, _)| *id == model_id)
+            {
+                Some((id, owned_by, _)) => {
+                    ollama::json_response(builder, &ollama::show_response(&id, &owned_by))
+                }
+                None => ollama::error_response(
+                    builder,
+                    StatusCode::NOT_FOUND,
+                    &format!("model '{}' not found", model_id),
+                ),
+            }
+        }
+        "/api/embed" | "/api/embeddings" => {
+            let input = match ollama::embedding_input(&body) {
+                Ok(input) => input,
+                Err(e) => return ollama::error_response(builder, StatusCode::BAD_REQUEST, &e),
+            };
+            let request = serde_json::json!({ "model": model_id, "input": input });
+            return match openai_response(client, config, "/embeddings", request).await {
+                Ok(response) => {
+                    ollama::translate_embeddings_response(
+                        builder,
+                        response,
+                        path == "/api/embeddings",
+                        model_id,
+                    )
+                    .await
+                }
+                Err((status, message)) => ollama::error_response(builder, status, &message),
+            };
+        }
+        "/api/chat" => (
+            ollama::OllamaEndpoint::Chat,
+            ollama::openai_request_from_chat(&body).map(Some),
+        ),
+        _ => (
+            ollama::OllamaEndpoint::Generate,
+            ollama::openai_request_from_generate(&body),
+        ),
+    };
+
+    let (request, stream) = match translated {
+        Ok(Some(translated)) => translated,
+        Ok(None) => return ollama::json_response(builder, &ollama::load_response(&model_id)),
+        Err(e) => return ollama::error_response(builder, StatusCode::BAD_REQUEST, &e),
+    };
+    match openai_response(client, config, "/chat/completions", request).await {
+        Ok(response) => {
+            ollama::translate_chat_response(builder, endpoint, response, stream, model_id).await
+        }
+        Err((status, message)) => ollama::error_response(builder, status, &message),
+    }
 }
 
 /// Determines the final destination path based on the original request path
@@ -40,11 +365,11 @@ fn get_destination_path(original_path: &str, prefix: &str) -> String {
 }
 
 /// Handles the proxy request logic
-async fn proxy_request(
+async fn proxy_request<R: Runtime>(
     req: Request<Body>,
+    app_handle: AppHandle<R>,
     client: Client,
-    config: ProxyConfig,
-    sessions: Arc<Mutex<HashMap<i32, BackendSession>>>,
+    config: Arc<ProxyConfig>,
 ) -> Result<Response<Body>, hyper::Error> {
     if req.method() == hyper::Method::OPTIONS {
         log::debug!(
@@ -129,6 +454,9 @@ async fn proxy_request(
         let allowed_headers = [
             "accept",
             "accept-language",
+            "anthropic-beta",
+            "anthropic-dangerous-direct-browser-access",
+            "anthropic-version",
             "authorization",
             "cache-control",
             "connection",
@@ -137,6 +465,9 @@ async fn proxy_request(
             "host",
             "if-modified-since",
             "keep-alive",
+            "last-event-id",
+            "mcp-protocol-version",
+            "mcp-session-id",
             "origin",
             "user-agent",
             "x-api-key",
@@ -144,6 +475,7 @@ async fn proxy_request(
             "x-forwarded-for",
             "x-forwarded-host",
             "x-forwarded-proto",
+            "x-jan-tools",
             "x-requested-with",
             "x-stainless-arch",
             "x-stainless-lang",
@@ -261,10 +593,8 @@ async fn proxy_request(
     }
 
     if !is_whitelisted_path && !config.proxy_api_key.is_empty() {
-        if let Some(authorization) = parts.headers.get(hyper::header::AUTHORIZATION) {
-            let auth_str = authorization.to_str().unwrap_or("");
-
-            if auth_str.strip_prefix("Bearer ") != Some(config.proxy_api_key.as_str()) {
+        if let Some(api_key) = request_api_key(&parts.headers) {
+            if api_key != config.proxy_api_key {
                 let mut error_response = Response::builder().status(StatusCode::UNAUTHORIZED);
                 error_response = add_cors_headers_with_host_and_origin(
                     error_response,
@@ -311,8 +641,82 @@ async fn proxy_request(
     let buffered_body: Option<Bytes>;
     let original_path = parts.uri.path();
     let destination_path = get_destination_path(original_path, &config.prefix);
+    let cors_builder = || {
+        add_cors_headers_with_host_and_origin(
+            Response::builder(),
+            &host_header,
+            &origin_header,
+            &config.trusted_hosts,
+        )
+    };
 
     match (method.clone(), destination_path.as_str()) {
+        (hyper::Method::POST, "/messages") => {
+            let body_bytes = match read_body(body, cors_builder()).await {
+                Ok(bytes) => bytes,
+                Err(response) => return Ok(response),
+            };
+            let response_builder = cors_builder();
+            return Ok(
+                handle_anthropic_messages(&client, &config, response_builder, body_bytes).await,
+            );
+        }
+        (_, "/mcp") => {
+            let body_bytes = match read_body(body, cors_builder()).await {
+                Ok(bytes) => bytes,
+                Err(response) => return Ok(response),
+            };
+            let response_builder = cors_builder();
+            return Ok(mcp::handle_mcp_request(
+                &config.mcp_servers,
+                &config.tool_call_cancellations,
+                response_builder,
+                &method,
+                &headers,
+                body_bytes,
+            )
+            .await);
+        }
+        (_, threads_path)
+            if threads_path == "/threads" || threads_path.starts_with("/threads/") =>
+        {
+            let body_bytes = match read_body(body, cors_builder()).await {
+                Ok(bytes) => bytes,
+                Err(response) => return Ok(response),
+            };
+            let response_builder = cors_builder();
+            return Ok(threads::handle_threads_request(
+                &app_handle,
+                response_builder,
+                &method,
+                threads_path,
+                parts.uri.query(),
+                body_bytes,
+            )
+            .await);
+        }
+        (hyper::Method::GET, "/api/tags")
+        | (hyper::Method::GET, "/api/ps")
+        | (hyper::Method::GET, "/api/version")
+        | (hyper::Method::POST, "/api/chat")
+        | (hyper::Method::POST, "/api/generate")
+        | (hyper::Method::POST, "/api/embed")
+        | (hyper::Method::POST, "/api/embeddings")
+        | (hyper::Method::POST, "/api/show") => {
+            let body_bytes = match read_body(body, cors_builder()).await {
+                Ok(bytes) => bytes,
+                Err(response) => return Ok(response),
+            };
+            let response_builder = cors_builder();
+            return Ok(handle_ollama(
+                &client,
+                &config,
+                response_builder,
+                &destination_path,
+                body_bytes,
+            )
+            .await);
+        }
         (hyper::Method::POST, "/chat/completions")
         | (hyper::Method::POST, "/completions")
         | (hyper::Method::POST, "/embeddings") => {
@@ -320,31 +724,89 @@ async fn proxy_request(
                 "Handling POST request to {} requiring model lookup in body",
                 destination_path
             );
-            let body_bytes = match hyper::body::to_bytes(body).await {
+            let body_bytes = match read_body(body, cors_builder()).await {
                 Ok(bytes) => bytes,
-                Err(_) => {
-                    let mut error_response =
-                        Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR);
-                    error_response = add_cors_headers_with_host_and_origin(
-                        error_response,
-                        &host_header,
-                        &origin_header,
-                        &config.trusted_hosts,
-                    );
-                    return Ok(error_response
-                        .body(Body::from("Failed to read request body"))
-                        .unwrap());
-                }
+                Err(response) => return Ok(response),
             };
             buffered_body = Some(body_bytes.clone());
 
             match serde_json::from_slice::<serde_json::Value>(&body_bytes) {
                 Ok(json_body) => {
+                    if destination_path == "/chat/completions" {
+                        if let Some(selection) = tools::tool_selection(&json_body, &headers) {
+                            log::debug!("Running server-side tools for {}", destination_path);
+                            let response_builder = add_cors_headers_with_host_and_origin(
+                                Response::builder(),
+                                &host_header,
+                                &origin_header,
+                                &config.trusted_hosts,
+                            );
+                            return Ok(handle_tool_request(
+                                &client,
+                                &config,
+                                response_builder,
+                                json_body,
+                                selection,
+                            )
+                            .await);
+                        }
+                    }
                     if let Some(model_id) = json_body.get("model").and_then(|v| v.as_str()) {
                         log::debug!("Extracted model_id: {}", model_id);
-                        let sessions_guard = sessions.lock().await;
+                        // Released before the remote lookup takes the provider configs lock
+                        let (any_session, session) = {
+                            let sessions_guard = config.sessions.lock().await;
+                            (
+                                !sessions_guard.is_empty(),
+                                sessions_guard
+                                    .values()
+                                    .find(|s| s.info.model_id == model_id)
+                                    .map(|s| (s.info.port, s.info.api_key.clone())),
+                            )
+                        };
+                        if session.is_none() && destination_path != "/completions" {
+                            if let Some((provider, model, provider_config)) =
+                                resolve_remote_model(&config, model_id).await
+                            {
+                                log::debug!(
+                                    "Routing model_id {} to remote provider {}",
+                                    model_id,
+                                    provider
+                                );
+                                let response_builder = add_cors_headers_with_host_and_origin(
+                                    Response::builder(),
+                                    &host_header,
+                                    &origin_header,
+                                    &config.trusted_hosts,
+                                );
+                                let handled = if destination_path == "/embeddings" {
+                                    remote::handle_embeddings(
+                                        config.chat_service.clone(),
+                                        response_builder,
+                                        json_body.clone(),
+                                        model_id.to_string(),
+                                        provider,
+                                        model,
+                                        provider_config,
+                                    )
+                                    .await
+                                } else {
+                                    remote::handle_chat_completion(
+                                        config.chat_service.clone(),
+                                        response_builder,
+                                        json_body.clone(),
+                                        model_id.to_string(),
+                                        provider,
+                                        model,
+                                        provider_config,
+                                    )
+                                    .await
+                                };
+                                return Ok(handled);
+                            }
+                        }
 
-                        if sessions_guard.is_empty() {
+                        if !any_session {
                             log::warn!(
                                 "Request for model '{}' but no models are running.",
                                 model_id
@@ -362,12 +824,9 @@ async fn proxy_request(
                                 .unwrap());
                         }
 
-                        if let Some(session) = sessions_guard
-                            .values()
-                            .find(|s| s.info.model_id == model_id)
-                        {
-                            target_port = Some(session.info.port);
-                            session_api_key = Some(session.info.api_key.clone());
+                        if let Some((port, api_key)) = session {
+                            target_port = Some(port);
+                            session_api_key = Some(api_key);
                             log::debug!("Found session for model_id {}", model_id,);
                         } else {
                             log::warn!("No running session found for model_id: {}", model_id);
@@ -425,16 +884,15 @@ async fn proxy_request(
         }
         (hyper::Method::GET, "/models") => {
             log::debug!("Handling GET /v1/models request");
-            let sessions_guard = sessions.lock().await;
-
-            let models_data: Vec<_> = sessions_guard
-                .values()
-                .map(|session| {
+            let models_data: Vec<_> = available_models(&config)
+                .await
+                .into_iter()
+                .map(|(model_id, owned_by, _)| {
                     serde_json::json!({
-                        "id": session.info.model_id,
+                        "id": model_id,
                         "object": "model",
                         "created": 1,
-                        "owned_by": "user"
+                        "owned_by": owned_by
                     })
                 })
                 .collect();
@@ -515,7 +973,10 @@ async fn proxy_request(
     let mut outbound_req = client.request(method.clone(), &upstream_url);
 
     for (name, value) in headers.iter() {
-        if name != hyper::header::HOST && name != hyper::header::AUTHORIZATION {
+        if name != hyper::header::HOST
+            && name != hyper::header::AUTHORIZATION
+            && name != "x-api-key"
+        {
             outbound_req = outbound_req.header(name, value);
         }
     }
@@ -602,6 +1063,20 @@ async fn proxy_request(
     }
 }
 
+/// Collects a request body, or builds the 500 response to return when it can't be read
+async fn read_body(
+    body: Body,
+    builder: hyper::http::response::Builder,
+) -> Result<Bytes, Response<Body>> {
+    hyper::body::to_bytes(body).await.map_err(|e| {
+        log::error!("Failed to read request body: {}", e);
+        builder
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from("Failed to read request body"))
+            .unwrap()
+    })
+}
+
 fn add_cors_headers_with_host_and_origin(
     builder: hyper::http::response::Builder,
     host: &str,
@@ -620,7 +1095,8 @@ fn add_cors_headers_with_host_and_origin(
     builder = builder
         .header("Access-Control-Allow-Origin", allow_origin_header.clone())
         .header("Access-Control-Allow-Methods", "GET, POST, PUT, DELETE, OPTIONS, PATCH")
-        .header("Access-Control-Allow-Headers", "Authorization, Content-Type, Host, Accept, Accept-Language, Cache-Control, Connection, DNT, If-Modified-Since, Keep-Alive, Origin, User-Agent, X-Requested-With, X-CSRF-Token, X-Forwarded-For, X-Forwarded-Proto, X-Forwarded-Host, authorization, content-type, x-api-key")
+        .header("Access-Control-Allow-Headers", "Authorization, Content-Type, Host, Accept, Accept-Language, Cache-Control, Connection, DNT, If-Modified-Since, Keep-Alive, Origin, User-Agent, X-Requested-With, X-CSRF-Token, X-Forwarded-For, X-Forwarded-Proto, X-Forwarded-Host, authorization, content-type, x-api-key, anthropic-version, anthropic-beta, anthropic-dangerous-direct-browser-access")
+        .header("Access-Control-Expose-Headers", mcp::SESSION_HEADER)
         .header("Vary", "Origin");
 
     if allow_origin_header != "*" {
@@ -635,45 +1111,34 @@ pub async fn is_server_running(server_handle: Arc<Mutex<Option<ServerHandle>>>)
     handle_guard.is_some()
 }
 
-pub async fn start_server(
+pub async fn start_server<R: Runtime>(
+    app_handle: AppHandle<R>,
     server_handle: Arc<Mutex<Option<ServerHandle>>>,
-    sessions: Arc<Mutex<HashMap<i32, BackendSession>>>,
-    host: String,
-    port: u16,
-    prefix: String,
-    proxy_api_key: String,
-    trusted_hosts: Vec<Vec<String>>,
-    proxy_timeout: u64,
+    config: Arc<ProxyConfig>,
 ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
     let mut handle_guard = server_handle.lock().await;
     if handle_guard.is_some() {
         return Err("Server is already running".into());
     }
 
-    let addr: SocketAddr = format!("{}:{}", host, port)
+    let addr: SocketAddr = format!("{}:{}", config.host, config.port)
         .parse()
         .map_err(|e| format!("Invalid address: {}", e))?;
 
-    let config = ProxyConfig {
-        prefix,
-        proxy_api_key,
-        trusted_hosts,
-    };
-
     let client = Client::builder()
-        .timeout(std::time::Duration::from_secs(proxy_timeout))
+        .timeout(std::time::Duration::from_secs(config.proxy_timeout))
         .pool_max_idle_per_host(10)
         .pool_idle_timeout(std::time::Duration::from_secs(30))
         .build()?;
 
     let make_svc = make_service_fn(move |_conn| {
+        let app_handle = app_handle.clone();
         let client = client.clone();
         let config = config.clone();
-        let sessions = sessions.clone();
 
         async move {
             Ok::<_, Infallible>(service_fn(move |req| {
-                proxy_request(req, client.clone(), config.clone(), sessions.clone())
+                proxy_request(req, app_handle.clone(), client.clone(), config.clone())
             }))
         }
     });
@@ -0,0 +1,675 @@
+//! Serves remote provider models (`provider/model` ids) through the OpenAI-compatible
+//! API by translating requests and stream events to and from `ChatService`, and their
+//! embeddings through `ChatService::embed`.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hyper::body::Bytes;
+use hyper::http::response::Builder;
+use hyper::{Body, Response, StatusCode};
+use rig::completion::ToolDefinition;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::core::chat::content::{parse_data_uri, ChatContent, ContentPart, MediaSource};
+use crate::core::chat::embeddings::EmbeddingResponse;
+use crate::core::chat::helpers::{params_from_model_settings, run_chat, spawn_chat_stream};
+use crate::core::chat::providers::ProviderConfig;
+use crate::core::chat::retry::error_status;
+use crate::core::chat::ChatService;
+use crate::core::chat::{
+    ChatMessage, ChatRequest, ChatStreamEvent, CompletionMetadata, GenerationParams, ToolCallInfo,
+    ToolChoice,
+};
+
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Converts one OpenAI content part. `image_url` and `file` parts carry a URL or a
+/// `data:` URI; local paths are never read for API clients.
+fn content_part(part: &Value) -> Option<ContentPart> {
+    let str_field =
+        |value: &Value, key: &str| value.get(key).and_then(|v| v.as_str()).map(String::from);
+    match part.get("type").and_then(|t| t.as_str())? {
+        "text" => Some(ContentPart::Text {
+            text: str_field(part, "text")?,
+        }),
+        "image_url" => {
+            let image = part.get("image_url")?;
+            let url = image
+                .as_str()
+                .map(String::from)
+                .or_else(|| str_field(image, "url"))?;
+            Some(ContentPart::Image(MediaSource {
+                url: Some(url),
+                ..Default::default()
+            }))
+        }
+        "file" => {
+            let file = part.get("file")?;
+            let (media_type, data) = parse_data_uri(&str_field(file, "file_data")?)?;
+            Some(ContentPart::Document(MediaSource {
+                data: Some(data),
+                media_type: Some(media_type),
+                name: str_field(file, "filename"),
+                ..Default::default()
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// Converts OpenAI message content (a string or an array of content parts)
+pub fn message_content(content: &Value) -> ChatContent {
+    match content {
+        Value::String(text) => ChatContent::Text(text.clone()),
+        Value::Array(parts) => ChatContent::Parts(parts.iter().filter_map(content_part).collect()),
+        _ => ChatContent::default(),
+    }
+}
+
+/// Reads the sampling parameters of an OpenAI chat completion request
+pub fn generation_params_from_openai(body: &Value) -> GenerationParams {
+    // OpenAI uses the same field names as the model settings
+    let mut params = params_from_model_settings(body);
+    if let Some(max_tokens) = body.get("max_completion_tokens").and_then(|v| v.as_u64()) {
+        params.max_tokens = Some(max_tokens);
+    }
+    params
+}
+
+/// Reads the `tools` of an OpenAI request. Only `function` tools are supported.
+fn tools_from_openai(body: &Value) -> Result<Vec<ToolDefinition>, String> {
+    let tools = match body.get("tools") {
+        None | Some(Value::Null) => return Ok(Vec::new()),
+        Some(Value::Array(tools)) => tools,
+        Some(_) => return Err("'tools' must be an array".to_string()),
+    };
+    tools
+        .iter()
+        .map(|tool| {
+            let kind = tool
+                .get("type")
+                .and_then(|t| t.as_str())
+                .unwrap_or_default();
+            if kind != "function" {
+                return Err(format!(
+                    "Unsupported tool type '{}' in 'tools', only 'function' tools are supported",
+                    kind
+                ));
+            }
+            let function = tool.get("function").unwrap_or(&Value::Null);
+            let name = function
+                .get("name")
+                .and_then(|n| n.as_str())
+                .ok_or("'tools[].function.name' is required")?;
+            Ok(ToolDefinition {
+                name: name.to_string(),
+                description: function
+                    .get("description")
+                    .and_then(|d| d.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                parameters: function
+                    .get("parameters")
+                    .cloned()
+                    .unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+            })
+        })
+        .collect()
+}
+
+/// Reads the `tool_choice` of an OpenAI request
+fn tool_choice_from_openai(body: &Value) -> Result<Option<ToolChoice>, String> {
+    let choice = match body.get("tool_choice") {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::String(choice)) => match choice.as_str() {
+            "auto" => ToolChoice::Auto,
+            "none" => ToolChoice::None,
+            "required" => ToolChoice::Required,
+            other => return Err(format!("Unsupported 'tool_choice' value '{}'", other)),
+        },
+        Some(choice) => choice
+            .pointer("/function/name")
+            .and_then(|n| n.as_str())
+            .map(|name| ToolChoice::Tool(name.to_string()))
+            .ok_or("'tool_choice' must be 'auto', 'none', 'required' or a function")?,
+    };
+    Ok(Some(choice))
+}
+
+/// Reads the `tool_calls` of an OpenAI assistant message. The arguments arrive as a
+/// JSON-encoded string and are kept parsed so every provider gets an object.
+fn tool_calls_from_openai(message: &Value) -> Result<Vec<ToolCallInfo>, String> {
+    let Some(calls) = message.get("tool_calls").and_then(|c| c.as_array()) else {
+        return Ok(Vec::new());
+    };
+    calls
+        .iter()
+        .map(|call| {
+            let id = call
+                .get("id")
+                .and_then(|id| id.as_str())
+                .ok_or("'tool_calls[].id' is required")?;
+            let name = call
+                .pointer("/function/name")
+                .and_then(|n| n.as_str())
+                .ok_or("'tool_calls[].function.name' is required")?;
+            let arguments = match call.pointer("/function/arguments") {
+                None | Some(Value::Null) => json!({}),
+                Some(Value::String(text)) if text.trim().is_empty() => json!({}),
+                Some(Value::String(text)) => {
+                    serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.clone()))
+                }
+                Some(arguments) => arguments.clone(),
+            };
+            Ok(ToolCallInfo {
+                id: id.to_string(),
+                call_id: None,
+                name: name.to_string(),
+                arguments,
+            })
+        })
+        .collect()
+}
+
+/// Converts one OpenAI history message, keeping the tool calls of assistant messages
+/// and the call id of tool results
+fn chat_message_from_openai(message: &Value) -> Result<ChatMessage, String> {
+    let role = message
+        .get("role")
+        .and_then(|r| r.as_str())
+        .unwrap_or("user");
+    if role == "function" {
+        return Err("Messages with role 'function' are not supported, use 'tool'".to_string());
+    }
+    let tool_call_id = message
+        .get("tool_call_id")
+        .and_then(|id| id.as_str())
+        .map(String::from);
+    if role == "tool" && tool_call_id.is_none() {
+        return Err("'tool_call_id' is required on messages with role 'tool'".to_string());
+    }
+
+    Ok(ChatMessage {
+        role: role.to_string(),
+        content: message_content(message.get("content").unwrap_or(&Value::Null)),
+        timestamp: None,
+        tool_calls: tool_calls_from_openai(message)?,
+        tool_call_id,
+    })
+}
+
+/// Converts an OpenAI chat completion request body into a `ChatRequest`.
+/// A last `user` message becomes the prompt. A conversation ending with `tool`
+/// messages keeps them in the history and the model continues after them.
+/// System messages stay in the history and end up in the preamble.
+pub fn chat_request_from_openai(
+    body: &Value,
+    provider: &str,
+    model: &str,
+) -> Result<ChatRequest, String> {
+    if body.get("functions").is_some() || body.get("function_call").is_some() {
+        return Err("'functions' and 'function_call' are not supported, use 'tools'".to_string());
+    }
+    let messages = body
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .ok_or("Request body must contain a 'messages' array")?;
+    let (last, history) = messages
+        .split_last()
+        .ok_or("'messages' must not be empty")?;
+    let (prompt, history) = match last.get("role").and_then(|r| r.as_str()) {
+        Some("user") => (
+            message_content(last.get("content").unwrap_or(&Value::Null)),
+            history,
+        ),
+        Some("tool") => (ChatContent::default(), messages.as_slice()),
+        _ => return Err("The last message must have role 'user' or 'tool'".to_string()),
+    };
+
+    let chat_history = history
+        .iter()
+        .map(chat_message_from_openai)
+        .collect::<Result<Vec<_>, _>>()?;
+    let attachments = prompt
+        .parts()
+        .into_iter()
+        .filter(|part| !matches!(part, ContentPart::Text { .. }))
+        .collect();
+
+    Ok(ChatRequest {
+        prompt: prompt.text(),
+        provider: provider.to_string(),
+        model: model.to_string(),
+        stream_id: None,
+        chat_history: Some(chat_history),
+        attachments,
+        supports_vision: None,
+        instructions: None,
+        params: generation_params_from_openai(body),
+        provider_config: None,
+        context: None,
+        retry: None,
+        fallbacks: Vec::new(),
+        response_format: body
+            .get("response_format")
+            .filter(|format| !format.is_null())
+            .map(|format| serde_json::from_value(format.clone()))
+            .transpose()
+            .map_err(|e| format!("Invalid 'response_format': {}", e))?,
+        validation_retries: None,
+        tools: tools_from_openai(body)?,
+        tool_choice: tool_choice_from_openai(body)?,
+    })
+}
+
+/// Maps completion metadata onto an OpenAI `usage` object
+fn usage_value(metadata: Option<&CompletionMetadata>) -> Value {
+    match metadata.and_then(|m| m.usage) {
+        Some(usage) => json!({
+            "prompt_tokens": usage.prompt_tokens,
+            "completion_tokens": usage.completion_tokens,
+            "total_tokens": usage.total_tokens
+        }),
+        None => Value::Null,
+    }
+}
+
+/// Maps a tool call onto an OpenAI `tool_calls` entry, whose arguments are a JSON string
+fn openai_tool_call(call: &ToolCallInfo) -> Value {
+    let arguments = match &call.arguments {
+        Value::String(arguments) => arguments.clone(),
+        arguments => arguments.to_string(),
+    };
+    json!({
+        "id": call.id,
+        "type": "function",
+        "function": { "name": call.name, "arguments": arguments }
+    })
+}
+
+/// Builds a non-streaming `chat.completion` response body
+pub fn completion_response(
+    completion_id: &str,
+    model_id: &str,
+    content: &str,
+    tool_calls: &[ToolCallInfo],
+    metadata: Option<&CompletionMetadata>,
+) -> Value {
+    let finish_reason = metadata.map_or("stop", |m| m.finish_reason.as_str());
+    let mut message = json!({ "role": "assistant", "content": content });
+    if !tool_calls.is_empty() {
+        if content.is_empty() {
+            message["content"] = Value::Null;
+        }
+        message["tool_calls"] = tool_calls.iter().map(openai_tool_call).collect();
+    }
+    json!({
+        "id": completion_id,
+        "object": "chat.completion",
+        "created": unix_timestamp(),
+        "model": model_id,
+        "choices": [{
+            "index": 0,
+            "message": message,
+            "finish_reason": finish_reason
+        }],
+        "usage": usage_value(metadata)
+    })
+}
+
+/// Builds a `chat.completion.chunk` stream payload
+pub fn completion_chunk(
+    completion_id: &str,
+    model_id: &str,
+    delta: Value,
+    finish_reason: Option<&str>,
+) -> Value {
+    json!({
+        "id": completion_id,
+        "object": "chat.completion.chunk",
+        "created": unix_timestamp(),
+        "model": model_id,
+        "choices": [{
+            "index": 0,
+            "delta": delta,
+            "finish_reason": finish_reason
+        }]
+    })
+}
+
+/// Builds the stream chunk of a tool call, the `index`-th of the completion. The call
+/// is sent whole, as rig reports it once its arguments are complete.
+pub fn tool_call_chunk(
+    completion_id: &str,
+    model_id: &str,
+    index: usize,
+    call: &ToolCallInfo,
+) -> Value {
+    let mut tool_call = openai_tool_call(call);
+    tool_call["index"] = json!(index);
+    completion_chunk(
+        completion_id,
+        model_id,
+        json!({ "tool_calls": [tool_call] }),
+        None,
+    )
+}
+
+/// Maps a chat stream event onto an OpenAI stream chunk, if it has an equivalent.
+/// `tool_call` events are numbered by the caller, see `tool_call_chunk`.
+pub fn chunk_from_event(
+    completion_id: &str,
+    model_id: &str,
+    event: &ChatStreamEvent,
+) -> Option<Value> {
+    let (delta, finish_reason) = match event.event_type.as_str() {
+        "start" => (json!({ "role": "assistant", "content": "" }), None),
+        "text" => (json!({ "content": event.content }), None),
+        "reasoning" => (json!({ "reasoning_content": event.content }), None),
+        "complete" => {
+            let finish_reason = event
+                .metadata
+                .as_ref()
+                .map_or("stop", |m| m.finish_reason.as_str());
+            let mut chunk =
+                completion_chunk(completion_id, model_id, json!({}), Some(finish_reason));
+            let usage = usage_value(event.metadata.as_ref());
+            if !usage.is_null() {
+                chunk["usage"] = usage;
+            }
+            return Some(chunk);
+        }
+        _ => return None,
+    };
+    Some(completion_chunk(
+        completion_id,
+        model_id,
+        delta,
+        finish_reason,
+    ))
+}
+
+pub fn sse_data(value: &Value) -> Bytes {
+    Bytes::from(format!("data: {}\n\n", value))
+}
+
+/// An OpenAI error body
+pub fn openai_error(status: StatusCode, message: &str) -> Value {
+    let (error_type, code) = match status.as_u16() {
+        401 => ("authentication_error", Some("invalid_api_key")),
+        403 => ("permission_error", None),
+        404 => ("not_found_error", Some("model_not_found")),
+        429 => ("rate_limit_error", Some("rate_limit_exceeded")),
+        400..=499 => ("invalid_request_error", None),
+        _ => ("api_error", None),
+    };
+    json!({ "error": { "message": message, "type": error_type, "code": code } })
+}
+
+/// Responds with an OpenAI error body
+pub fn openai_error_response(
+    builder: Builder,
+    status: StatusCode,
+    message: &str,
+) -> Response<Body> {
+    builder
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(openai_error(status, message).to_string()))
+        .unwrap()
+}
+
+/// The status a failed provider request is answered with: the provider's own status
+/// when the client can act on it (bad request, auth, unknown model, rate limit),
+/// 502 otherwise
+pub fn provider_error_status(error: &str) -> StatusCode {
+    match error_status(error) {
+        Some(status @ (400 | 401 | 403 | 404 | 413 | 422 | 429)) => {
+            StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY)
+        }
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+pub fn sse_error(message: &str) -> Bytes {
+    sse_data(&json!({
+        "error": { "message": message, "type": "provider_error" }
+    }))
+}
+
+/// Handles `POST /chat/completions` for a remote provider model
+///
+/// # Arguments
+/// * `chat_service` - The app's chat service
+/// * `builder` - Response builder with CORS headers already applied
+/// * `body` - The OpenAI request body
+/// * `model_id` - The requested `provider/model` id, echoed back in responses
+/// * `provider` / `model` - The parsed parts of `model_id`
+/// * `provider_config` - The registered config for `provider`, if any
+pub async fn handle_chat_completion(
+    chat_service: ChatService,
+    builder: Builder,
+    body: Value,
+    model_id: String,
+    provider: String,
+    model: String,
+    provider_config: Option<ProviderConfig>,
+) -> Response<Body> {
+    let request = match chat_request_from_openai(&body, &provider, &model) {
+        Ok(request) => ChatRequest {
+            provider_config,
+            ..request
+        },
+        Err(e) => {
+            log::warn!("Invalid chat completion request for {}: {}", model_id, e);
+            return openai_error_response(builder, StatusCode::BAD_REQUEST, &e);
+        }
+    };
+    let completion_id = format!("chatcmpl-{}", uuid::Uuid::new_v4());
+
+    if !body
+        .get("stream")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+    {
+        return match run_chat(&chat_service, request).await {
+            Ok(response) => builder
+                .status(StatusCode::OK)
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    completion_response(
+                        &completion_id,
+                        &model_id,
+                        &response.content,
+                        &response.tool_calls,
+                        response.metadata.as_ref(),
+                    )
+                    .to_string(),
+                ))
+                .unwrap(),
+            Err(e) => {
+                log::error!("Remote chat completion for {} failed: {}", model_id, e);
+                openai_error_response(builder, provider_error_status(&e), &e)
+            }
+        };
+    }
+
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<ChatStreamEvent>();
+    let cancel_token = CancellationToken::new();
+    let chat_task = spawn_chat_stream(chat_service, request, cancel_token.clone(), move |event| {
+        let _ = event_tx.send(event);
+    });
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        let mut finished = false;
+        let mut tool_calls = 0;
+        while let Some(event) = event_rx.recv().await {
+            if event.event_type == "error" {
+                let _ = sender.send_data(sse_error(&event.content)).await;
+                finished = true;
+                break;
+            }
+            let chunk = if event.event_type == "tool_call" {
+                serde_json::from_str::<ToolCallInfo>(&event.content)
+                    .ok()
+                    .map(|call| {
+                        tool_calls += 1;
+                        tool_call_chunk(&completion_id, &model_id, tool_calls - 1, &call)
+                    })
+            } else {
+                chunk_from_event(&completion_id, &model_id, &event)
+            };
+            if let Some(chunk) = chunk {
+                if sender.send_data(sse_data(&chunk)).await.is_err() {
+                    log::debug!("Client disconnected during streaming, cancelling");
+                    cancel_token.cancel();
+                    return;
+                }
+            }
+            if event.is_final {
+                finished = true;
+                break;
+            }
+        }
+
+        if !finished {
+            let message = match chat_task.await {
+                Ok(Err(e)) => e,
+                Err(e) => format!("Task failed: {}", e),
+                Ok(Ok(_)) => "Stream ended unexpectedly".to_string(),
+            };
+            log::error!("Remote chat stream for {} failed: {}", model_id, message);
+            let _ = sender.send_data(sse_error(&message)).await;
+        }
+        let _ = sender
+            .send_data(Bytes::from_static(b"data: [DONE]\n\n"))
+            .await;
+        log::debug!("Streaming complete to client");
+    });
+
+    builder
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+        .header(hyper::header::CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap()
+}
+
+/// Reads a whole OpenAI JSON response, returning the upstream error message on failure
+pub async fn read_json_response(response: Response<Body>) -> Result<Value, (StatusCode, String)> {
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?;
+    let parsed = serde_json::from_slice::<Value>(&bytes).ok();
+    if !status.is_success() {
+        let message = parsed
+            .as_ref()
+            .and_then(|body| body.pointer("/error/message").and_then(|m| m.as_str()))
+            .map(String::from)
+            .unwrap_or_else(|| String::from_utf8_lossy(&bytes).to_string());
+        return Err((status, message));
+    }
+    parsed.ok_or((
+        StatusCode::BAD_GATEWAY,
+        "The model returned an invalid response".to_string(),
+    ))
+}
+
+/// Reads the `input` of an OpenAI embeddings request: a string or an array of strings.
+/// Token arrays are not supported, remote providers take text.
+pub fn embedding_inputs(body: &Value) -> Result<Vec<String>, String> {
+    match body.get("input") {
+        Some(Value::String(input)) => Ok(vec![input.clone()]),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map(String::from)
+                    .ok_or_else(|| "'input' must be a string or an array of strings".to_string())
+            })
+            .collect(),
+        _ => Err("Request body must contain an 'input' field".to_string()),
+    }
+}
+
+/// Builds an OpenAI embeddings response. With `encoding_format` `base64` each vector
+/// is sent as base64 of little-endian `f32`s, as OpenAI does.
+pub fn embeddings_response(model_id: &str, response: &EmbeddingResponse, base64: bool) -> Value {
+    let data: Vec<Value> = response
+        .embeddings
+        .iter()
+        .enumerate()
+        .map(|(index, vector)| {
+            let embedding = if base64 {
+                let bytes: Vec<u8> = vector
+                    .iter()
+                    .flat_map(|v| (*v as f32).to_le_bytes())
+                    .collect();
+                json!(BASE64.encode(bytes))
+            } else {
+                json!(vector)
+            };
+            json!({ "object": "embedding", "index": index, "embedding": embedding })
+        })
+        .collect();
+    json!({
+        "object": "list",
+        "data": data,
+        "model": model_id,
+        "usage": {
+            "prompt_tokens": response.usage.prompt_tokens,
+            "total_tokens": response.usage.total_tokens,
+        }
+    })
+}
+
+/// Handles `POST /embeddings` for a remote provider model
+///
+/// # Arguments
+/// * `chat_service` - The app's chat service
+/// * `builder` - Response builder with CORS headers already applied
+/// * `body` - The OpenAI request body
+/// * `model_id` - The requested `provider/model` id, echoed back in the response
+/// * `provider` / `model` - The parsed parts of `model_id`
+/// * `provider_config` - The registered config for `provider`, if any
+pub async fn handle_embeddings(
+    chat_service: ChatService,
+    builder: Builder,
+    body: Value,
+    model_id: String,
+    provider: String,
+    model: String,
+    provider_config: Option<ProviderConfig>,
+) -> Response<Body> {
+    let inputs = match embedding_inputs(&body) {
+        Ok(inputs) => inputs,
+        Err(e) => return openai_error_response(builder, StatusCode::BAD_REQUEST, &e),
+    };
+    let base64 = body.get("encoding_format").and_then(|v| v.as_str()) == Some("base64");
+
+    match chat_service
+        .embed(&provider, &model, inputs, provider_config.as_ref())
+        .await
+    {
+        Ok(response) => builder
+            .status(StatusCode::OK)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                embeddings_response(&model_id, &response, base64).to_string(),
+            ))
+            .unwrap(),
+        Err(e) => {
+            log::error!("Remote embeddings for {} failed: {}", model_id, e);
+            openai_error_response(builder, provider_error_status(&e), &e)
+        }
+    }
+}
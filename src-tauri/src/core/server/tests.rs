@@ -0,0 +1,1760 @@
+use super::anthropic::*;
+use super::mcp;
+use super::ollama::{self, OllamaEndpoint, OllamaStream};
+use super::remote::*;
+use super::threads::{self as threads_api, PageQuery, ThreadRoute};
+use super::tools::{self, ToolSelection};
+use crate::core::chat::content::{ContentPart, MediaSource};
+use crate::core::chat::embeddings::{EmbeddingResponse, EmbeddingUsage};
+use crate::core::chat::helpers::parse_remote_model;
+use crate::core::chat::{
+    ChatService, ChatStreamEvent, ChatUsage, CompletionMetadata, ToolCallInfo, ToolChoice,
+};
+use serde_json::json;
+
+#[test]
+fn test_parse_remote_model() {
+    assert_eq!(
+        parse_remote_model("anthropic/claude-3-5-haiku-latest"),
+        Some((
+            "anthropic".to_string(),
+            "claude-3-5-haiku-latest".to_string()
+        ))
+    );
+    assert_eq!(parse_remote_model("unsloth/Qwen3-4B-GGUF"), None);
+    assert_eq!(parse_remote_model("openai/"), None);
+    assert_eq!(parse_remote_model("llama3.2"), None);
+}
+
+#[test]
+fn test_chat_request_from_openai() {
+    let body = json!({
+        "model": "openai/gpt-4o-mini",
+        "messages": [
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "Hi" },
+            { "role": "assistant", "content": "Hello!" },
+            { "role": "user", "content": [
+                { "type": "text", "text": "What is" },
+                { "type": "text", "text": "Rust?" }
+            ]}
+        ]
+    });
+
+    let request = chat_request_from_openai(&body, "openai", "gpt-4o-mini").unwrap();
+    assert_eq!(request.prompt, "What is\nRust?");
+    assert_eq!(request.provider, "openai");
+    let history = request.chat_history.unwrap();
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[0].role, "system");
+    assert_eq!(history[2].content.text(), "Hello!");
+    assert!(request.attachments.is_empty());
+}
+
+#[test]
+fn test_chat_request_from_openai_with_image() {
+    let body = json!({
+        "messages": [
+            { "role": "user", "content": [
+                { "type": "text", "text": "What is in this picture?" },
+                { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" } }
+            ]}
+        ]
+    });
+
+    let request = chat_request_from_openai(&body, "openai", "gpt-4o").unwrap();
+    assert_eq!(request.prompt, "What is in this picture?");
+    assert_eq!(
+        request.attachments,
+        vec![ContentPart::Image(MediaSource {
+            url: Some("data:image/png;base64,iVBORw0KGgo=".to_string()),
+            ..Default::default()
+        })]
+    );
+}
+
+#[test]
+fn test_generation_params_from_openai() {
+    let body = json!({
+        "temperature": 0.3,
+        "max_tokens": 100,
+        "max_completion_tokens": 200,
+        "stop": ["\n\n"],
+        "seed": 42
+    });
+    let params = generation_params_from_openai(&body);
+    assert_eq!(params.temperature, Some(0.3));
+    assert_eq!(params.max_tokens, Some(200));
+    assert_eq!(params.stop, Some(vec!["\n\n".to_string()]));
+    assert_eq!(params.seed, Some(42));
+    assert_eq!(params.top_p, None);
+}
+
+#[test]
+fn test_chat_request_from_openai_requires_user_prompt() {
+    let body = json!({ "messages": [{ "role": "assistant", "content": "Hi" }] });
+    assert!(chat_request_from_openai(&body, "openai", "gpt-4o").is_err());
+
+    let body = json!({ "messages": [] });
+    assert!(chat_request_from_openai(&body, "openai", "gpt-4o").is_err());
+}
+
+#[test]
+fn test_chunk_from_event() {
+    let event = ChatStreamEvent {
+        stream_id: "s".to_string(),
+        content: "Hel".to_string(),
+        event_type: "text".to_string(),
+        is_final: false,
+        seq: 0,
+        metadata: None,
+        context: None,
+        compare: None,
+    };
+    let chunk = chunk_from_event("chatcmpl-1", "openai/gpt-4o", &event).unwrap();
+    assert_eq!(chunk["object"], "chat.completion.chunk");
+    assert_eq!(chunk["choices"][0]["delta"]["content"], "Hel");
+    assert!(chunk["choices"][0]["finish_reason"].is_null());
+
+    let event = ChatStreamEvent {
+        event_type: "complete".to_string(),
+        is_final: true,
+        ..event
+    };
+    let chunk = chunk_from_event("chatcmpl-1", "openai/gpt-4o", &event).unwrap();
+    assert_eq!(chunk["choices"][0]["finish_reason"], "stop");
+    assert!(chunk.get("usage").is_none());
+
+    let event = event.with_metadata(CompletionMetadata {
+        usage: Some(ChatUsage {
+            prompt_tokens: 12,
+            completion_tokens: 30,
+            total_tokens: 42,
+        }),
+        finish_reason: "length".to_string(),
+        ..Default::default()
+    });
+    let chunk = chunk_from_event("chatcmpl-1", "openai/gpt-4o", &event).unwrap();
+    assert_eq!(chunk["choices"][0]["finish_reason"], "length");
+    assert_eq!(chunk["usage"]["total_tokens"], 42);
+
+    let event = ChatStreamEvent {
+        event_type: "tool_call".to_string(),
+        ..event
+    };
+    assert!(chunk_from_event("chatcmpl-1", "openai/gpt-4o", &event).is_none());
+}
+
+fn weather_tool() -> serde_json::Value {
+    json!({
+        "type": "function",
+        "function": {
+            "name": "get_weather",
+            "description": "Weather for a city",
+            "parameters": { "type": "object", "properties": { "city": { "type": "string" } } }
+        }
+    })
+}
+
+#[test]
+fn test_chat_request_from_openai_with_tool_calls() {
+    let body = json!({
+        "tools": [weather_tool()],
+        "tool_choice": { "type": "function", "function": { "name": "get_weather" } },
+        "messages": [
+            { "role": "user", "content": "Weather in Paris and Rome?" },
+            { "role": "assistant", "content": null, "tool_calls": [
+                { "id": "call_1", "type": "function",
+                  "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" } },
+                { "id": "call_2", "type": "function",
+                  "function": { "name": "get_weather", "arguments": "" } }
+            ]},
+            { "role": "tool", "tool_call_id": "call_1", "content": "Sunny" },
+            { "role": "tool", "tool_call_id": "call_2", "content": [{ "type": "text", "text": "Rainy" }] }
+        ]
+    });
+
+    let request = chat_request_from_openai(&body, "openai", "gpt-4o").unwrap();
+    assert_eq!(request.prompt, "");
+    assert_eq!(request.tools.len(), 1);
+    assert_eq!(request.tools[0].name, "get_weather");
+    assert_eq!(
+        request.tools[0].parameters["properties"]["city"]["type"],
+        "string"
+    );
+    assert_eq!(
+        request.tool_choice,
+        Some(ToolChoice::Tool("get_weather".to_string()))
+    );
+    let history = request.chat_history.unwrap();
+    assert_eq!(history.len(), 4);
+    assert_eq!(history[1].tool_calls.len(), 2);
+    assert_eq!(
+        history[1].tool_calls[0].arguments,
+        json!({ "city": "Paris" })
+    );
+    assert_eq!(history[1].tool_calls[1].arguments, json!({}));
+    assert_eq!(history[3].role, "tool");
+    assert_eq!(history[3].tool_call_id.as_deref(), Some("call_2"));
+    assert_eq!(history[3].content.text(), "Rainy");
+}
+
+#[test]
+fn test_chat_request_from_openai_rejects_unsupported_tools() {
+    let user = json!([{ "role": "user", "content": "Hi" }]);
+    let error =
+        |body: serde_json::Value| chat_request_from_openai(&body, "openai", "gpt-4o").unwrap_err();
+
+    assert!(error(json!({
+        "messages": user.clone(),
+        "tools": [{ "type": "code_interpreter" }]
+    }))
+    .contains("'tools'"));
+    assert!(
+        error(json!({ "messages": user.clone(), "tool_choice": "sometimes" }))
+            .contains("'tool_choice'")
+    );
+    assert!(error(json!({ "messages": user.clone(), "functions": [] })).contains("'functions'"));
+    assert!(error(json!({
+        "messages": [{ "role": "user", "content": "Hi" }, { "role": "tool", "content": "Sunny" }]
+    }))
+    .contains("'tool_call_id'"));
+}
+
+#[test]
+fn test_chat_request_from_openai_rejects_invalid_response_format() {
+    let user = json!([{ "role": "user", "content": "Hi" }]);
+    let request = |response_format: serde_json::Value| {
+        chat_request_from_openai(
+            &json!({ "messages": user.clone(), "response_format": response_format }),
+            "openai",
+            "gpt-4o",
+        )
+    };
+
+    // A schema format without its schema is refused, not ignored
+    let error =
+        request(json!({ "type": "json_schema", "json_schema": { "name": "reply" } })).unwrap_err();
+    assert!(error.starts_with("Invalid 'response_format'"));
+    assert!(request(json!({ "type": "yaml" })).is_err());
+    assert!(request(json!(null)).unwrap().response_format.is_none());
+    assert!(request(json!({ "type": "json_object" }))
+        .unwrap()
+        .response_format
+        .is_some());
+}
+
+#[test]
+fn test_completion_response_with_tool_calls() {
+    let calls = vec![ToolCallInfo {
+        id: "call_1".to_string(),
+        call_id: None,
+        name: "get_weather".to_string(),
+        arguments: json!({ "city": "Paris" }),
+    }];
+    let metadata = CompletionMetadata {
+        finish_reason: "tool_calls".to_string(),
+        ..Default::default()
+    };
+
+    let body = completion_response("chatcmpl-1", "openai/gpt-4o", "", &calls, Some(&metadata));
+    let message = &body["choices"][0]["message"];
+    assert!(message["content"].is_null());
+    assert_eq!(message["tool_calls"][0]["id"], "call_1");
+    assert_eq!(message["tool_calls"][0]["function"]["name"], "get_weather");
+    assert_eq!(
+        message["tool_calls"][0]["function"]["arguments"],
+        "{\"city\":\"Paris\"}"
+    );
+    assert_eq!(body["choices"][0]["finish_reason"], "tool_calls");
+
+    let body = completion_response("chatcmpl-1", "openai/gpt-4o", "Hi", &[], None);
+    assert_eq!(body["choices"][0]["message"]["content"], "Hi");
+    assert!(body["choices"][0]["message"].get("tool_calls").is_none());
+
+    let chunk = tool_call_chunk("chatcmpl-1", "openai/gpt-4o", 1, &calls[0]);
+    let tool_call = &chunk["choices"][0]["delta"]["tool_calls"][0];
+    assert_eq!(tool_call["index"], 1);
+    assert_eq!(tool_call["function"]["arguments"], "{\"city\":\"Paris\"}");
+}
+
+/// Writes a mock provider fixture that calls `tool` first and answers once it sees
+/// the tool result
+fn weather_fixture_calling(tool: &str) -> String {
+    let path = std::env::temp_dir().join(format!("jan-mock-{}.yaml", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        format!(
+            r#"
+responses:
+  - prompt_contains: "Sunny, 22"
+    chunks:
+      - {{ type: text, text: "It is sunny in Paris." }}
+  - chunks:
+      - {{ type: tool_call, id: call_1, name: {}, arguments: {{ city: Paris }} }}
+"#,
+            tool
+        ),
+    )
+    .unwrap();
+    path.to_string_lossy().to_string()
+}
+
+fn weather_fixture() -> String {
+    weather_fixture_calling("get_weather")
+}
+
+#[tokio::test]
+async fn test_chat_completion_tool_call_round_trip() {
+    let fixture = weather_fixture();
+    let complete = |body: serde_json::Value| {
+        let fixture = fixture.clone();
+        async move {
+            let response = handle_chat_completion(
+                ChatService::new(),
+                hyper::Response::builder(),
+                body,
+                format!("mock/{}", fixture),
+                "mock".to_string(),
+                fixture,
+                None,
+            )
+            .await;
+            assert_eq!(response.status(), hyper::StatusCode::OK);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+    let mut messages = vec![json!({ "role": "user", "content": "Weather in Paris?" })];
+
+    let first = complete(json!({ "messages": messages, "tools": [weather_tool()] })).await;
+    assert_eq!(first["choices"][0]["finish_reason"], "tool_calls");
+    let assistant = first["choices"][0]["message"].clone();
+    assert_eq!(
+        assistant["tool_calls"][0]["function"]["name"],
+        "get_weather"
+    );
+    assert_eq!(
+        assistant["tool_calls"][0]["function"]["arguments"],
+        "{\"city\":\"Paris\"}"
+    );
+
+    messages.push(assistant);
+    messages.push(json!({ "role": "tool", "tool_call_id": "call_1", "content": "Sunny, 22°C" }));
+    let second = complete(json!({ "messages": messages, "tools": [weather_tool()] })).await;
+    assert_eq!(second["choices"][0]["finish_reason"], "stop");
+    assert_eq!(
+        second["choices"][0]["message"]["content"],
+        "It is sunny in Paris."
+    );
+}
+
+#[tokio::test]
+async fn test_anthropic_tool_use_round_trip_on_remote_model() {
+    let fixture = weather_fixture();
+    let model_id = format!("mock/{}", fixture);
+    let messages_turn = |body: serde_json::Value| {
+        let fixture = fixture.clone();
+        let model_id = model_id.clone();
+        async move {
+            let openai_body = openai_request_from_anthropic(&body).unwrap();
+            let response = handle_chat_completion(
+                ChatService::new(),
+                hyper::Response::builder(),
+                openai_body,
+                model_id.clone(),
+                "mock".to_string(),
+                fixture,
+                None,
+            )
+            .await;
+            let status = response.status();
+            let response = translate_response(
+                hyper::Response::builder(),
+                status,
+                response.into_body(),
+                false,
+                model_id,
+            )
+            .await;
+            assert_eq!(response.status(), hyper::StatusCode::OK);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+    let tools = json!([{
+        "name": "get_weather",
+        "description": "Current weather in a city",
+        "input_schema": { "type": "object", "properties": { "city": { "type": "string" } } }
+    }]);
+    let mut messages = vec![json!({ "role": "user", "content": "Weather in Paris?" })];
+
+    let first = messages_turn(json!({
+        "model": model_id,
+        "max_tokens": 256,
+        "tools": tools,
+        "messages": messages,
+    }))
+    .await;
+    assert_eq!(first["stop_reason"], "tool_use");
+    assert_eq!(first["content"][0]["type"], "tool_use");
+    assert_eq!(first["content"][0]["id"], "call_1");
+    assert_eq!(first["content"][0]["name"], "get_weather");
+    assert_eq!(first["content"][0]["input"], json!({ "city": "Paris" }));
+
+    messages.push(json!({ "role": "assistant", "content": first["content"] }));
+    messages.push(json!({
+        "role": "user",
+        "content": [{ "type": "tool_result", "tool_use_id": "call_1", "content": "Sunny, 22°C" }]
+    }));
+    let second = messages_turn(json!({
+        "model": model_id,
+        "max_tokens": 256,
+        "tools": tools,
+        "messages": messages,
+    }))
+    .await;
+    assert_eq!(second["stop_reason"], "end_turn");
+    assert_eq!(second["content"][0]["type"], "text");
+    assert_eq!(second["content"][0]["text"], "It is sunny in Paris.");
+}
+
+#[test]
+fn test_embedding_inputs() {
+    assert_eq!(
+        embedding_inputs(&json!({ "input": "hello" })).unwrap(),
+        vec!["hello"]
+    );
+    assert_eq!(
+        embedding_inputs(&json!({ "input": ["a", "b"] })).unwrap(),
+        vec!["a", "b"]
+    );
+    assert!(embedding_inputs(&json!({ "input": [[1, 2, 3]] })).is_err());
+    assert!(embedding_inputs(&json!({ "model": "openai/text-embedding-3-small" })).is_err());
+}
+
+#[test]
+fn test_embeddings_response() {
+    let response = EmbeddingResponse {
+        provider: "openai".to_string(),
+        model: "text-embedding-3-small".to_string(),
+        embeddings: vec![vec![0.5, -1.0], vec![0.25, 2.0]],
+        dimensions: 2,
+        usage: EmbeddingUsage {
+            prompt_tokens: 4,
+            total_tokens: 4,
+        },
+    };
+    let body = embeddings_response("openai/text-embedding-3-small", &response, false);
+    assert_eq!(body["object"], "list");
+    assert_eq!(body["data"][1]["index"], 1);
+    assert_eq!(body["data"][1]["embedding"], json!([0.25, 2.0]));
+    assert_eq!(body["usage"]["prompt_tokens"], 4);
+
+    let body = embeddings_response("openai/text-embedding-3-small", &response, true);
+    let encoded = body["data"][0]["embedding"].as_str().unwrap();
+    let bytes =
+        base64::Engine::decode(&base64::engine::general_purpose::STANDARD, encoded).unwrap();
+    let decoded: Vec<f32> = bytes
+        .chunks(4)
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    assert_eq!(decoded, vec![0.5, -1.0]);
+}
+
+#[tokio::test]
+async fn test_handle_embeddings_with_mock_provider() {
+    let response = handle_embeddings(
+        ChatService::new(),
+        hyper::Response::builder(),
+        json!({ "model": "mock/embed", "input": ["first", "second"] }),
+        "mock/embed".to_string(),
+        "mock".to_string(),
+        "embed".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["model"], "mock/embed");
+    assert_eq!(body["data"].as_array().unwrap().len(), 2);
+
+    let response = handle_embeddings(
+        ChatService::new(),
+        hyper::Response::builder(),
+        json!({ "model": "mock/embed", "input": [] }),
+        "mock/embed".to_string(),
+        "mock".to_string(),
+        "embed".to_string(),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), hyper::StatusCode::BAD_GATEWAY);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"]["type"], "api_error");
+    assert!(body["error"]["message"].is_string());
+}
+
+#[test]
+fn test_openai_errors() {
+    assert_eq!(
+        provider_error_status("CompletionError: ProviderError: status 401, invalid x-api-key"),
+        hyper::StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        provider_error_status("HttpError: 404 Not Found: model gpt-9 does not exist"),
+        hyper::StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        provider_error_status("status: 400, max_tokens is too large"),
+        hyper::StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        provider_error_status("status 503, upstream unavailable"),
+        hyper::StatusCode::BAD_GATEWAY
+    );
+    assert_eq!(
+        provider_error_status("connection reset by peer"),
+        hyper::StatusCode::BAD_GATEWAY
+    );
+
+    let error = openai_error(hyper::StatusCode::UNAUTHORIZED, "Invalid API key");
+    assert_eq!(
+        error,
+        json!({ "error": { "message": "Invalid API key", "type": "authentication_error", "code": "invalid_api_key" } })
+    );
+    let error = openai_error(
+        hyper::StatusCode::BAD_REQUEST,
+        "'messages' must not be empty",
+    );
+    assert_eq!(error["error"]["type"], "invalid_request_error");
+    assert!(error["error"]["code"].is_null());
+}
+
+#[test]
+fn test_openai_request_from_anthropic() {
+    let body = json!({
+        "model": "llama3.2",
+        "max_tokens": 256,
+        "system": [{ "type": "text", "text": "Be brief." }],
+        "stop_sequences": ["END"],
+        "stream": true,
+        "tools": [{
+            "name": "get_weather",
+            "description": "Weather for a city",
+            "input_schema": { "type": "object", "properties": { "city": { "type": "string" } } }
+        }],
+        "tool_choice": { "type": "tool", "name": "get_weather" },
+        "messages": [
+            { "role": "user", "content": [
+                { "type": "text", "text": "Weather in Paris?" },
+                { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo=" } }
+            ]},
+            { "role": "assistant", "content": [
+                { "type": "text", "text": "Checking." },
+                { "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": { "city": "Paris" } }
+            ]},
+            { "role": "user", "content": [
+                { "type": "tool_result", "tool_use_id": "toolu_1", "content": [{ "type": "text", "text": "18C" }] },
+                { "type": "text", "text": "Thanks" }
+            ]}
+        ]
+    });
+    let request = openai_request_from_anthropic(&body).unwrap();
+    assert_eq!(request["model"], "llama3.2");
+    assert_eq!(request["max_tokens"], 256);
+    assert_eq!(request["stop"], json!(["END"]));
+    assert_eq!(request["stream_options"]["include_usage"], true);
+    assert_eq!(request["tools"][0]["function"]["name"], "get_weather");
+    assert_eq!(request["tool_choice"]["function"]["name"], "get_weather");
+
+    let messages = request["messages"].as_array().unwrap();
+    assert_eq!(
+        messages[0],
+        json!({ "role": "system", "content": "Be brief." })
+    );
+    assert_eq!(messages[1]["content"][0]["text"], "Weather in Paris?");
+    assert_eq!(
+        messages[1]["content"][1]["image_url"]["url"],
+        "data:image/png;base64,iVBORw0KGgo="
+    );
+    assert_eq!(messages[2]["content"], "Checking.");
+    assert_eq!(messages[2]["tool_calls"][0]["id"], "toolu_1");
+    assert_eq!(
+        messages[2]["tool_calls"][0]["function"]["arguments"],
+        "{\"city\":\"Paris\"}"
+    );
+    assert_eq!(
+        messages[3],
+        json!({ "role": "tool", "tool_call_id": "toolu_1", "content": "18C" })
+    );
+    assert_eq!(messages[4], json!({ "role": "user", "content": "Thanks" }));
+
+    assert!(openai_request_from_anthropic(&json!({ "model": "x" })).is_err());
+}
+
+#[test]
+fn test_anthropic_response_from_openai() {
+    let body = json!({
+        "id": "chatcmpl-abc",
+        "choices": [{
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": "Let me check.",
+                "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+                }]
+            },
+            "finish_reason": "tool_calls"
+        }],
+        "usage": { "prompt_tokens": 12, "completion_tokens": 7, "total_tokens": 19 }
+    });
+    let response = anthropic_response_from_openai(&body, "llama3.2");
+    assert_eq!(response["id"], "msg_abc");
+    assert_eq!(response["type"], "message");
+    assert_eq!(response["model"], "llama3.2");
+    assert_eq!(response["stop_reason"], "tool_use");
+    assert_eq!(
+        response["content"][0],
+        json!({ "type": "text", "text": "Let me check." })
+    );
+    assert_eq!(response["content"][1]["type"], "tool_use");
+    assert_eq!(response["content"][1]["input"]["city"], "Paris");
+    assert_eq!(response["usage"]["input_tokens"], 12);
+    assert_eq!(response["usage"]["output_tokens"], 7);
+}
+
+fn event_names(events: &[serde_json::Value]) -> Vec<&str> {
+    events
+        .iter()
+        .map(|event| event["type"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_message_stream_events() {
+    let mut stream = MessageStream::new("llama3.2");
+    let mut events = Vec::new();
+    for chunk in [
+        json!({ "id": "chatcmpl-1", "choices": [{ "index": 0, "delta": { "role": "assistant", "content": "" } }] }),
+        json!({ "choices": [{ "index": 0, "delta": { "reasoning_content": "Hmm" } }] }),
+        json!({ "choices": [{ "index": 0, "delta": { "content": "Hel" } }] }),
+        json!({ "choices": [{ "index": 0, "delta": { "content": "lo" } }] }),
+        json!({ "choices": [{ "index": 0, "delta": { "tool_calls": [
+            { "index": 0, "id": "call_1", "function": { "name": "lookup", "arguments": "" } }
+        ] } }] }),
+        json!({ "choices": [{ "index": 0, "delta": { "tool_calls": [
+            { "index": 0, "function": { "arguments": "{\"q\":1}" } }
+        ] } }] }),
+        json!({ "choices": [{ "index": 0, "delta": {}, "finish_reason": "tool_calls" }] }),
+        json!({ "choices": [], "usage": { "prompt_tokens": 5, "completion_tokens": 9 } }),
+    ] {
+        events.extend(stream.push_chunk(&chunk));
+    }
+    events.extend(stream.finish());
+
+    assert_eq!(
+        event_names(&events),
+        vec![
+            "message_start",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_stop",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]
+    );
+    assert_eq!(events[0]["message"]["id"], "msg_1");
+    assert_eq!(events[1]["content_block"]["type"], "thinking");
+    assert_eq!(events[5]["delta"]["text"], "Hel");
+    assert_eq!(events[8]["index"], 2);
+    assert_eq!(events[8]["content_block"]["name"], "lookup");
+    assert_eq!(events[9]["delta"]["partial_json"], "{\"q\":1}");
+    assert_eq!(events[11]["delta"]["stop_reason"], "tool_use");
+    assert_eq!(events[11]["usage"]["output_tokens"], 9);
+
+    let mut failed = MessageStream::new("llama3.2");
+    let events = failed.push_chunk(&json!({ "error": { "message": "boom" } }));
+    assert_eq!(events[0]["error"]["message"], "boom");
+    assert!(failed.finish().is_empty());
+}
+
+#[tokio::test]
+async fn test_anthropic_stream_from_mock_provider() {
+    let openai_body = openai_request_from_anthropic(&json!({
+        "model": "mock/echo",
+        "max_tokens": 64,
+        "stream": true,
+        "messages": [{ "role": "user", "content": "hello there" }]
+    }))
+    .unwrap();
+    let response = handle_chat_completion(
+        ChatService::new(),
+        hyper::Response::builder(),
+        openai_body,
+        "mock/echo".to_string(),
+        "mock".to_string(),
+        "echo".to_string(),
+        None,
+    )
+    .await;
+    let status = response.status();
+    let response = translate_response(
+        hyper::Response::builder(),
+        status,
+        response.into_body(),
+        true,
+        "mock/echo".to_string(),
+    )
+    .await;
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+
+    let events: Vec<serde_json::Value> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect();
+    let names = event_names(&events);
+    assert_eq!(names.first(), Some(&"message_start"));
+    assert_eq!(names.last(), Some(&"message_stop"));
+    assert!(body.contains("event: content_block_delta"));
+    let text: String = events
+        .iter()
+        .filter_map(|event| event.pointer("/delta/text").and_then(|t| t.as_str()))
+        .collect();
+    assert_eq!(text, "hello there");
+}
+
+#[tokio::test]
+async fn test_anthropic_error_response() {
+    let response = translate_response(
+        hyper::Response::builder(),
+        hyper::StatusCode::BAD_REQUEST,
+        hyper::Body::from("The last message must have role 'user'"),
+        false,
+        "mock/echo".to_string(),
+    )
+    .await;
+    assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["type"], "error");
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+}
+
+#[test]
+fn test_ollama_rfc3339() {
+    assert_eq!(ollama::rfc3339(0), "1970-01-01T00:00:00Z");
+    assert_eq!(ollama::rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+    assert_eq!(ollama::rfc3339(1_735_689_599), "2024-12-31T23:59:59Z");
+}
+
+#[test]
+fn test_openai_request_from_ollama_chat() {
+    let (request, stream) = ollama::openai_request_from_chat(&json!({
+        "model": "llama3.2",
+        "messages": [
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "What is this?", "images": ["iVBORw0KGgo="] },
+            {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{ "function": { "name": "weather", "arguments": { "city": "Paris" } } }]
+            },
+            { "role": "tool", "content": "18C" }
+        ],
+        "format": "json",
+        "options": { "temperature": 0.2, "num_predict": 64 }
+    }))
+    .unwrap();
+
+    assert!(stream);
+    assert_eq!(request["stream_options"]["include_usage"], true);
+    assert_eq!(request["max_tokens"], 64);
+    assert_eq!(request["temperature"], 0.2);
+    assert_eq!(request["response_format"]["type"], "json_object");
+    assert_eq!(
+        request["messages"][1]["content"][1]["image_url"]["url"],
+        "data:image/png;base64,iVBORw0KGgo="
+    );
+    let call = &request["messages"][2]["tool_calls"][0];
+    assert_eq!(call["function"]["arguments"], "{\"city\":\"Paris\"}");
+    assert_eq!(request["messages"][3]["tool_call_id"], call["id"]);
+
+    assert!(ollama::openai_request_from_chat(&json!({ "messages": [] })).is_err());
+}
+
+#[test]
+fn test_openai_request_from_ollama_generate() {
+    let (request, stream) = ollama::openai_request_from_generate(&json!({
+        "model": "llama3.2",
+        "system": "Answer in French.",
+        "prompt": "Hello",
+        "stream": false
+    }))
+    .unwrap()
+    .unwrap();
+    assert!(!stream);
+    assert!(request.get("stream").is_none());
+    assert_eq!(request["messages"][0]["role"], "system");
+    assert_eq!(request["messages"][1]["content"], "Hello");
+
+    // An empty prompt only loads the model
+    assert!(
+        ollama::openai_request_from_generate(&json!({ "model": "llama3.2" }))
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(ollama::load_response("llama3.2")["done_reason"], "load");
+}
+
+#[test]
+fn test_ollama_response_from_openai() {
+    let body = json!({
+        "choices": [{
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": { "name": "weather", "arguments": "{\"city\":\"Paris\"}" }
+                }]
+            },
+            "finish_reason": "tool_calls"
+        }],
+        "usage": { "prompt_tokens": 12, "completion_tokens": 5 }
+    });
+    let chat = ollama::ollama_response_from_openai(OllamaEndpoint::Chat, &body, "llama3.2");
+    assert_eq!(chat["done"], true);
+    assert_eq!(chat["done_reason"], "stop");
+    assert_eq!(chat["prompt_eval_count"], 12);
+    assert_eq!(chat["eval_count"], 5);
+    assert_eq!(
+        chat["message"]["tool_calls"][0]["function"]["arguments"]["city"],
+        "Paris"
+    );
+
+    let generate = ollama::ollama_response_from_openai(
+        OllamaEndpoint::Generate,
+        &json!({ "choices": [{ "message": { "content": "Hi" }, "finish_reason": "length" }] }),
+        "llama3.2",
+    );
+    assert_eq!(generate["response"], "Hi");
+    assert_eq!(generate["done_reason"], "length");
+    assert_eq!(generate["context"], json!([]));
+}
+
+#[test]
+fn test_ollama_stream_objects() {
+    let mut stream = OllamaStream::new(OllamaEndpoint::Chat, "llama3.2");
+    let first = stream.push_chunk(&json!({ "choices": [{ "delta": { "content": "Hel" } }] }));
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0]["done"], false);
+    assert_eq!(first[0]["message"]["content"], "Hel");
+
+    for fragment in ["{\"city\":", "\"Paris\"}"] {
+        let objects = stream.push_chunk(&json!({
+            "choices": [{ "delta": { "tool_calls": [{
+                "index": 0,
+                "function": { "name": if fragment.starts_with('{') { "weather" } else { "" }, "arguments": fragment }
+            }] } }]
+        }));
+        assert!(objects.is_empty());
+    }
+    stream.push_chunk(&json!({ "choices": [{ "delta": {}, "finish_reason": "tool_calls" }] }));
+    stream.push_chunk(
+        &json!({ "choices": [], "usage": { "prompt_tokens": 3, "completion_tokens": 7 } }),
+    );
+
+    let last = stream.finish();
+    assert_eq!(last.len(), 2);
+    let call = &last[0]["message"]["tool_calls"][0]["function"];
+    assert_eq!(call["name"], "weather");
+    assert_eq!(call["arguments"]["city"], "Paris");
+    assert_eq!(last[1]["done"], true);
+    assert_eq!(last[1]["eval_count"], 7);
+    assert!(stream.finish().is_empty());
+
+    let line = ollama::ndjson_line(&last[1]);
+    assert!(line.ends_with(b"\n"));
+    assert_eq!(line.iter().filter(|&&b| b == b'\n').count(), 1);
+}
+
+#[test]
+fn test_ollama_stream_error() {
+    let mut stream = OllamaStream::new(OllamaEndpoint::Generate, "llama3.2");
+    let objects = stream.push_chunk(&json!({ "error": { "message": "model crashed" } }));
+    assert_eq!(objects, vec![json!({ "error": "model crashed" })]);
+    assert!(stream.finish().is_empty());
+}
+
+#[tokio::test]
+async fn test_ollama_embeddings_response() {
+    let upstream = hyper::Response::new(hyper::Body::from(
+        json!({
+            "data": [{ "embedding": [0.1, 0.2] }, { "embedding": [0.3, 0.4] }],
+            "usage": { "prompt_tokens": 4 }
+        })
+        .to_string(),
+    ));
+    let response = ollama::translate_embeddings_response(
+        hyper::Response::builder(),
+        upstream,
+        true,
+        "nomic".to_string(),
+    )
+    .await;
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body, json!({ "embedding": [0.1, 0.2] }));
+
+    assert_eq!(
+        ollama::embedding_input(&json!({ "input": ["a", "b"] })).unwrap(),
+        json!(["a", "b"])
+    );
+    assert!(ollama::embedding_input(&json!({ "model": "nomic" })).is_err());
+}
+
+#[test]
+fn test_threads_parse_route() {
+    assert_eq!(
+        threads_api::parse_route("/threads"),
+        Some(ThreadRoute::Threads)
+    );
+    assert_eq!(
+        threads_api::parse_route("/threads/abc/"),
+        Some(ThreadRoute::Thread("abc"))
+    );
+    assert_eq!(
+        threads_api::parse_route("/threads/abc/messages/m1"),
+        Some(ThreadRoute::Message("abc", "m1"))
+    );
+    assert_eq!(threads_api::parse_route("/threads/../messages"), None);
+    assert_eq!(threads_api::parse_route("/threads/a/runs"), None);
+}
+
+#[test]
+fn test_threads_pagination() {
+    let items: Vec<_> = (1..=5)
+        .map(|i| json!({ "id": format!("m{}", i) }))
+        .collect();
+    let ids = |page: &serde_json::Value| -> Vec<String> {
+        page["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["id"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let newest =
+        threads_api::paginate(items.clone(), &PageQuery::parse(Some("limit=2")).unwrap()).unwrap();
+    assert_eq!(ids(&newest), vec!["m5", "m4"]);
+    assert_eq!(newest["has_more"], true);
+    assert_eq!(newest["last_id"], "m4");
+
+    let next = threads_api::paginate(
+        items.clone(),
+        &PageQuery::parse(Some("limit=2&after=m4")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(ids(&next), vec!["m3", "m2"]);
+
+    let ascending = threads_api::paginate(
+        items.clone(),
+        &PageQuery::parse(Some("order=asc&after=m1&before=m4")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(ids(&ascending), vec!["m2", "m3"]);
+    assert_eq!(ascending["has_more"], false);
+
+    // A cursor that matches nothing must not restart from the first page
+    for query in ["after=gone", "before=gone"] {
+        assert!(
+            threads_api::paginate(items.clone(), &PageQuery::parse(Some(query)).unwrap()).is_err()
+        );
+    }
+
+    assert!(PageQuery::parse(Some("limit=0")).is_err());
+    assert!(PageQuery::parse(Some("order=random")).is_err());
+}
+
+#[test]
+fn test_threads_new_message() {
+    let message = threads_api::new_message(
+        "t1",
+        &json!({
+            "role": "user",
+            "content": [
+                { "type": "text", "text": "Hi" },
+                { "type": "image_url", "image_url": { "url": "https://example.com/a.png" } }
+            ],
+            "metadata": { "source": "script" }
+        }),
+    )
+    .unwrap();
+    assert_eq!(message["object"], "thread.message");
+    assert_eq!(message["thread_id"], "t1");
+    assert_eq!(message["content"][0]["text"]["value"], "Hi");
+    assert_eq!(message["content"][1]["type"], "image_url");
+    assert_eq!(message["metadata"]["source"], "script");
+
+    assert!(threads_api::new_message("t1", &json!({ "role": "tool", "content": "x" })).is_err());
+    assert!(threads_api::new_message("t1", &json!({ "role": "user" })).is_err());
+}
+
+#[test]
+fn test_threads_apply_changes() {
+    let mut thread = json!({ "id": "t1", "title": "Old", "created": 1, "metadata": { "a": 1 } });
+    threads_api::apply_thread_changes(
+        &mut thread,
+        &json!({ "id": "other", "title": "New", "metadata": { "b": 2 } }),
+    )
+    .unwrap();
+    assert_eq!(thread["id"], "t1");
+    assert_eq!(thread["title"], "New");
+    assert_eq!(thread["created"], 1);
+    assert_eq!(thread["metadata"], json!({ "a": 1, "b": 2 }));
+    assert!(thread["updated"].as_i64().unwrap() > 1);
+}
+
+async fn threads_call(
+    app: &tauri::App<tauri::test::MockRuntime>,
+    method: hyper::Method,
+    path: &str,
+    query: Option<&str>,
+    body: serde_json::Value,
+) -> (hyper::StatusCode, serde_json::Value) {
+    let body = if body.is_null() {
+        hyper::body::Bytes::new()
+    } else {
+        hyper::body::Bytes::from(body.to_string())
+    };
+    let response = threads_api::handle_threads_request(
+        app.handle(),
+        hyper::Response::builder(),
+        &method,
+        path,
+        query,
+        body,
+    )
+    .await;
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn test_threads_api_round_trip() {
+    use hyper::{Method, StatusCode};
+    let app = tauri::test::mock_app();
+
+    let (status, thread) = threads_call(
+        &app,
+        Method::POST,
+        "/threads",
+        None,
+        json!({ "title": "From a script", "messages": [{ "role": "user", "content": "Hello" }] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let thread_id = thread["id"].as_str().unwrap().to_string();
+    let messages_path = format!("/threads/{}/messages", thread_id);
+
+    let (_, fetched) = threads_call(
+        &app,
+        Method::GET,
+        &format!("/threads/{}", thread_id),
+        None,
+        json!(null),
+    )
+    .await;
+    assert_eq!(fetched["title"], "From a script");
+
+    let (status, created) = threads_call(
+        &app,
+        Method::POST,
+        &messages_path,
+        None,
+        json!({ "role": "assistant", "content": "Hi there" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let message_id = created["id"].as_str().unwrap().to_string();
+
+    let (_, list) = threads_call(
+        &app,
+        Method::GET,
+        &messages_path,
+        Some("order=asc"),
+        json!(null),
+    )
+    .await;
+    assert_eq!(list["data"].as_array().unwrap().len(), 2);
+    assert_eq!(list["data"][0]["content"][0]["text"]["value"], "Hello");
+    assert_eq!(list["last_id"], message_id.as_str());
+
+    let message_path = format!("{}/{}", messages_path, message_id);
+    let (_, modified) = threads_call(
+        &app,
+        Method::POST,
+        &message_path,
+        None,
+        json!({ "metadata": { "reviewed": true } }),
+    )
+    .await;
+    assert_eq!(modified["metadata"]["reviewed"], true);
+
+    let (_, deleted) = threads_call(&app, Method::DELETE, &message_path, None, json!(null)).await;
+    assert_eq!(deleted["deleted"], true);
+    let (status, _) = threads_call(&app, Method::GET, &message_path, None, json!(null)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, error) = threads_call(
+        &app,
+        Method::POST,
+        &messages_path,
+        None,
+        json!({ "role": "user" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"]["type"], "invalid_request_error");
+
+    let thread_path = format!("/threads/{}", thread_id);
+    let (_, deleted) = threads_call(&app, Method::DELETE, &thread_path, None, json!(null)).await;
+    assert_eq!(deleted["object"], "thread.deleted");
+    let (status, _) = threads_call(&app, Method::GET, &thread_path, None, json!(null)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_mcp_tool_names() {
+    let name = mcp::namespaced_tool_name("sequential-thinking", "think");
+    assert_eq!(name, "sequential-thinking__think");
+    assert_eq!(
+        mcp::split_tool_name(&name, ["fetch", "sequential-thinking"]),
+        Some(("sequential-thinking", "think"))
+    );
+    assert_eq!(
+        mcp::split_tool_name("fetch__get__page", ["fetch"]),
+        Some(("fetch", "get__page"))
+    );
+    assert_eq!(
+        mcp::split_tool_name("my__server__search", ["my", "my__server"]),
+        Some(("my__server", "search"))
+    );
+    assert_eq!(
+        mcp::split_tool_name("my__search", ["my", "my__server"]),
+        Some(("my", "search"))
+    );
+    assert_eq!(mcp::split_tool_name("other__think", ["fetch"]), None);
+    assert_eq!(mcp::split_tool_name("think", ["think"]), None);
+    assert_eq!(mcp::split_tool_name("fetch__", ["fetch"]), None);
+    assert_eq!(mcp::split_tool_name("__think", [""]), None);
+}
+
+fn empty_mcp_state() -> (
+    crate::core::state::SharedMcpServers,
+    crate::core::state::SharedToolCallCancellations,
+) {
+    (Default::default(), Default::default())
+}
+
+#[tokio::test]
+async fn test_mcp_handle_message() {
+    let (servers, cancellations) = empty_mcp_state();
+
+    let initialized = mcp::handle_message(
+        &servers,
+        &cancellations,
+        None,
+        &json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "protocolVersion": "2025-03-26" } }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(initialized["id"], 1);
+    assert_eq!(initialized["result"]["protocolVersion"], "2025-03-26");
+    assert!(initialized["result"]["capabilities"]["tools"].is_object());
+
+    let tools = mcp::handle_message(
+        &servers,
+        &cancellations,
+        None,
+        &json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(tools["result"]["tools"], json!([]));
+
+    let unknown_tool = mcp::handle_message(
+        &servers,
+        &cancellations,
+        None,
+        &json!({ "jsonrpc": "2.0", "id": "a", "method": "tools/call", "params": { "name": "fetch__get" } }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(unknown_tool["error"]["code"], -32602);
+
+    let unknown_method = mcp::handle_message(
+        &servers,
+        &cancellations,
+        None,
+        &json!({ "jsonrpc": "2.0", "id": 3, "method": "resources/list" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(unknown_method["error"]["code"], -32601);
+
+    assert!(mcp::handle_message(
+        &servers,
+        &cancellations,
+        None,
+        &json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+    )
+    .await
+    .is_none());
+}
+
+#[tokio::test]
+async fn test_mcp_cancellation_notification() {
+    let (servers, cancellations) = empty_mcp_state();
+    let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+    cancellations
+        .lock()
+        .await
+        .insert("mcp:s1:7".to_string(), cancel_tx);
+
+    mcp::handle_message(
+        &servers,
+        &cancellations,
+        Some("s1"),
+        &json!({ "jsonrpc": "2.0", "method": "notifications/cancelled", "params": { "requestId": 7 } }),
+    )
+    .await;
+    assert!(cancel_rx.await.is_ok());
+    assert!(cancellations.lock().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_mcp_http_request() {
+    let (servers, cancellations) = empty_mcp_state();
+    let call = |method: hyper::Method, headers: hyper::HeaderMap, body: serde_json::Value| {
+        let servers = servers.clone();
+        let cancellations = cancellations.clone();
+        async move {
+            mcp::handle_mcp_request(
+                &servers,
+                &cancellations,
+                hyper::Response::builder(),
+                &method,
+                &headers,
+                hyper::body::Bytes::from(body.to_string()),
+            )
+            .await
+        }
+    };
+
+    let response = call(
+        hyper::Method::POST,
+        hyper::HeaderMap::new(),
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+    )
+    .await;
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    assert!(response.headers().contains_key(mcp::SESSION_HEADER));
+
+    let response = call(
+        hyper::Method::POST,
+        hyper::HeaderMap::new(),
+        json!([
+            { "jsonrpc": "2.0", "method": "notifications/initialized" },
+            { "jsonrpc": "2.0", "id": 2, "method": "ping" }
+        ]),
+    )
+    .await;
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let replies: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(
+        replies,
+        json!([{ "jsonrpc": "2.0", "id": 2, "result": {} }])
+    );
+
+    let response = call(
+        hyper::Method::POST,
+        hyper::HeaderMap::new(),
+        json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+    )
+    .await;
+    assert_eq!(response.status(), hyper::StatusCode::ACCEPTED);
+
+    let response = call(hyper::Method::GET, hyper::HeaderMap::new(), json!(null)).await;
+    assert_eq!(response.status(), hyper::StatusCode::METHOD_NOT_ALLOWED);
+
+    let ping = json!({ "jsonrpc": "2.0", "id": 3, "method": "ping" });
+    let with_header = |name: &'static str, value: &'static str| {
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert(name, hyper::header::HeaderValue::from_static(value));
+        headers
+    };
+    let response = call(
+        hyper::Method::POST,
+        with_header("accept", "application/json, text/event-stream"),
+        ping.clone(),
+    )
+    .await;
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let response = call(
+        hyper::Method::POST,
+        with_header("accept", "text/event-stream"),
+        ping.clone(),
+    )
+    .await;
+    assert_eq!(response.status(), hyper::StatusCode::NOT_ACCEPTABLE);
+
+    let response = call(
+        hyper::Method::POST,
+        with_header(mcp::PROTOCOL_VERSION_HEADER, "2025-06-18"),
+        ping.clone(),
+    )
+    .await;
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let response = call(
+        hyper::Method::POST,
+        with_header(mcp::PROTOCOL_VERSION_HEADER, "2023-01-01"),
+        ping,
+    )
+    .await;
+    assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn test_tool_selection() {
+    let headers = hyper::HeaderMap::new();
+    assert_eq!(
+        tools::tool_selection(&json!({ "jan_tools": "auto" }), &headers),
+        Some(ToolSelection::All)
+    );
+    assert_eq!(
+        tools::tool_selection(&json!({ "jan_tools": ["fetch", "exa__search"] }), &headers),
+        Some(ToolSelection::Only(vec![
+            "fetch".to_string(),
+            "exa__search".to_string()
+        ]))
+    );
+    assert_eq!(
+        tools::tool_selection(&json!({ "jan_tools": "none" }), &headers),
+        None
+    );
+    assert_eq!(tools::tool_selection(&json!({}), &headers), None);
+
+    let mut headers = hyper::HeaderMap::new();
+    headers.insert(tools::TOOLS_HEADER, "fetch, exa".parse().unwrap());
+    assert_eq!(
+        tools::tool_selection(&json!({}), &headers),
+        Some(ToolSelection::Only(vec![
+            "fetch".to_string(),
+            "exa".to_string()
+        ]))
+    );
+    // The request field wins over the header
+    assert_eq!(
+        tools::tool_selection(&json!({ "jan_tools": "none" }), &headers),
+        None
+    );
+}
+
+#[test]
+fn test_tool_closing_chunks() {
+    let completion = json!({
+        "id": "chatcmpl-upstream",
+        "object": "chat.completion",
+        "created": 5,
+        "model": "llama3.2",
+        "choices": [{ "index": 0, "message": { "role": "assistant", "content": "It is 18C." }, "finish_reason": "stop" }],
+        "usage": { "prompt_tokens": 30, "completion_tokens": 6, "total_tokens": 36 }
+    });
+    // The text was already streamed, so only the finish reason and usage are left
+    let chunks = tools::closing_chunks("chatcmpl-1", "llama3.2", 5, &completion, true);
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0]["id"], "chatcmpl-1");
+    assert_eq!(chunks[0]["choices"][0]["finish_reason"], "stop");
+    assert_eq!(chunks[1]["usage"]["total_tokens"], 36);
+    assert_eq!(
+        tools::closing_chunks("chatcmpl-1", "llama3.2", 5, &completion, false).len(),
+        1
+    );
+
+    let calls = json!({
+        "choices": [{
+            "index": 0,
+            "message": {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{ "id": "call_1", "type": "function", "function": { "name": "lookup", "arguments": "{}" } }]
+            },
+            "finish_reason": "tool_calls"
+        }]
+    });
+    let chunks = tools::closing_chunks("chatcmpl-1", "llama3.2", 5, &calls, false);
+    assert_eq!(chunks.len(), 2);
+    let call = &chunks[0]["choices"][0]["delta"]["tool_calls"][0];
+    assert_eq!(call["index"], 0);
+    assert_eq!(call["function"]["name"], "lookup");
+    assert_eq!(chunks[1]["choices"][0]["finish_reason"], "tool_calls");
+
+    let step = tools::step_chunk("chatcmpl-1", "llama3.2", 5, &json!({ "type": "tool_call" }));
+    assert_eq!(step["choices"], json!([]));
+    assert_eq!(step["jan_tool_step"]["type"], "tool_call");
+}
+
+fn json_response(body: serde_json::Value) -> hyper::Response<hyper::Body> {
+    hyper::Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(hyper::Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Connects an in-process MCP server named `weather` with a `get_weather` tool. The
+/// arguments of every call it receives are pushed to `calls`.
+async fn weather_mcp_servers(
+    calls: std::sync::Arc<std::sync::Mutex<Vec<serde_json::Value>>>,
+) -> crate::core::state::SharedMcpServers {
+    use rmcp::ServiceExt;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let (read, mut write) = tokio::io::split(server_end);
+        let mut lines = tokio::io::BufReader::new(read).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let message: serde_json::Value = serde_json::from_str(&line).unwrap();
+            let Some(id) = message.get("id") else {
+                continue;
+            };
+            let result = match message["method"].as_str().unwrap_or_default() {
+                "initialize" => json!({
+                    "protocolVersion": "2025-03-26",
+                    "capabilities": { "tools": {} },
+                    "serverInfo": { "name": "weather", "version": "1.0.0" }
+                }),
+                "tools/list" => json!({
+                    "tools": [{
+                        "name": "get_weather",
+                        "description": "Current weather in a city",
+                        "inputSchema": { "type": "object", "properties": { "city": { "type": "string" } } }
+                    }]
+                }),
+                "tools/call" => {
+                    let arguments = message["params"]["arguments"].clone();
+                    let city = arguments["city"].as_str().unwrap_or_default().to_string();
+                    calls.lock().unwrap().push(arguments);
+                    json!({
+                        "content": [{ "type": "text", "text": format!("Sunny, 22°C in {}", city) }],
+                        "isError": false
+                    })
+                }
+                _ => json!({}),
+            };
+            let reply = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+            write
+                .write_all(format!("{}\n", reply).as_bytes())
+                .await
+                .unwrap();
+        }
+    });
+
+    let client = ().serve(tokio::io::split(client_end)).await.unwrap();
+    let servers = crate::core::state::SharedMcpServers::default();
+    servers.lock().await.insert(
+        "weather".to_string(),
+        crate::core::state::RunningServiceEnum::NoInit(client),
+    );
+    servers
+}
+
+#[tokio::test]
+async fn test_tool_loop_runs_mcp_tool_for_remote_model() {
+    let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let servers = weather_mcp_servers(calls.clone()).await;
+    let fixture = weather_fixture_calling("weather__get_weather");
+    let model_id = format!("mock/{}", fixture);
+    let complete = |request: serde_json::Value| {
+        let (fixture, model_id) = (fixture.clone(), model_id.clone());
+        async move {
+            Ok(handle_chat_completion(
+                ChatService::new(),
+                hyper::Response::builder(),
+                request,
+                model_id,
+                "mock".to_string(),
+                fixture,
+                None,
+            )
+            .await)
+        }
+    };
+
+    let outcome = tools::run_tool_loop(
+        servers,
+        json!({
+            "model": model_id,
+            "jan_tools": "auto",
+            "messages": [{ "role": "user", "content": "Weather in Paris?" }]
+        }),
+        ToolSelection::All,
+        complete,
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(*calls.lock().unwrap(), vec![json!({ "city": "Paris" })]);
+    assert_eq!(outcome.steps.len(), 2);
+    assert_eq!(outcome.steps[0]["type"], "tool_call");
+    assert_eq!(outcome.steps[0]["name"], "weather__get_weather");
+    assert_eq!(outcome.steps[1]["type"], "tool_result");
+    assert_eq!(outcome.steps[1]["content"], "Sunny, 22°C in Paris");
+    assert_eq!(outcome.steps[1]["is_error"], false);
+    // The second model turn saw the tool result
+    assert_eq!(
+        outcome.completion["choices"][0]["message"]["content"],
+        "It is sunny in Paris."
+    );
+    assert_eq!(outcome.completion["choices"][0]["finish_reason"], "stop");
+}
+
+#[tokio::test]
+async fn test_tool_loop_returns_client_tool_calls() {
+    let (servers, _) = empty_mcp_state();
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    let complete = move |request: serde_json::Value| {
+        recorded.lock().unwrap().push(request);
+        async move {
+            Ok(json_response(json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{ "id": "call_1", "type": "function", "function": { "name": "lookup", "arguments": "{}" } }]
+                    },
+                    "finish_reason": "tool_calls"
+                }],
+                "usage": { "prompt_tokens": 10, "completion_tokens": 2, "total_tokens": 12 }
+            })))
+        }
+    };
+
+    let outcome = tools::run_tool_loop(
+        servers,
+        json!({
+            "model": "llama3.2",
+            "stream": true,
+            "stream_options": { "include_usage": true },
+            "jan_tools": "auto",
+            "messages": [{ "role": "user", "content": "Look it up" }],
+            "tools": [{ "type": "function", "function": { "name": "lookup", "parameters": {} } }]
+        }),
+        ToolSelection::All,
+        complete,
+        None,
+    )
+    .await
+    .unwrap();
+
+    // `lookup` is the client's own tool, so its call is returned instead of run
+    assert!(outcome.steps.is_empty());
+    assert_eq!(
+        outcome.completion["choices"][0]["finish_reason"],
+        "tool_calls"
+    );
+    assert_eq!(outcome.completion["usage"]["total_tokens"], 12);
+
+    let requests = seen.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["stream"], false);
+    assert!(requests[0].get("jan_tools").is_none());
+    assert!(requests[0].get("stream_options").is_none());
+    assert_eq!(requests[0]["tools"][0]["function"]["name"], "lookup");
+}
+
+#[tokio::test]
+async fn test_respond_with_tools_streams_final_answer() {
+    let (servers, _) = empty_mcp_state();
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = seen.clone();
+    let response = tools::respond_with_tools(
+        hyper::Response::builder(),
+        servers,
+        json!({
+            "model": "llama3.2",
+            "stream": true,
+            "jan_tools": "auto",
+            "messages": [{ "role": "user", "content": "Hi" }]
+        }),
+        ToolSelection::All,
+        move |request| {
+            recorded.lock().unwrap().push(request);
+            async {
+                let (mut sender, body) = hyper::Body::channel();
+                tokio::spawn(async move {
+                    for chunk in [
+                        json!({ "id": "upstream", "choices": [{ "index": 0, "delta": { "role": "assistant", "content": "Hel" }, "finish_reason": null }] }),
+                        json!({ "id": "upstream", "choices": [{ "index": 0, "delta": { "content": "lo" }, "finish_reason": null }] }),
+                        json!({ "id": "upstream", "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }] }),
+                    ] {
+                        let _ = sender.send_data(sse_data(&chunk)).await;
+                    }
+                    let _ = sender
+                        .send_data(hyper::body::Bytes::from_static(b"data: [DONE]\n\n"))
+                        .await;
+                });
+                Ok(hyper::Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+                    .body(body)
+                    .unwrap())
+            }
+        },
+    )
+    .await;
+    assert_eq!(
+        response.headers()[hyper::header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    let chunks: Vec<serde_json::Value> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter_map(|data| serde_json::from_str(data).ok())
+        .collect();
+
+    // Each delta is passed on as it arrives, under the proxy's completion id
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+    assert_eq!(chunks[0]["choices"][0]["delta"]["content"], "Hel");
+    assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "lo");
+    assert_eq!(chunks[2]["choices"][0]["finish_reason"], "stop");
+    assert_eq!(chunks[0]["id"], chunks[2]["id"]);
+    assert_ne!(chunks[0]["id"], "upstream");
+    assert!(text.ends_with("data: [DONE]\n\n"));
+
+    let requests = seen.lock().unwrap();
+    assert_eq!(requests[0]["stream"], true);
+    assert_eq!(requests[0]["stream_options"]["include_usage"], true);
+}
+
+#[tokio::test]
+async fn test_respond_with_tools_streams_mcp_tool_loop() {
+    let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let servers = weather_mcp_servers(calls.clone()).await;
+    let fixture = weather_fixture_calling("weather__get_weather");
+    let model_id = format!("mock/{}", fixture);
+    let response = tools::respond_with_tools(
+        hyper::Response::builder(),
+        servers,
+        json!({
+            "model": model_id,
+            "stream": true,
+            "stream_options": { "include_usage": true },
+            "jan_tools": "auto",
+            "messages": [{ "role": "user", "content": "Weather in Paris?" }]
+        }),
+        ToolSelection::All,
+        move |request| {
+            let (fixture, model_id) = (fixture.clone(), model_id.clone());
+            async move {
+                Ok(handle_chat_completion(
+                    ChatService::new(),
+                    hyper::Response::builder(),
+                    request,
+                    model_id,
+                    "mock".to_string(),
+                    fixture,
+                    None,
+                )
+                .await)
+            }
+        },
+    )
+    .await;
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    let chunks: Vec<serde_json::Value> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter_map(|data| serde_json::from_str(data).ok())
+        .collect();
+
+    assert_eq!(*calls.lock().unwrap(), vec![json!({ "city": "Paris" })]);
+    let steps: Vec<_> = chunks
+        .iter()
+        .filter_map(|chunk| chunk.get("jan_tool_step"))
+        .collect();
+    assert_eq!(steps.len(), 2);
+    assert_eq!(steps[0]["type"], "tool_call");
+    assert_eq!(steps[1]["content"], "Sunny, 22°C in Paris");
+    // The MCP call ran on the server, so the client only sees the answer
+    assert!(!text.contains("\"tool_calls\""));
+    let content: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk.pointer("/choices/0/delta/content"))
+        .filter_map(|c| c.as_str())
+        .collect();
+    assert_eq!(content, "It is sunny in Paris.");
+    assert!(chunks.iter().any(|chunk| chunk["usage"].is_object()));
+    assert!(text.ends_with("data: [DONE]\n\n"));
+}
+
+#[tokio::test]
+async fn test_respond_with_tools_reports_errors_as_json() {
+    let (servers, _) = empty_mcp_state();
+    let response = tools::respond_with_tools(
+        hyper::Response::builder(),
+        servers,
+        json!({
+            "model": "llama3.2",
+            "jan_tools": "auto",
+            "messages": [{ "role": "user", "content": "Hi" }]
+        }),
+        ToolSelection::All,
+        |_request| async {
+            Err((
+                hyper::StatusCode::NOT_FOUND,
+                "No running session found for model 'llama3.2'".to_string(),
+            ))
+        },
+    )
+    .await;
+    assert_eq!(response.status(), hyper::StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.headers()[hyper::header::CONTENT_TYPE],
+        "application/json"
+    );
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(
+        body["error"]["message"],
+        "No running session found for model 'llama3.2'"
+    );
+    assert_eq!(body["error"]["type"], "not_found_error");
+}
@@ -0,0 +1,494 @@
+//! OpenAI Assistants-style `/threads` API over the threads module, so scripts can read
+//! and append to Jan conversations. Reads and writes go through the same helpers and
+//! per-thread locks as the Tauri commands.
+
+use hyper::body::Bytes;
+use hyper::http::response::Builder;
+use hyper::{Body, Method, Response, StatusCode};
+use serde_json::{json, Map, Value};
+use std::fs;
+use tauri::Runtime;
+
+use crate::core::chat::recorder::thread_text_content;
+use crate::core::threads::commands::{
+    create_message, create_thread, delete_message, delete_thread, list_messages, list_threads,
+};
+use crate::core::threads::helpers::{
+    get_lock_for_thread, merge_metadata_object, update_message, update_thread_metadata,
+};
+use crate::core::threads::utils::get_thread_metadata_path;
+
+/// Page size of list requests without `limit`, and the largest accepted
+pub const DEFAULT_PAGE_LIMIT: usize = 20;
+pub const MAX_PAGE_LIMIT: usize = 100;
+
+type ApiError = (StatusCode, String);
+
+fn not_found(what: &str, id: &str) -> ApiError {
+    (
+        StatusCode::NOT_FOUND,
+        format!("No {} found with id '{}'", what, id),
+    )
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    (StatusCode::BAD_REQUEST, message.into())
+}
+
+fn internal(message: String) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, message)
+}
+
+fn now_secs() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// A route of the threads API, from a path with the server prefix removed
+#[derive(Debug, PartialEq)]
+pub enum ThreadRoute<'a> {
+    Threads,
+    Thread(&'a str),
+    Messages(&'a str),
+    Message(&'a str, &'a str),
+}
+
+/// Ids are used as directory names, so only plain names are accepted
+fn valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id != "."
+        && id != ".."
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Parses `/threads`, `/threads/{id}`, `/threads/{id}/messages` and
+/// `/threads/{id}/messages/{message_id}`
+pub fn parse_route(path: &str) -> Option<ThreadRoute<'_>> {
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').skip(1).collect();
+    let route = match segments.as_slice() {
+        ["threads"] => ThreadRoute::Threads,
+        ["threads", id] => ThreadRoute::Thread(id),
+        ["threads", id, "messages"] => ThreadRoute::Messages(id),
+        ["threads", id, "messages", message_id] => ThreadRoute::Message(id, message_id),
+        _ => return None,
+    };
+    let ids_valid = match route {
+        ThreadRoute::Threads => true,
+        ThreadRoute::Thread(id) | ThreadRoute::Messages(id) => valid_id(id),
+        ThreadRoute::Message(id, message_id) => valid_id(id) && valid_id(message_id),
+    };
+    ids_valid.then_some(route)
+}
+
+/// `limit`, `order`, `after` and `before` of a list request
+#[derive(Debug, PartialEq)]
+pub struct PageQuery {
+    pub limit: usize,
+    pub ascending: bool,
+    pub after: Option<String>,
+    pub before: Option<String>,
+}
+
+impl PageQuery {
+    /// Parses the query string of a list request. The order is newest first unless
+    /// `order=asc`.
+    pub fn parse(query: Option<&str>) -> Result<Self, String> {
+        let mut page = Self {
+            limit: DEFAULT_PAGE_LIMIT,
+            ascending: false,
+            after: None,
+            before: None,
+        };
+        for pair in query
+            .unwrap_or_default()
+            .split('&')
+            .filter(|p| !p.is_empty())
+        {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "limit" => {
+                    page.limit = value
+                        .parse()
+                        .ok()
+                        .filter(|limit| (1..=MAX_PAGE_LIMIT).contains(limit))
+                        .ok_or_else(|| format!("limit must be between 1 and {}", MAX_PAGE_LIMIT))?;
+                }
+                "order" => {
+                    page.ascending = match value {
+                        "asc" => true,
+                        "desc" => false,
+                        _ => return Err("order must be 'asc' or 'desc'".to_string()),
+                    };
+                }
+                "after" if !value.is_empty() => page.after = Some(value.to_string()),
+                "before" if !value.is_empty() => page.before = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        Ok(page)
+    }
+}
+
+fn item_id(item: &Value) -> Option<&str> {
+    item.get("id").and_then(|id| id.as_str())
+}
+
+/// One page of `items`, which are in ascending order, as an OpenAI list object.
+/// `after` and `before` are cursors relative to the requested order; a cursor that
+/// matches no item is an error.
+pub fn paginate(mut items: Vec<Value>, page: &PageQuery) -> Result<Value, String> {
+    if !page.ascending {
+        items.reverse();
+    }
+    let position = |name: &str, cursor: &Option<String>| -> Result<Option<usize>, String> {
+        let Some(cursor) = cursor.as_deref() else {
+            return Ok(None);
+        };
+        items
+            .iter()
+            .position(|item| item_id(item) == Some(cursor))
+            .map(Some)
+            .ok_or_else(|| format!("No item with id '{}' for '{}'", cursor, name))
+    };
+    let start = position("after", &page.after)?.map_or(0, |index| index + 1);
+    let end = position("before", &page.before)?
+        .unwrap_or(items.len())
+        .max(start);
+
+    let window = &items[start..end];
+    let data: Vec<Value> = window.iter().take(page.limit).cloned().collect();
+    Ok(json!({
+        "object": "list",
+        "first_id": data.first().and_then(item_id),
+        "last_id": data.last().and_then(item_id),
+        "has_more": window.len() > data.len(),
+        "data": data,
+    }))
+}
+
+fn read_thread<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    thread_id: &str,
+) -> Result<Value, ApiError> {
+    let path = get_thread_metadata_path(app_handle.clone(), thread_id);
+    if !path.exists() {
+        return Err(not_found("thread", thread_id));
+    }
+    let data = fs::read_to_string(&path).map_err(|e| internal(e.to_string()))?;
+    serde_json::from_str(&data).map_err(|e| internal(e.to_string()))
+}
+
+/// Thread content from an OpenAI message `content`: a string, or text and
+/// `image_url` parts
+pub fn message_content(content: &Value) -> Result<Vec<Value>, String> {
+    match content {
+        Value::String(text) => Ok(vec![thread_text_content(text)]),
+        Value::Array(parts) => parts
+            .iter()
+            .map(|part| match part.get("type").and_then(|t| t.as_str()) {
+                Some("text") => match part.get("text") {
+                    Some(Value::String(text)) => Ok(thread_text_content(text)),
+                    Some(text) if text.get("value").is_some() => Ok(part.clone()),
+                    _ => Err("text parts need a 'text' value".to_string()),
+                },
+                Some("image_url") if part.pointer("/image_url/url").is_some() => {
+                    Ok(json!({ "type": "image_url", "image_url": part["image_url"].clone() }))
+                }
+                _ => Err(format!("Unsupported content part: {}", part)),
+            })
+            .collect(),
+        _ => Err("content must be a string or an array of parts".to_string()),
+    }
+}
+
+/// A new thread message from an OpenAI "create message" body
+pub fn new_message(thread_id: &str, body: &Value) -> Result<Value, String> {
+    let role = body
+        .get("role")
+        .and_then(|r| r.as_str())
+        .ok_or("role is required")?;
+    if !matches!(role, "user" | "assistant" | "system") {
+        return Err(format!("Unsupported role '{}'", role));
+    }
+    let content = message_content(body.get("content").ok_or("content is required")?)?;
+    let now = now_ms();
+    let mut message = json!({
+        "id": uuid::Uuid::new_v4().to_string(),
+        "object": "thread.message",
+        "thread_id": thread_id,
+        "role": role,
+        "content": content,
+        "status": "ready",
+        "created_at": now,
+        "completed_at": now,
+        "metadata": body.get("metadata").filter(|m| m.is_object()).cloned().unwrap_or_else(|| json!({})),
+    });
+    for key in ["assistant_id", "attachments"] {
+        if let Some(value) = body.get(key).filter(|v| !v.is_null()) {
+            message[key] = value.clone();
+        }
+    }
+    Ok(message)
+}
+
+/// Copies the modifiable fields of a "modify thread" body onto `thread`.
+/// `metadata` is merged; `id` and `created` are kept.
+pub fn apply_thread_changes(thread: &mut Value, changes: &Value) -> Result<(), String> {
+    let changes = changes.as_object().ok_or("Body must be a JSON object")?;
+    let thread = thread.as_object_mut().ok_or("Invalid thread.json")?;
+    for (key, value) in changes {
+        match key.as_str() {
+            "id" | "object" | "created" => {}
+            "metadata" => {
+                let metadata = value
+                    .as_object()
+                    .cloned()
+                    .ok_or("metadata must be an object")?;
+                merge_metadata_object(thread, metadata);
+            }
+            _ => {
+                thread.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    thread.insert("updated".to_string(), json!(now_secs()));
+    Ok(())
+}
+
+async fn thread_messages<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    thread_id: &str,
+) -> Result<Vec<Value>, ApiError> {
+    read_thread(app_handle, thread_id)?;
+    list_messages(app_handle.clone(), thread_id.to_string())
+        .await
+        .map_err(internal)
+}
+
+async fn create_api_thread<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    body: Value,
+) -> Result<Value, ApiError> {
+    let body = match body {
+        Value::Null => Map::new(),
+        Value::Object(body) => body,
+        _ => return Err(bad_request("Body must be a JSON object")),
+    };
+    let initial_messages = match body.get("messages") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Array(messages)) => messages.clone(),
+        Some(_) => return Err(bad_request("messages must be an array")),
+    };
+    // Checked before the thread is written so a bad message creates nothing
+    for message in &initial_messages {
+        new_message("", message).map_err(bad_request)?;
+    }
+
+    let now = now_secs();
+    let mut thread = json!({
+        "object": "thread",
+        "title": "New Thread",
+        "assistants": [],
+        "created": now,
+        "updated": now,
+        "metadata": {},
+    });
+    for (key, value) in body {
+        if !matches!(
+            key.as_str(),
+            "id" | "object" | "messages" | "created" | "updated"
+        ) {
+            thread[key] = value;
+        }
+    }
+    let thread = create_thread(app_handle.clone(), thread)
+        .await
+        .map_err(internal)?;
+    let thread_id = item_id(&thread).unwrap_or_default().to_string();
+    for message in &initial_messages {
+        let message = new_message(&thread_id, message).map_err(bad_request)?;
+        create_message(app_handle.clone(), message)
+            .await
+            .map_err(internal)?;
+    }
+    Ok(thread)
+}
+
+async fn modify_api_thread<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    thread_id: &str,
+    changes: Value,
+) -> Result<Value, ApiError> {
+    let lock = get_lock_for_thread(thread_id).await;
+    let _guard = lock.lock().await;
+
+    let mut thread = read_thread(app_handle, thread_id)?;
+    apply_thread_changes(&mut thread, &changes).map_err(bad_request)?;
+    update_thread_metadata(app_handle.clone(), thread_id, &thread).map_err(internal)?;
+    Ok(thread)
+}
+
+async fn modify_api_message<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    thread_id: &str,
+    message_id: &str,
+    changes: Value,
+) -> Result<Value, ApiError> {
+    read_thread(app_handle, thread_id)?;
+    let metadata = match changes.get("metadata") {
+        None => None,
+        Some(Value::Object(metadata)) => Some(metadata.clone()),
+        Some(_) => return Err(bad_request("metadata must be an object")),
+    };
+    let content = changes
+        .get("content")
+        .map(message_content)
+        .transpose()
+        .map_err(bad_request)?;
+
+    update_message(app_handle.clone(), thread_id, message_id, |message| {
+        if let Some(metadata) = metadata {
+            merge_metadata_object(message, metadata);
+        }
+        if let Some(content) = content {
+            message.insert("content".to_string(), Value::Array(content));
+        }
+    })
+    .await
+    .map_err(|_| not_found("message", message_id))?;
+
+    thread_messages(app_handle, thread_id)
+        .await?
+        .into_iter()
+        .find(|m| item_id(m) == Some(message_id))
+        .ok_or_else(|| not_found("message", message_id))
+}
+
+async fn route_request<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    method: &Method,
+    route: ThreadRoute<'_>,
+    query: Option<&str>,
+    body: Value,
+) -> Result<Value, ApiError> {
+    match (method, route) {
+        (&Method::GET, ThreadRoute::Threads) => {
+            let page = PageQuery::parse(query).map_err(bad_request)?;
+            let mut threads = list_threads(app_handle.clone()).await.map_err(internal)?;
+            threads.sort_by_key(|t| t.get("updated").and_then(|u| u.as_i64()).unwrap_or(0));
+            paginate(threads, &page).map_err(bad_request)
+        }
+        (&Method::POST, ThreadRoute::Threads) => create_api_thread(app_handle, body).await,
+        (&Method::GET, ThreadRoute::Thread(id)) => read_thread(app_handle, id),
+        (&Method::POST, ThreadRoute::Thread(id)) => modify_api_thread(app_handle, id, body).await,
+        (&Method::DELETE, ThreadRoute::Thread(id)) => {
+            read_thread(app_handle, id)?;
+            let lock = get_lock_for_thread(id).await;
+            let _guard = lock.lock().await;
+            delete_thread(app_handle.clone(), id.to_string())
+                .await
+                .map_err(internal)?;
+            Ok(json!({ "id": id, "object": "thread.deleted", "deleted": true }))
+        }
+        (&Method::GET, ThreadRoute::Messages(id)) => {
+            let page = PageQuery::parse(query).map_err(bad_request)?;
+            paginate(thread_messages(app_handle, id).await?, &page).map_err(bad_request)
+        }
+        (&Method::POST, ThreadRoute::Messages(id)) => {
+            read_thread(app_handle, id)?;
+            let message = new_message(id, &body).map_err(bad_request)?;
+            create_message(app_handle.clone(), message)
+                .await
+                .map_err(internal)
+        }
+        (&Method::GET, ThreadRoute::Message(id, message_id)) => thread_messages(app_handle, id)
+            .await?
+            .into_iter()
+            .find(|m| item_id(m) == Some(message_id))
+            .ok_or_else(|| not_found("message", message_id)),
+        (&Method::POST, ThreadRoute::Message(id, message_id)) => {
+            modify_api_message(app_handle, id, message_id, body).await
+        }
+        (&Method::DELETE, ThreadRoute::Message(id, message_id)) => {
+            if !thread_messages(app_handle, id)
+                .await?
+                .iter()
+                .any(|m| item_id(m) == Some(message_id))
+            {
+                return Err(not_found("message", message_id));
+            }
+            delete_message(app_handle.clone(), id.to_string(), message_id.to_string())
+                .await
+                .map_err(internal)?;
+            Ok(json!({ "id": message_id, "object": "thread.message.deleted", "deleted": true }))
+        }
+        _ => Err((
+            StatusCode::METHOD_NOT_ALLOWED,
+            format!("{} is not supported here", method),
+        )),
+    }
+}
+
+/// Handles a request to `/threads` or below
+///
+/// # Arguments
+/// * `app_handle` - Used to locate the threads in the data folder
+/// * `builder` - Response builder with CORS headers already applied
+/// * `method` / `path` / `query` - The request, with the server prefix removed from `path`
+/// * `body` - The request body, empty for `GET` and `DELETE`
+pub async fn handle_threads_request<R: Runtime>(
+    app_handle: &tauri::AppHandle<R>,
+    builder: Builder,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    body: Bytes,
+) -> Response<Body> {
+    let result = match parse_route(path) {
+        None => Err((StatusCode::NOT_FOUND, format!("Unknown path {}", path))),
+        Some(route) => {
+            let body = if body.is_empty() {
+                Ok(Value::Null)
+            } else {
+                serde_json::from_slice(&body)
+                    .map_err(|e| bad_request(format!("Invalid JSON body: {}", e)))
+            };
+            match body {
+                Ok(body) => route_request(app_handle, method, route, query, body).await,
+                Err(e) => Err(e),
+            }
+        }
+    };
+
+    let (status, body) = match result {
+        Ok(value) => (StatusCode::OK, value),
+        Err((status, message)) => {
+            log::debug!("Threads API {} {} failed: {}", method, path, message);
+            let error_type = if status == StatusCode::INTERNAL_SERVER_ERROR {
+                "server_error"
+            } else {
+                "invalid_request_error"
+            };
+            (
+                status,
+                json!({ "error": { "message": message, "type": error_type } }),
+            )
+        }
+    };
+    builder
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
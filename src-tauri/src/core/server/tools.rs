@@ -0,0 +1,660 @@
+//! Server-side tool execution for `/chat/completions`. A request opting in with
+//! `"jan_tools": "auto"` (or a list of tools) or the `x-jan-tools` header gets the
+//! connected MCP tools added; the server runs the tool calls the model makes and asks
+//! again until it answers, then replies in the standard OpenAI format.
+//!
+//! When streaming, every request to the model streams too: text and reasoning go to
+//! the client as they arrive, while tool calls are collected and run once the model's
+//! turn ends. Every tool call and result is sent as an extra chunk with empty
+//! `choices` and a `jan_tool_step` field. OpenAI clients already skip such chunks (they
+//! look like usage chunks), so only clients that know about the steps see them.
+
+use futures_util::StreamExt;
+use hyper::body::Bytes;
+use hyper::http::response::Builder;
+use hyper::{Body, Response, StatusCode};
+use serde_json::{json, Map, Value};
+use std::future::Future;
+use tokio::sync::mpsc;
+
+use super::mcp::{namespaced_tool_name, resolve_tool_name};
+use super::remote::{openai_error_response, read_json_response, sse_data, sse_error};
+use crate::core::chat::agent::run_server_tool;
+use crate::core::mcp::helpers::list_tools_with_server;
+use crate::core::state::SharedMcpServers;
+
+/// Request field and header that turn on server-side tool execution
+pub const TOOLS_FIELD: &str = "jan_tools";
+pub const TOOLS_HEADER: &str = "x-jan-tools";
+
+/// Model requests per chat completion before the model must answer without tools
+pub const MAX_TOOL_ITERATIONS: usize = 10;
+
+type ApiError = (StatusCode, String);
+
+/// Which MCP tools a request offers to the model
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolSelection {
+    All,
+    /// Namespaced tool names (`server__tool`) or whole servers
+    Only(Vec<String>),
+}
+
+impl ToolSelection {
+    fn includes(&self, server: &str, name: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(names) => names.iter().any(|n| n == server || n == name),
+        }
+    }
+}
+
+/// Reads the opt-in from the `jan_tools` field, then the `x-jan-tools` header.
+/// `"auto"` selects every tool, an array (or comma-separated header) some of them;
+/// `"none"` or nothing leaves the request alone.
+pub fn tool_selection(body: &Value, headers: &hyper::HeaderMap) -> Option<ToolSelection> {
+    let from_list = |names: Vec<String>| (!names.is_empty()).then_some(ToolSelection::Only(names));
+    let from_str = |value: &str| match value.trim() {
+        "" | "none" => None,
+        "auto" => Some(ToolSelection::All),
+        list => from_list(
+            list.split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect(),
+        ),
+    };
+    match body.get(TOOLS_FIELD) {
+        Some(Value::String(value)) => from_str(value),
+        Some(Value::Array(names)) => from_list(
+            names
+                .iter()
+                .filter_map(|n| n.as_str().map(String::from))
+                .collect(),
+        ),
+        Some(_) => None,
+        None => headers
+            .get(TOOLS_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(from_str),
+    }
+}
+
+/// The selected MCP tools as OpenAI function tools
+pub async fn mcp_tools(
+    servers: &SharedMcpServers,
+    selection: &ToolSelection,
+) -> Result<Vec<Value>, String> {
+    Ok(list_tools_with_server(servers, None)
+        .await?
+        .into_iter()
+        .filter(|tool| {
+            selection.includes(
+                &tool.server,
+                &namespaced_tool_name(&tool.server, &tool.name),
+            )
+        })
+        .map(|tool| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": namespaced_tool_name(&tool.server, &tool.name),
+                    "description": tool.description.unwrap_or_default(),
+                    "parameters": tool.input_schema,
+                }
+            })
+        })
+        .collect())
+}
+
+fn function_name(tool: &Value) -> Option<&str> {
+    tool.pointer("/function/name").and_then(|n| n.as_str())
+}
+
+/// Runs one tool call of the model. Returns the `tool` message for the conversation
+/// and whether the call failed; failures are told to the model rather than ending
+/// the request.
+pub async fn execute_tool_call(servers: &SharedMcpServers, call: &Value) -> (Value, bool) {
+    let call_id = call
+        .get("id")
+        .and_then(|id| id.as_str())
+        .unwrap_or_default();
+    let name = call
+        .pointer("/function/name")
+        .and_then(|n| n.as_str())
+        .unwrap_or_default();
+    let arguments = call.pointer("/function/arguments").unwrap_or(&Value::Null);
+
+    let (content, is_error) = match resolve_tool_name(servers, name).await {
+        Some((server, tool)) => run_server_tool(servers, server, tool, arguments).await,
+        None => (format!("Tool {} is not available", name), true),
+    };
+    if is_error {
+        log::warn!("Server-side tool call {} failed: {}", name, content);
+    }
+    (
+        json!({ "role": "tool", "tool_call_id": call_id, "content": content }),
+        is_error,
+    )
+}
+
+fn add_usage(total: &mut Map<String, Value>, usage: Option<&Value>) {
+    for key in ["prompt_tokens", "completion_tokens", "total_tokens"] {
+        let step = usage
+            .and_then(|u| u.get(key))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0);
+        let sum = total.get(key).and_then(|v| v.as_u64()).unwrap_or(0) + step;
+        total.insert(key.to_string(), json!(sum));
+    }
+}
+
+/// What a streaming tool loop sends while it runs
+#[derive(Debug)]
+pub enum ToolLoopEvent {
+    /// A tool call or tool result
+    Step(Value),
+    /// Text or reasoning the model streamed, as a chunk `delta`
+    Delta(Value),
+}
+
+/// Builds a `chat.completion` from the chunks of a streamed one
+#[derive(Default)]
+struct StreamedCompletion {
+    id: Value,
+    created: Value,
+    model: Value,
+    content: String,
+    reasoning: String,
+    tool_calls: Vec<Value>,
+    finish_reason: Value,
+    usage: Value,
+}
+
+impl StreamedCompletion {
+    /// Adds a chunk, returning the text and reasoning delta to pass on, if any
+    fn push(&mut self, chunk: &Value) -> Option<Value> {
+        for (field, key) in [
+            (&mut self.id, "id"),
+            (&mut self.created, "created"),
+            (&mut self.model, "model"),
+        ] {
+            if field.is_null() {
+                *field = chunk.get(key).cloned().unwrap_or(Value::Null);
+            }
+        }
+        if let Some(usage) = chunk.get("usage").filter(|u| u.is_object()) {
+            self.usage = usage.clone();
+        }
+        let choice = chunk.pointer("/choices/0")?;
+        if let Some(reason) = choice.get("finish_reason").filter(|r| !r.is_null()) {
+            self.finish_reason = reason.clone();
+        }
+        let delta = choice.get("delta")?;
+        for call in delta
+            .get("tool_calls")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+        {
+            let index = call
+                .get("index")
+                .and_then(|i| i.as_u64())
+                .map_or(self.tool_calls.len(), |i| i as usize);
+            while self.tool_calls.len() <= index {
+                self.tool_calls.push(json!({
+                    "id": null,
+                    "type": "function",
+                    "function": { "name": "", "arguments": "" },
+                }));
+            }
+            let entry = &mut self.tool_calls[index];
+            if let Some(id) = call.get("id").filter(|id| id.is_string()) {
+                entry["id"] = id.clone();
+            }
+            for key in ["name", "arguments"] {
+                if let Some(part) = call
+                    .pointer(&format!("/function/{}", key))
+                    .and_then(|p| p.as_str())
+                {
+                    let joined =
+                        format!("{}{}", entry["function"][key].as_str().unwrap_or(""), part);
+                    entry["function"][key] = json!(joined);
+                }
+            }
+        }
+
+        let mut forward = Map::new();
+        for (key, text) in [
+            ("content", &mut self.content),
+            ("reasoning_content", &mut self.reasoning),
+        ] {
+            if let Some(part) = delta
+                .get(key)
+                .and_then(|c| c.as_str())
+                .filter(|c| !c.is_empty())
+            {
+                text.push_str(part);
+                forward.insert(key.to_string(), json!(part));
+            }
+        }
+        (!forward.is_empty()).then_some(Value::Object(forward))
+    }
+
+    fn finish(self) -> Value {
+        let mut message = json!({ "role": "assistant", "content": self.content });
+        if self.content.is_empty() && !self.tool_calls.is_empty() {
+            message["content"] = Value::Null;
+        }
+        if !self.reasoning.is_empty() {
+            message["reasoning_content"] = json!(self.reasoning);
+        }
+        let finish_reason = match self.finish_reason {
+            Value::Null if !self.tool_calls.is_empty() => json!("tool_calls"),
+            Value::Null => json!("stop"),
+            reason => reason,
+        };
+        if !self.tool_calls.is_empty() {
+            message["tool_calls"] = Value::Array(self.tool_calls);
+        }
+        json!({
+            "id": self.id,
+            "object": "chat.completion",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{ "index": 0, "message": message, "finish_reason": finish_reason }],
+            "usage": self.usage,
+        })
+    }
+}
+
+/// Reads one streamed model turn into a `chat.completion`, sending its text and
+/// reasoning to `events` as they arrive. A provider that answers with plain JSON
+/// instead has its message sent as one delta.
+async fn read_streamed_turn(
+    response: Response<Body>,
+    events: &mpsc::UnboundedSender<ToolLoopEvent>,
+) -> Result<Value, ApiError> {
+    let disconnected = || (StatusCode::BAD_GATEWAY, "Client disconnected".to_string());
+    let is_sse = response
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/event-stream"));
+    if !response.status().is_success() || !is_sse {
+        let completion = read_json_response(response).await?;
+        let mut streamed = StreamedCompletion::default();
+        let message = completion.pointer("/choices/0/message").cloned();
+        if let Some(delta) = streamed.push(&json!({ "choices": [{ "delta": message }] })) {
+            events
+                .send(ToolLoopEvent::Delta(delta))
+                .map_err(|_| disconnected())?;
+        }
+        return Ok(completion);
+    }
+
+    let mut streamed = StreamedCompletion::default();
+    let mut body = response.into_body();
+    let mut buffer = Vec::new();
+    while let Some(bytes) = body.next().await {
+        let bytes = bytes.map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?;
+        buffer.extend_from_slice(&bytes);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            if data == "[DONE]" {
+                return Ok(streamed.finish());
+            }
+            let Ok(chunk) = serde_json::from_str::<Value>(data) else {
+                continue;
+            };
+            if let Some(error) = chunk.get("error") {
+                let message = error
+                    .get("message")
+                    .and_then(|m| m.as_str())
+                    .map_or_else(|| error.to_string(), String::from);
+                return Err((StatusCode::BAD_GATEWAY, message));
+            }
+            if let Some(delta) = streamed.push(&chunk) {
+                events
+                    .send(ToolLoopEvent::Delta(delta))
+                    .map_err(|_| disconnected())?;
+            }
+        }
+    }
+    Ok(streamed.finish())
+}
+
+/// The final completion of a tool loop, with the steps taken to reach it
+#[derive(Debug)]
+pub struct ToolLoopOutcome {
+    /// The model's last `chat.completion`, with usage summed over all requests
+    pub completion: Value,
+    pub steps: Vec<Value>,
+}
+
+/// Runs the tool loop for `request`, an OpenAI chat completion request. `complete`
+/// makes one request to the model and returns its OpenAI response. With `events`,
+/// every request streams, and its text and each step are sent there as they happen;
+/// the loop stops when that channel is closed.
+pub async fn run_tool_loop<F, Fut>(
+    servers: SharedMcpServers,
+    mut request: Value,
+    selection: ToolSelection,
+    complete: F,
+    events: Option<mpsc::UnboundedSender<ToolLoopEvent>>,
+) -> Result<ToolLoopOutcome, ApiError>
+where
+    F: Fn(Value) -> Fut,
+    Fut: Future<Output = Result<Response<Body>, ApiError>>,
+{
+    let object = request.as_object_mut().ok_or((
+        StatusCode::BAD_REQUEST,
+        "Body must be a JSON object".to_string(),
+    ))?;
+    object.remove(TOOLS_FIELD);
+    object.remove("stream_options");
+    object.insert("stream".to_string(), json!(events.is_some()));
+    if events.is_some() {
+        object.insert(
+            "stream_options".to_string(),
+            json!({ "include_usage": true }),
+        );
+    }
+
+    let offered = mcp_tools(&servers, &selection)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+    let mcp_names: Vec<String> = offered
+        .iter()
+        .filter_map(function_name)
+        .map(String::from)
+        .collect();
+    let mut tools = object
+        .get("tools")
+        .and_then(|t| t.as_array())
+        .cloned()
+        .unwrap_or_default();
+    tools.retain(|tool| {
+        !function_name(tool).is_some_and(|name| mcp_names.iter().any(|n| n == name))
+    });
+    tools.extend(offered);
+    if !tools.is_empty() {
+        object.insert("tools".to_string(), Value::Array(tools));
+    }
+
+    let mut usage = Map::new();
+    let mut taken = Vec::new();
+    let mut record = |step: Value| -> Result<(), ApiError> {
+        if let Some(events) = &events {
+            events
+                .send(ToolLoopEvent::Step(step.clone()))
+                .map_err(|_| (StatusCode::BAD_GATEWAY, "Client disconnected".to_string()))?;
+        }
+        taken.push(step);
+        Ok(())
+    };
+
+    let mut iteration = 0;
+    loop {
+        iteration += 1;
+        let last = iteration == MAX_TOOL_ITERATIONS;
+        if last {
+            // Last request: the model has to answer with what it has
+            request["tool_choice"] = json!("none");
+        }
+        let response = complete(request.clone()).await?;
+        let mut completion = match &events {
+            Some(events) => read_streamed_turn(response, events).await?,
+            None => read_json_response(response).await?,
+        };
+        add_usage(&mut usage, completion.get("usage"));
+
+        let message = completion
+            .pointer("/choices/0/message")
+            .cloned()
+            .unwrap_or(Value::Null);
+        let calls: Vec<Value> = message
+            .get("tool_calls")
+            .and_then(|c| c.as_array())
+            .cloned()
+            .unwrap_or_default();
+        // Calls of the client's own tools go back to the client
+        let runs_here = !calls.is_empty()
+            && calls.iter().all(|call| {
+                call.pointer("/function/name")
+                    .and_then(|n| n.as_str())
+                    .is_some_and(|name| mcp_names.iter().any(|n| n == name))
+            });
+        if !runs_here || last {
+            completion["usage"] = Value::Object(usage);
+            return Ok(ToolLoopOutcome {
+                completion,
+                steps: taken,
+            });
+        }
+
+        let mut messages = request
+            .get("messages")
+            .and_then(|m| m.as_array())
+            .cloned()
+            .unwrap_or_default();
+        messages.push(json!({
+            "role": "assistant",
+            "content": message.get("content").cloned().unwrap_or(Value::Null),
+            "tool_calls": calls,
+        }));
+        for call in &calls {
+            let name = call
+                .pointer("/function/name")
+                .cloned()
+                .unwrap_or(Value::Null);
+            record(json!({
+                "type": "tool_call",
+                "id": call.get("id"),
+                "name": name,
+                "arguments": call.pointer("/function/arguments"),
+            }))?;
+            let (tool_message, is_error) = execute_tool_call(&servers, call).await;
+            record(json!({
+                "type": "tool_result",
+                "tool_call_id": call.get("id"),
+                "name": name,
+                "content": tool_message["content"],
+                "is_error": is_error,
+            }))?;
+            messages.push(tool_message);
+        }
+        request["messages"] = Value::Array(messages);
+    }
+}
+
+/// A `chat.completion.chunk` of the streamed answer
+fn stream_chunk(completion_id: &str, model: &str, created: u64, choices: Value) -> Value {
+    json!({
+        "id": completion_id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": model,
+        "choices": choices,
+    })
+}
+
+/// A chunk carrying one tool step, with empty `choices` so clients skip it
+pub fn step_chunk(completion_id: &str, model: &str, created: u64, step: &Value) -> Value {
+    let mut chunk = stream_chunk(completion_id, model, created, json!([]));
+    chunk["jan_tool_step"] = step.clone();
+    chunk
+}
+
+/// The chunks ending a streamed answer whose text was already sent: the calls of the
+/// client's own tools, if any, then the finish reason, then the usage when
+/// `include_usage` is set
+pub fn closing_chunks(
+    completion_id: &str,
+    model: &str,
+    created: u64,
+    completion: &Value,
+    include_usage: bool,
+) -> Vec<Value> {
+    let chunk = |choices: Value| stream_chunk(completion_id, model, created, choices);
+    let mut chunks = Vec::new();
+    if let Some(calls) = completion
+        .pointer("/choices/0/message/tool_calls")
+        .and_then(|c| c.as_array())
+    {
+        let indexed: Vec<Value> = calls
+            .iter()
+            .enumerate()
+            .map(|(index, call)| {
+                let mut call = call.clone();
+                call["index"] = json!(index);
+                call
+            })
+            .collect();
+        chunks.push(chunk(json!([{
+            "index": 0,
+            "delta": { "tool_calls": indexed },
+            "finish_reason": null,
+        }])));
+    }
+
+    let finish_reason = completion
+        .pointer("/choices/0/finish_reason")
+        .cloned()
+        .unwrap_or(json!("stop"));
+    chunks.push(chunk(
+        json!([{ "index": 0, "delta": {}, "finish_reason": finish_reason }]),
+    ));
+    if include_usage {
+        let mut usage = chunk(json!([]));
+        usage["usage"] = completion.get("usage").cloned().unwrap_or(Value::Null);
+        chunks.push(usage);
+    }
+    chunks
+}
+
+/// Answers a chat completion request with server-side tool execution
+///
+/// # Arguments
+/// * `builder` - Response builder with CORS headers already applied
+/// * `servers` - The running MCP servers providing the tools
+/// * `request` - The client's OpenAI request
+/// * `selection` - The tools the request opted in to
+/// * `complete` - Makes one chat completion request to the model and returns its response
+pub async fn respond_with_tools<F, Fut>(
+    builder: Builder,
+    servers: SharedMcpServers,
+    request: Value,
+    selection: ToolSelection,
+    complete: F,
+) -> Response<Body>
+where
+    F: Fn(Value) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Response<Body>, ApiError>> + Send + 'static,
+{
+    let stream = request
+        .get("stream")
+        .and_then(|s| s.as_bool())
+        .unwrap_or(false);
+    if !stream {
+        return match run_tool_loop(servers, request, selection, complete, None).await {
+            Ok(outcome) => {
+                let mut completion = outcome.completion;
+                completion["jan_tool_steps"] = Value::Array(outcome.steps);
+                builder
+                    .status(StatusCode::OK)
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(completion.to_string()))
+                    .unwrap()
+            }
+            Err((status, message)) => openai_error_response(builder, status, &message),
+        };
+    }
+
+    let include_usage = request
+        .pointer("/stream_options/include_usage")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let model = request
+        .get("model")
+        .and_then(|m| m.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let completion_id = format!("chatcmpl-{}", uuid::Uuid::new_v4());
+    let created = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<ToolLoopEvent>();
+    let loop_task = tokio::spawn(run_tool_loop(
+        servers,
+        request,
+        selection,
+        complete,
+        Some(event_tx),
+    ));
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        let mut started = false;
+        while let Some(event) = event_rx.recv().await {
+            let chunk = match event {
+                ToolLoopEvent::Step(step) => step_chunk(&completion_id, &model, created, &step),
+                ToolLoopEvent::Delta(mut delta) => {
+                    if !started {
+                        delta["role"] = json!("assistant");
+                        started = true;
+                    }
+                    let choices = json!([{ "index": 0, "delta": delta, "finish_reason": null }]);
+                    stream_chunk(&completion_id, &model, created, choices)
+                }
+            };
+            if sender.send_data(sse_data(&chunk)).await.is_err() {
+                log::debug!("Client disconnected during tool execution, cancelling");
+                loop_task.abort();
+                return;
+            }
+        }
+        // The loop dropped its sender, so it has finished
+        match loop_task.await {
+            Ok(Ok(outcome)) => {
+                let chunks = closing_chunks(
+                    &completion_id,
+                    &model,
+                    created,
+                    &outcome.completion,
+                    include_usage,
+                );
+                for chunk in chunks {
+                    if sender.send_data(sse_data(&chunk)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Ok(Err((_, message))) => {
+                log::error!("Tool loop for {} failed: {}", model, message);
+                let _ = sender.send_data(sse_error(&message)).await;
+            }
+            Err(e) => {
+                let _ = sender
+                    .send_data(sse_error(&format!("Task failed: {}", e)))
+                    .await;
+            }
+        }
+        let _ = sender
+            .send_data(Bytes::from_static(b"data: [DONE]\n\n"))
+            .await;
+    });
+
+    builder
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+        .header(hyper::header::CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap()
+}
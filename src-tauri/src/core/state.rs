@@ -1,12 +1,16 @@
 use std::{collections::HashMap, sync::Arc};
 
-use crate::core::downloads::models::DownloadManagerState;
+use crate::core::{
+    chat::{providers::ProviderConfig, retry::ChatTarget, streams::SharedChatStreams, ChatService},
+    downloads::models::DownloadManagerState,
+    server::proxy::BackendSession,
+};
 use rmcp::{
     model::{CallToolRequestParam, CallToolResult, InitializeRequestParam, Tool},
-    service::RunningService,
+    service::{Peer, RunningService},
     RoleClient, ServiceError,
 };
-use tokio::sync::{Mutex, oneshot};
+use tokio::sync::{oneshot, Mutex};
 use tokio::task::JoinHandle;
 
 /// Server handle type for managing the proxy server lifecycle
@@ -18,6 +22,16 @@ pub enum RunningServiceEnum {
 }
 pub type SharedMcpServers = Arc<Mutex<HashMap<String, RunningServiceEnum>>>;
 
+/// Senders cancelling running tool calls, keyed by cancellation token
+pub type SharedToolCallCancellations = Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>;
+
+/// Running model sessions keyed by process id
+pub type SharedModelSessions = Arc<Mutex<HashMap<i32, BackendSession>>>;
+
+/// Provider configs keyed by the provider name used in chat requests. In memory only,
+/// the frontend registers them on every start (see `set_provider_config`).
+pub type SharedProviderConfigs = Arc<Mutex<HashMap<String, ProviderConfig>>>;
+
 #[derive(Default)]
 pub struct AppState {
     pub app_token: Option<String>,
@@ -27,10 +41,24 @@ pub struct AppState {
     pub mcp_active_servers: Arc<Mutex<HashMap<String, serde_json::Value>>>,
     pub mcp_successfully_connected: Arc<Mutex<HashMap<String, bool>>>,
     pub server_handle: Arc<Mutex<Option<ServerHandle>>>,
-    pub tool_call_cancellations: Arc<Mutex<HashMap<String, oneshot::Sender<()>>>>,
+    pub tool_call_cancellations: SharedToolCallCancellations,
+    pub model_sessions: SharedModelSessions,
+    pub chat_streams: SharedChatStreams,
+    pub provider_configs: SharedProviderConfigs,
+    pub chat_service: ChatService,
+    /// Model writing thread titles and summaries, the thread's chat model when unset
+    pub title_model: Arc<Mutex<Option<ChatTarget>>>,
 }
 
 impl RunningServiceEnum {
+    /// Handle for sending requests to the server. It is cheap to clone, so callers can
+    /// release the servers lock before awaiting a request.
+    pub fn peer(&self) -> Peer<RoleClient> {
+        match self {
+            Self::NoInit(s) => s.peer().clone(),
+            Self::WithInit(s) => s.peer().clone(),
+        }
+    }
     pub async fn list_all_tools(&self) -> Result<Vec<Tool>, ServiceError> {
         match self {
             Self::NoInit(s) => s.list_all_tools().await,
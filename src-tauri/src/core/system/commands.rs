@@ -7,6 +7,7 @@ use crate::core::app::commands::{
 };
 use crate::core::app::models::AppConfiguration;
 use crate::core::mcp::helpers::clean_up_mcp_servers;
+use crate::core::runtime::helpers::clean_up_model_sessions;
 use crate::core::state::AppState;
 
 #[tauri::command]
@@ -22,6 +23,7 @@ pub fn factory_reset(app_handle: tauri::AppHandle, state: State<'_, AppState>) {
     log::info!("Factory reset, removing data folder: {:?}", data_folder);
 
     tauri::async_runtime::block_on(async {
+        clean_up_model_sessions(state.model_sessions.clone()).await;
         clean_up_mcp_servers(state.clone()).await;
 
         if data_folder.exists() {
@@ -1,16 +1,15 @@
-use std::fs::{self, File};
-use std::io::Write;
+use std::fs;
 use tauri::Runtime;
 use uuid::Uuid;
 
 use super::helpers::{
-    get_lock_for_thread, read_messages_from_file, update_thread_metadata, write_messages_to_file,
+    append_message, get_lock_for_thread, read_messages_from_file, update_thread_metadata,
+    write_messages_to_file,
 };
 use super::{
     constants::THREADS_FILE,
     utils::{
-        ensure_data_dirs, ensure_thread_dir_exists, get_data_dir, get_messages_path,
-        get_thread_dir, get_thread_metadata_path,
+        ensure_data_dirs, get_data_dir, get_messages_path, get_thread_dir, get_thread_metadata_path,
     },
 };
 
@@ -127,28 +126,12 @@ pub async fn create_message<R: Runtime>(
             .ok_or("Missing thread_id")?;
         id.to_string()
     };
-    ensure_thread_dir_exists(app_handle.clone(), &thread_id)?;
-    let path = get_messages_path(app_handle.clone(), &thread_id);
-
     if message.get("id").is_none() {
         let uuid = Uuid::new_v4().to_string();
         message["id"] = serde_json::Value::String(uuid);
     }
 
-    // Acquire per-thread lock before writing
-    {
-        let lock = get_lock_for_thread(&thread_id).await;
-        let _guard = lock.lock().await;
-
-        let mut file: File = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
-            .map_err(|e| e.to_string())?;
-
-        let data = serde_json::to_string(&message).map_err(|e| e.to_string())?;
-        writeln!(file, "{}", data).map_err(|e| e.to_string())?;
-    }
+    append_message(app_handle, &thread_id, &message).await?;
 
     Ok(message)
 }
@@ -8,7 +8,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use super::utils::{get_messages_path, get_thread_metadata_path};
+use super::utils::{ensure_thread_dir_exists, get_messages_path, get_thread_metadata_path};
 
 // Global per-thread locks for message file writes
 pub static MESSAGE_LOCKS: Lazy<Mutex<HashMap<String, Arc<Mutex<()>>>>> =
@@ -85,3 +85,64 @@ pub fn update_thread_metadata<R: Runtime>(
     fs::write(path, data).map_err(|e| e.to_string())?;
     Ok(())
 }
+
+/// Appends a message to a thread's messages.jsonl file, creating the thread directory
+/// if needed. Takes the per-thread lock, so it must not be called while holding it.
+pub async fn append_message<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    thread_id: &str,
+    message: &serde_json::Value,
+) -> Result<(), String> {
+    ensure_thread_dir_exists(app_handle.clone(), thread_id)?;
+    let path = get_messages_path(app_handle, thread_id);
+
+    let lock = get_lock_for_thread(thread_id).await;
+    let _guard = lock.lock().await;
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    let data = serde_json::to_string(message).map_err(|e| e.to_string())?;
+    writeln!(file, "{}", data).map_err(|e| e.to_string())
+}
+
+/// Applies `update` to a message and rewrites the thread's messages.jsonl file.
+/// Takes the per-thread lock, so it must not be called while holding it.
+pub async fn update_message<R: Runtime>(
+    app_handle: tauri::AppHandle<R>,
+    thread_id: &str,
+    message_id: &str,
+    update: impl FnOnce(&mut serde_json::Map<String, serde_json::Value>),
+) -> Result<(), String> {
+    let lock = get_lock_for_thread(thread_id).await;
+    let _guard = lock.lock().await;
+
+    let mut messages = read_messages_from_file(app_handle.clone(), thread_id)?;
+    let message = messages
+        .iter_mut()
+        .find(|m| m.get("id").and_then(|v| v.as_str()) == Some(message_id))
+        .and_then(|m| m.as_object_mut())
+        .ok_or_else(|| format!("Message {} not found in thread {}", message_id, thread_id))?;
+    update(message);
+
+    let path = get_messages_path(app_handle, thread_id);
+    write_messages_to_file(&messages, &path)
+}
+
+/// Merges `metadata` into the `metadata` object of a message, replacing a non-object value
+pub fn merge_metadata_object(
+    message: &mut serde_json::Map<String, serde_json::Value>,
+    metadata: serde_json::Map<String, serde_json::Value>,
+) {
+    let entry = message
+        .entry("metadata")
+        .or_insert_with(|| serde_json::json!({}));
+    if !entry.is_object() {
+        *entry = serde_json::json!({});
+    }
+    if let Some(existing) = entry.as_object_mut() {
+        existing.extend(metadata);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::core::app::commands::get_jan_data_folder_path;
 
 use super::commands::*;
+use super::helpers::{merge_metadata_object, update_message};
 use serde_json::json;
 use std::fs;
 use std::path::PathBuf;
@@ -131,3 +132,53 @@ async fn test_create_and_get_thread_assistant() {
     // Clean up
     let _ = fs::remove_dir_all(data_dir);
 }
+
+#[tokio::test]
+async fn test_update_message_merges_metadata() {
+    let (app, data_dir) = mock_app_with_temp_data_dir();
+    let thread = json!({
+        "object": "thread",
+        "title": "Metadata Thread",
+        "assistants": [],
+        "created": 123,
+        "updated": 123,
+        "metadata": null
+    });
+    let created = create_thread(app.handle().clone(), thread).await.unwrap();
+    let thread_id = created["id"].as_str().unwrap().to_string();
+
+    let message = json!({
+        "object": "message",
+        "thread_id": thread_id,
+        "role": "assistant",
+        "content": [],
+        "status": "ready",
+        "created_at": 123,
+        "completed_at": 123,
+        "metadata": { "model": "gpt-4o" }
+    });
+    let created_msg = create_message(app.handle().clone(), message).await.unwrap();
+    let message_id = created_msg["id"].as_str().unwrap().to_string();
+
+    update_message(app.handle().clone(), &thread_id, &message_id, |message| {
+        message.insert("status".to_string(), json!("stopped"));
+        let metadata = json!({ "finish_reason": "stop", "duration_ms": 420 });
+        merge_metadata_object(message, metadata.as_object().unwrap().clone());
+    })
+    .await
+    .unwrap();
+
+    let messages = list_messages(app.handle().clone(), thread_id.clone())
+        .await
+        .unwrap();
+    assert_eq!(messages[0]["status"], "stopped");
+    assert_eq!(messages[0]["metadata"]["model"], "gpt-4o");
+    assert_eq!(messages[0]["metadata"]["finish_reason"], "stop");
+    assert_eq!(messages[0]["metadata"]["duration_ms"], 420);
+
+    let missing = update_message(app.handle().clone(), &thread_id, "missing", |_| {}).await;
+    assert!(missing.is_err());
+
+    // Clean up
+    let _ = fs::remove_dir_all(data_dir);
+}
@@ -3,6 +3,7 @@ use core::{
     app::commands::get_jan_data_folder_path,
     downloads::models::DownloadManagerState,
     mcp::helpers::clean_up_mcp_servers,
+    runtime::helpers::clean_up_model_sessions,
     setup::{self, setup_mcp},
     state::AppState,
 };
@@ -69,6 +70,10 @@ pub fn run() {
             core::server::commands::start_server,
             core::server::commands::stop_server,
             core::server::commands::get_server_status,
+            // Model runtime commands
+            core::runtime::commands::load_model,
+            core::runtime::commands::unload_model,
+            core::runtime::commands::list_sessions,
             // MCP commands
             core::mcp::commands::get_tools,
             core::mcp::commands::call_tool,
@@ -92,13 +97,30 @@ pub fn run() {
             core::threads::commands::get_thread_assistant,
             core::threads::commands::create_thread_assistant,
             core::threads::commands::modify_thread_assistant,
+            // Prompt templates
+            core::prompts::commands::list_prompt_templates,
+            core::prompts::commands::get_prompt_template,
+            core::prompts::commands::create_prompt_template,
+            core::prompts::commands::update_prompt_template,
+            core::prompts::commands::delete_prompt_template,
+            core::prompts::commands::render_prompt,
             // Download
             core::downloads::commands::download_files,
             core::downloads::commands::cancel_download_task,
             // Chat
             core::chat::commands::stream_chat,
             core::chat::commands::chat,
+            core::chat::commands::compare_chat,
+            core::chat::commands::embed,
             core::chat::commands::cancel_chat_stream,
+            core::chat::commands::attach_chat_stream,
+            core::chat::commands::list_active_streams,
+            core::chat::commands::set_provider_config,
+            core::chat::commands::remove_provider_config,
+            core::chat::commands::list_provider_configs,
+            core::chat::commands::set_title_model,
+            core::chat::commands::get_title_model,
+            core::chat::commands::list_provider_models,
         ])
         .manage(AppState {
             app_token: Some(generate_app_token()),
@@ -109,6 +131,11 @@ pub fn run() {
             mcp_successfully_connected: Arc::new(Mutex::new(HashMap::new())),
             server_handle: Arc::new(Mutex::new(None)),
             tool_call_cancellations: Arc::new(Mutex::new(HashMap::new())),
+            model_sessions: Arc::new(Mutex::new(HashMap::new())),
+            chat_streams: Arc::new(Mutex::new(HashMap::new())),
+            provider_configs: Arc::new(Mutex::new(HashMap::new())),
+            chat_service: core::chat::ChatService::new(),
+            title_model: Arc::new(Mutex::new(None)),
         })
         .setup(|app| {
             app.handle().plugin(
@@ -158,6 +185,7 @@ pub fn run() {
 
                     // Quick cleanup with shorter timeout
                     let state = app_handle.state::<AppState>();
+                    clean_up_model_sessions(state.model_sessions.clone()).await;
                     let _ = clean_up_mcp_servers(state).await;
                 });
             });